@@ -0,0 +1,142 @@
+// 所有接口地址均为相对路径，页面位于 <prefix>/ui/ 下，接口位于 <prefix>/ 下
+const API_BASE = "../";
+const TOKEN_KEY = "remote_http_agent_token";
+const MAX_RESULT_CHARS = 20000;
+
+function $(id) {
+  return document.getElementById(id);
+}
+
+function getToken() {
+  return sessionStorage.getItem(TOKEN_KEY) || "";
+}
+
+function authHeaders() {
+  return { Authorization: "Bearer " + getToken() };
+}
+
+function setText(id, text) {
+  $(id).textContent = text;
+}
+
+function formatTime(ms) {
+  return new Date(ms).toLocaleString();
+}
+
+function renderStats(records) {
+  const total = records.length;
+  const errors = records.filter((r) => r.error || (r.status && r.status >= 400)).length;
+  const duration = records.reduce((sum, r) => sum + r.duration_ms, 0);
+  const bytes = records.reduce((sum, r) => sum + r.bytes, 0);
+
+  setText("stat-total", String(total));
+  setText("stat-errors", String(errors));
+  setText("stat-avg", total ? Math.round(duration / total) + " ms" : "-");
+  setText("stat-bytes", String(bytes));
+}
+
+function renderHistory(records) {
+  const tbody = $("history");
+  tbody.replaceChildren();
+
+  for (const r of records) {
+    const tr = document.createElement("tr");
+    const cells = [
+      formatTime(r.timestamp_ms),
+      r.method,
+      r.url,
+      r.status === null ? "-" : String(r.status),
+      String(r.duration_ms),
+      String(r.bytes),
+      r.error || "",
+    ];
+    for (const value of cells) {
+      const td = document.createElement("td");
+      td.textContent = value;
+      tr.appendChild(td);
+    }
+    if (r.error) {
+      tr.className = "error";
+    }
+    tbody.appendChild(tr);
+  }
+}
+
+async function loadHistory() {
+  setText("history-msg", "");
+  try {
+    const resp = await fetch(API_BASE + "admin/requests?limit=200", { headers: authHeaders() });
+    const data = await resp.json();
+    if (!resp.ok) {
+      setText("history-msg", data.error || data.msg || resp.statusText);
+      return;
+    }
+    renderStats(data.requests);
+    renderHistory(data.requests);
+  } catch (e) {
+    setText("history-msg", String(e));
+  }
+}
+
+async function clearHistory() {
+  await fetch(API_BASE + "admin/requests", { method: "DELETE", headers: authHeaders() });
+  await loadHistory();
+}
+
+function parseHeaderLines(text) {
+  const headers = {};
+  for (const line of text.split("\n")) {
+    const index = line.indexOf(":");
+    if (index <= 0) {
+      continue;
+    }
+    const name = line.slice(0, index).trim();
+    const value = line.slice(index + 1).trim();
+    // 以 tun- 前缀发送，确保自定义头部被转发到目标服务器
+    headers["tun-" + name] = value;
+  }
+  return headers;
+}
+
+async function sendTestRequest(event) {
+  event.preventDefault();
+
+  const method = $("test-method").value;
+  const headers = Object.assign(parseHeaderLines($("test-headers").value), authHeaders());
+  const init = { method, headers };
+  const body = $("test-body").value;
+  if (body && method !== "GET" && method !== "HEAD") {
+    init.body = body;
+  }
+
+  const url = API_BASE + "proxy?url=" + encodeURIComponent($("test-url").value);
+  setText("test-result", "请求中…");
+  try {
+    const resp = await fetch(url, init);
+    const lines = [resp.status + " " + resp.statusText, ""];
+    resp.headers.forEach((value, name) => lines.push(name + ": " + value));
+    let text = await resp.text();
+    if (text.length > MAX_RESULT_CHARS) {
+      text = text.slice(0, MAX_RESULT_CHARS) + "\n…（已截断）";
+    }
+    lines.push("", text);
+    setText("test-result", lines.join("\n"));
+  } catch (e) {
+    setText("test-result", String(e));
+  }
+  await loadHistory();
+}
+
+$("token").value = getToken();
+$("token-form").addEventListener("submit", (event) => {
+  event.preventDefault();
+  sessionStorage.setItem(TOKEN_KEY, $("token").value.trim());
+  loadHistory();
+});
+$("refresh").addEventListener("click", loadHistory);
+$("clear").addEventListener("click", clearHistory);
+$("test-form").addEventListener("submit", sendTestRequest);
+
+if (getToken()) {
+  loadHistory();
+}
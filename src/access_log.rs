@@ -0,0 +1,225 @@
+//! 访问日志（`log.access_log_file`）：每个请求在响应体发送完（或客户端断开）后以 Apache
+//! Combined Log Format 写入一行，由后台线程写文件，不阻塞请求处理
+
+use crate::config::LogRotateConfig;
+use crate::log_file::RotatingFile;
+use crate::stream::CompletionBody;
+use crate::AppConfig;
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing_appender::non_blocking::{NonBlocking, NonBlockingBuilder, WorkerGuard};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// 访问日志文件，写入交给后台线程，丢弃时写完缓冲
+pub struct AccessLog {
+    writer: NonBlocking,
+    _guard: WorkerGuard,
+}
+
+impl AccessLog {
+    /// 打开（或创建）访问日志文件，所在目录不存在时自动创建
+    pub fn open(path: impl Into<PathBuf>, rotate: LogRotateConfig) -> io::Result<Self> {
+        let file = RotatingFile::open(path, rotate)?;
+        // 写入跟不上时丢弃日志行，而不是阻塞请求
+        let (writer, guard) = NonBlockingBuilder::default()
+            .lossy(true)
+            .thread_name("access-log-file")
+            .finish(file);
+        Ok(Self {
+            writer,
+            _guard: guard,
+        })
+    }
+
+    fn write(&self, line: &str) {
+        let _ = self.writer.clone().write_all(line.as_bytes());
+    }
+}
+
+/// 一个请求的访问日志字段，响应体字节数在发送完后补上
+struct Entry {
+    client_ip: Option<IpAddr>,
+    time: SystemTime,
+    request_line: String,
+    status: u16,
+    referer: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl Entry {
+    fn new(client_ip: Option<IpAddr>, request: &Request) -> Self {
+        let header = |name: header::HeaderName| header_value(request.headers(), name);
+        Self {
+            client_ip,
+            time: SystemTime::now(),
+            request_line: format!(
+                "{} {} {:?}",
+                request.method(),
+                request.uri(),
+                request.version()
+            ),
+            status: 0,
+            referer: header(header::REFERER),
+            user_agent: header(header::USER_AGENT),
+        }
+    }
+
+    /// `%h %l %u %t "%r" %>s %b "%{Referer}i" "%{User-agent}i"`，没有响应体时字节数为 `-`
+    fn format(&self, bytes: u64) -> String {
+        let quoted =
+            |value: &Option<String>| value.as_deref().map_or_else(|| "-".to_string(), escape);
+        format!(
+            "{} - - [{}] \"{}\" {} {} \"{}\" \"{}\"\n",
+            self.client_ip
+                .map_or_else(|| "-".to_string(), |ip| ip.to_canonical().to_string()),
+            clf_time(self.time),
+            escape(&self.request_line),
+            self.status,
+            if bytes == 0 {
+                "-".to_string()
+            } else {
+                bytes.to_string()
+            },
+            quoted(&self.referer),
+            quoted(&self.user_agent),
+        )
+    }
+}
+
+fn header_value(headers: &HeaderMap, name: header::HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+}
+
+/// 转义引号、反斜杠与不可打印字符，保证一个请求只占一行且引号内的字段可以解析
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                let mut buf = [0; 4];
+                for b in c.encode_utf8(&mut buf).bytes() {
+                    escaped.push_str(&format!("\\x{:02x}", b));
+                }
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// `10/Oct/2000:13:55:36 +0000` 形式的 UTC 时间
+fn clf_time(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_days(secs / 86400);
+    let secs = secs % 86400;
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// 1970-01-01 起的天数转为公历年、月（1-12）、日
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+/// 配置了访问日志时在最外层记录每个请求，包括被客户端 IP 规则或认证拒绝的请求；响应体发送完
+/// 或客户端断开时写入
+pub(crate) async fn access_log_middleware(
+    State(config): State<Arc<AppConfig>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(log) = config.state.access_log.clone() else {
+        return next.run(request).await;
+    };
+    let mut entry = Entry::new(config.client_ip(&request), &request);
+    let response = next.run(request).await;
+    entry.status = response.status().as_u16();
+    response.map(|inner| {
+        Body::new(CompletionBody::new(inner, move |bytes| {
+            log.write(&entry.format(bytes))
+        }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_clf_time() {
+        assert_eq!(clf_time(UNIX_EPOCH), "01/Jan/1970:00:00:00 +0000");
+        assert_eq!(
+            clf_time(UNIX_EPOCH + Duration::from_secs(971_186_136)),
+            "10/Oct/2000:13:55:36 +0000"
+        );
+        // 闰日与年末
+        assert_eq!(
+            clf_time(UNIX_EPOCH + Duration::from_secs(1_709_164_800)),
+            "29/Feb/2024:00:00:00 +0000"
+        );
+        assert_eq!(
+            clf_time(UNIX_EPOCH + Duration::from_secs(1_735_689_599)),
+            "31/Dec/2024:23:59:59 +0000"
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        let mut entry = Entry {
+            client_ip: Some("::ffff:203.0.113.7".parse().unwrap()),
+            time: UNIX_EPOCH + Duration::from_secs(971_186_136),
+            request_line: "GET /proxy?url=https%3A%2F%2Fexample.com HTTP/1.1".to_string(),
+            status: 200,
+            referer: Some("https://example.com/".to_string()),
+            user_agent: Some("curl/8.0 \"quoted\"\n".to_string()),
+        };
+        assert_eq!(
+            entry.format(2326),
+            "203.0.113.7 - - [10/Oct/2000:13:55:36 +0000] \"GET /proxy?url=https%3A%2F%2Fexample.com HTTP/1.1\" 200 2326 \"https://example.com/\" \"curl/8.0 \\\"quoted\\\"\\x0a\"\n"
+        );
+
+        entry.client_ip = None;
+        entry.status = 403;
+        entry.referer = None;
+        entry.user_agent = None;
+        assert_eq!(
+            entry.format(0),
+            "- - - [10/Oct/2000:13:55:36 +0000] \"GET /proxy?url=https%3A%2F%2Fexample.com HTTP/1.1\" 403 - \"-\" \"-\"\n"
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+/// 别名形式的目标地址前缀：`alias:<名称>/<路径>?<查询>`
+pub const ALIAS_PREFIX: &str = "alias:";
+
+/// 地址是否为别名形式
+pub fn is_alias(url: &str) -> bool {
+    url.trim_start().starts_with(ALIAS_PREFIX)
+}
+
+/// 校验别名名称：非空，且不含 `/`、`?`、`#`、`:` 与空白
+pub fn is_valid_alias_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name
+            .chars()
+            .any(|c| matches!(c, '/' | '?' | '#' | ':') || c.is_whitespace())
+}
+
+/// 已展开的别名，用于把同一基础地址下的重定向改写回别名形式
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AliasTarget {
+    name: String,
+    base: String,
+}
+
+impl AliasTarget {
+    /// 地址位于别名的基础地址之下时返回对应的别名形式
+    pub fn contract(&self, url: &str) -> Option<String> {
+        let rest = url.strip_prefix(&self.base)?;
+        if rest.is_empty() || rest.starts_with(['/', '?', '#']) {
+            Some(format!("{}{}{}", ALIAS_PREFIX, self.name, rest))
+        } else {
+            None
+        }
+    }
+}
+
+/// 配置的上游别名，名称到基础地址
+#[derive(Debug, Clone, Default)]
+pub struct Aliases {
+    entries: HashMap<String, String>,
+}
+
+impl Aliases {
+    pub fn new(aliases: &HashMap<String, String>) -> Self {
+        let entries = aliases
+            .iter()
+            .map(|(name, base)| (name.clone(), base.trim().trim_end_matches('/').to_string()))
+            .collect();
+        Self { entries }
+    }
+
+    /// 展开 `alias:<名称>` 开头的地址，返回实际地址与所用别名；不是别名形式时返回 `Ok(None)`
+    ///
+    /// 错误信息只包含客户端传入的别名名称，不列出已配置的别名与地址
+    pub fn expand(&self, url: &str) -> Result<Option<(String, AliasTarget)>, String> {
+        let Some(rest) = url.trim().strip_prefix(ALIAS_PREFIX) else {
+            return Ok(None);
+        };
+        let split = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+        let (name, path) = rest.split_at(split);
+
+        let base = self
+            .entries
+            .get(name)
+            .ok_or_else(|| format!("未知的别名 {:?}", name))?;
+        let target = AliasTarget {
+            name: name.to_string(),
+            base: base.clone(),
+        };
+        Ok(Some((format!("{}{}", base, path), target)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aliases() -> Aliases {
+        Aliases::new(&HashMap::from([
+            ("gh".to_string(), "https://api.github.com".to_string()),
+            ("v1".to_string(), "https://example.com/api/v1/".to_string()),
+        ]))
+    }
+
+    #[test]
+    fn test_expand_alias() {
+        let aliases = aliases();
+        let (url, target) = aliases
+            .expand("alias:gh/repos/foo/bar?x=1")
+            .unwrap()
+            .unwrap();
+        assert_eq!(url, "https://api.github.com/repos/foo/bar?x=1");
+        assert_eq!(target.name, "gh");
+
+        let (url, _) = aliases.expand("alias:v1/users?id=2").unwrap().unwrap();
+        assert_eq!(url, "https://example.com/api/v1/users?id=2");
+        let (url, _) = aliases.expand("alias:gh").unwrap().unwrap();
+        assert_eq!(url, "https://api.github.com");
+        let (url, _) = aliases.expand("alias:gh?q=1").unwrap().unwrap();
+        assert_eq!(url, "https://api.github.com?q=1");
+
+        assert!(aliases.expand("https://api.github.com/").unwrap().is_none());
+
+        let err = aliases.expand("alias:unknown/x").unwrap_err();
+        assert!(err.contains("unknown"), "{}", err);
+        assert!(!err.contains("github"), "{}", err);
+    }
+
+    #[test]
+    fn test_contract_alias() {
+        let (_, target) = aliases().expand("alias:v1/a").unwrap().unwrap();
+        assert_eq!(
+            target
+                .contract("https://example.com/api/v1/b?c=1")
+                .as_deref(),
+            Some("alias:v1/b?c=1")
+        );
+        assert_eq!(target.contract("https://example.com/api/v10/b"), None);
+        assert_eq!(target.contract("https://other.example.com/api/v1/b"), None);
+    }
+
+    #[test]
+    fn test_valid_alias_name() {
+        assert!(is_valid_alias_name("gh"));
+        assert!(is_valid_alias_name("my-api_2"));
+        assert!(!is_valid_alias_name(""));
+        assert!(!is_valid_alias_name("a/b"));
+        assert!(!is_valid_alias_name("a:b"));
+        assert!(!is_valid_alias_name("a b"));
+    }
+}
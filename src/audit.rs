@@ -0,0 +1,214 @@
+//! 审计日志（`audit.enabled`）：认证失败与管理操作记录在单独的 `audit` 目标下，
+//! 配置了 `audit.file` 时另外写入单独的文件，见 [`crate::telemetry::init`]
+
+use crate::config::AuditConfig;
+use crate::metrics;
+use crate::proxy::{redact_url, request_target};
+use crate::AppConfig;
+use axum::extract::Request;
+use axum::http::{header, Method};
+use std::net::IpAddr;
+
+/// 审计事件的 tracing 目标
+pub const TARGET: &str = "audit";
+
+/// 凭据最多记录的字符数
+const CREDENTIAL_PREFIX_CHARS: usize = 8;
+
+/// 凭据的前 8 个字符，足以区分来源而不泄露完整凭据
+fn credential_prefix(credential: &str) -> String {
+    credential.chars().take(CREDENTIAL_PREFIX_CHARS).collect()
+}
+
+fn display_ip(ip: Option<IpAddr>) -> String {
+    ip.map_or_else(|| "-".to_string(), |ip| ip.to_string())
+}
+
+/// 记录一次认证失败：`kind` 为 `bearer`、`jwt`、`signature` 或 `connect`，`credential` 为客户端
+/// 出示的凭据，只记录前 8 个字符；配置了 StatsD 时不论是否启用审计日志都计入 `auth_failures`
+pub(crate) fn auth_failure(
+    config: &AppConfig,
+    request: &Request,
+    kind: &str,
+    credential: &str,
+    reason: &str,
+) {
+    if let Some(statsd) = &config.state.statsd {
+        statsd.increment(metrics::AUTH_FAILURES);
+    }
+    if !config.state.config.audit.enabled {
+        return;
+    }
+    let url = if request.method() == Method::CONNECT {
+        request
+            .uri()
+            .authority()
+            .map(|authority| authority.to_string())
+    } else {
+        request_target(request.uri()).map(|url| redact_url(&url))
+    };
+    let user_agent = request
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    tracing::warn!(
+        target: TARGET,
+        event = "auth_failure",
+        kind,
+        client_ip = %display_ip(config.client_ip(request)),
+        credential = %credential_prefix(credential),
+        url = url.as_deref().unwrap_or(""),
+        user_agent,
+        "认证失败: {}",
+        reason
+    );
+}
+
+/// 记录一次管理操作（`kill`、`clear_history`、`token_rotate` 等），命令行操作没有客户端地址
+pub fn admin_action(config: &AuditConfig, action: &str, client_ip: Option<IpAddr>, detail: &str) {
+    if !config.enabled {
+        return;
+    }
+    tracing::info!(
+        target: TARGET,
+        event = "admin_action",
+        action,
+        client_ip = %display_ip(client_ip),
+        detail,
+        "管理操作: {}",
+        action
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use axum::body::Body;
+    use axum::extract::ConnectInfo;
+    use std::io::Write;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[derive(Clone, Default)]
+    struct TestWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for TestWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// 收集 `f` 中产生的审计事件（JSON 行）
+    fn capture(f: impl FnOnce()) -> Vec<serde_json::Value> {
+        let writer = TestWriter::default();
+        let make_writer = {
+            let writer = writer.clone();
+            move || writer.clone()
+        };
+        let subscriber = tracing_subscriber::registry().with(
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_writer(make_writer),
+        );
+        tracing::subscriber::with_default(subscriber, f);
+        let output = String::from_utf8(writer.0.lock().unwrap().clone()).unwrap();
+        output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .filter(|line: &serde_json::Value| line["target"] == TARGET)
+            .collect()
+    }
+
+    fn app_config(enabled: bool) -> Arc<AppConfig> {
+        let config = Config {
+            audit: AuditConfig {
+                enabled,
+                file: None,
+            },
+            trust_proxy_header: Some("x-real-ip".to_string()),
+            trusted_proxies: vec!["127.0.0.1".to_string()],
+            ..Config::default()
+        };
+        crate::app_config(&config, reqwest::Client::new()).unwrap()
+    }
+
+    #[test]
+    fn test_credential_prefix() {
+        assert_eq!(credential_prefix("abcdefghijklmnop"), "abcdefgh");
+        assert_eq!(credential_prefix("short"), "short");
+        assert_eq!(
+            credential_prefix("令牌令牌令牌令牌令牌"),
+            "令牌令牌令牌令牌"
+        );
+    }
+
+    #[test]
+    fn test_auth_failure() {
+        let mut request = Request::builder()
+            .uri("/proxy?url=https%3A%2F%2Fuser%3Apass%40example.com%2Fa%3Fapi_key%3Dk1")
+            .header("user-agent", "scanner/1.0")
+            .header("x-real-ip", "203.0.113.7")
+            .body(Body::empty())
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+
+        let config = app_config(true);
+        let events = capture(|| {
+            auth_failure(
+                &config,
+                &request,
+                "bearer",
+                "secret-token-value",
+                "bearer 认证失败",
+            )
+        });
+        assert_eq!(events.len(), 1);
+        let fields = &events[0]["fields"];
+        assert_eq!(events[0]["level"], "WARN");
+        assert_eq!(fields["event"], "auth_failure");
+        assert_eq!(fields["kind"], "bearer");
+        assert_eq!(fields["client_ip"], "203.0.113.7");
+        assert_eq!(fields["credential"], "secret-t");
+        assert_eq!(fields["url"], "https://***@example.com/a?api_key=***");
+        assert_eq!(fields["user_agent"], "scanner/1.0");
+        assert!(!events[0].to_string().contains("secret-token-value"));
+        assert!(!events[0].to_string().contains("pass"));
+        assert!(!events[0].to_string().contains("k1"));
+
+        // 没有对端地址、目标无法解析
+        let request = Request::builder()
+            .uri("/lanip")
+            .body(Body::empty())
+            .unwrap();
+        let events = capture(|| auth_failure(&config, &request, "jwt", "", "签名无效"));
+        assert_eq!(events[0]["fields"]["client_ip"], "-");
+        assert_eq!(events[0]["fields"]["url"], "");
+
+        // 未启用时不记录
+        let config = app_config(false);
+        assert!(capture(|| auth_failure(&config, &request, "bearer", "x", "失败")).is_empty());
+        assert!(capture(|| admin_action(&config.state.config.audit, "kill", None, "")).is_empty());
+    }
+
+    #[test]
+    fn test_admin_action() {
+        let config = AuditConfig {
+            enabled: true,
+            file: None,
+        };
+        let events = capture(|| admin_action(&config, "token_rotate", None, "config.json5"));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["level"], "INFO");
+        assert_eq!(events[0]["fields"]["action"], "token_rotate");
+        assert_eq!(events[0]["fields"]["detail"], "config.json5");
+    }
+}
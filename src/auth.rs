@@ -1,16 +1,154 @@
-/// 验证 Bearer Token（与 Go 版本完全一致）
-pub fn valid_bearer(authorization_header: &str, auth_key: &str) -> bool {
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+/// 最近验证通过的 token 数量上限，命中时不再计算 argon2
+const RECENT_TOKENS: usize = 32;
+
+tokio::task_local! {
+    static ANONYMOUS: ();
+}
+
+/// 以匿名身份（`public_hosts`）处理请求，期间 [`is_anonymous`] 返回 true
+pub(crate) async fn anonymous<F: Future>(future: F) -> F::Output {
+    ANONYMOUS.scope((), future).await
+}
+
+/// 当前请求是否未经认证、因目标在 `public_hosts` 中而放行
+pub(crate) fn is_anonymous() -> bool {
+    ANONYMOUS.try_with(|_| ()).is_ok()
+}
+
+/// 取出 `Bearer <token>` 中的 token，前缀不区分大小写
+pub(crate) fn bearer_token(authorization_header: &str) -> Option<&str> {
     const BEARER_PREFIX: &str = "Bearer ";
 
     if !authorization_header
         .to_lowercase()
         .starts_with(BEARER_PREFIX.to_lowercase().as_str())
     {
-        return false;
+        return None;
     }
+    Some(authorization_header[BEARER_PREFIX.len()..].trim())
+}
 
-    let token = authorization_header[BEARER_PREFIX.len()..].trim();
-    token == auth_key
+/// 验证 Bearer Token（与 Go 版本完全一致），`auth_key` 为空时（只配置了 `token_hash`）总是失败
+pub fn valid_bearer(authorization_header: &str, auth_key: &str) -> bool {
+    !auth_key.is_empty() && bearer_token(authorization_header) == Some(auth_key)
+}
+
+/// 配置的 `token_hash`（argon2id PHC 字符串），附带最近验证通过的 token 的 SHA-256
+#[derive(Debug, Clone, Default)]
+pub struct TokenHashes {
+    hashes: Arc<Vec<String>>,
+    recent: Arc<Mutex<VecDeque<[u8; 32]>>>,
+}
+
+impl TokenHashes {
+    pub fn new(hashes: &[String]) -> Self {
+        TokenHashes {
+            hashes: Arc::new(hashes.to_vec()),
+            recent: Arc::default(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// 最近验证通过时直接返回，否则逐个验证哈希
+    fn verify(&self, token: &str) -> bool {
+        if token.is_empty() || self.hashes.is_empty() {
+            return false;
+        }
+        let digest: [u8; 32] = Sha256::digest(token.as_bytes()).into();
+        {
+            let mut recent = self.recent.lock().unwrap();
+            if let Some(index) = recent.iter().position(|d| *d == digest) {
+                // 移到队首，队尾是最久未使用的
+                recent.remove(index);
+                recent.push_front(digest);
+                return true;
+            }
+        }
+        if !self
+            .hashes
+            .iter()
+            .any(|hash| verify_token_hash(token, hash))
+        {
+            return false;
+        }
+        let mut recent = self.recent.lock().unwrap();
+        if !recent.contains(&digest) {
+            recent.push_front(digest);
+            recent.truncate(RECENT_TOKENS);
+        }
+        true
+    }
+
+    /// 是否为最近验证通过的 token，不计算 argon2
+    fn recently_verified(&self, token: &str) -> bool {
+        let digest: [u8; 32] = Sha256::digest(token.as_bytes()).into();
+        self.recent.lock().unwrap().contains(&digest)
+    }
+
+    /// 异步版本的 [`valid_bearer_hashed`]：未命中最近验证的 token 时在阻塞线程池中计算 argon2，
+    /// 避免占用处理请求的线程
+    pub async fn verify_bearer(&self, authorization_header: &str) -> bool {
+        let Some(token) = bearer_token(authorization_header) else {
+            return false;
+        };
+        if self.hashes.is_empty() || token.is_empty() {
+            return false;
+        }
+        if self.recently_verified(token) {
+            return self.verify(token);
+        }
+        let hashes = self.clone();
+        let token = token.to_string();
+        tokio::task::spawn_blocking(move || hashes.verify(&token))
+            .await
+            .unwrap_or(false)
+    }
+}
+
+/// 按 `token_hash` 验证 Bearer Token，最近验证通过的 token 不再重复计算 argon2
+pub fn valid_bearer_hashed(authorization_header: &str, hashes: &TokenHashes) -> bool {
+    bearer_token(authorization_header).is_some_and(|token| hashes.verify(token))
+}
+
+/// 生成 token 的 argon2id PHC 字符串（`$argon2id$v=19$...`），参数使用 argon2 的默认值
+pub fn hash_token(token: &str) -> Result<String, String> {
+    let salt =
+        SaltString::encode_b64(uuid::Uuid::new_v4().as_bytes()).map_err(|e| e.to_string())?;
+    Argon2::default()
+        .hash_password(token.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// 检查 `token_hash` 是否为 argon2id PHC 字符串
+pub fn check_token_hash(hash: &str) -> Result<(), String> {
+    match PasswordHash::new(hash) {
+        Ok(parsed) if parsed.algorithm.as_str() == "argon2id" => Ok(()),
+        Ok(parsed) => Err(format!(
+            "unsupported algorithm {:?}, expected argon2id",
+            parsed.algorithm.as_str()
+        )),
+        Err(e) => Err(format!("not a valid PHC string ({})", e)),
+    }
+}
+
+/// token 是否与哈希匹配，哈希格式错误时视为不匹配
+pub fn verify_token_hash(token: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|parsed| {
+        Argon2::default()
+            .verify_password(token.as_bytes(), &parsed)
+            .is_ok()
+    })
 }
 
 #[cfg(test)]
@@ -30,5 +168,46 @@ mod tests {
         assert!(!valid_bearer("Basic test-token-123", token));
         assert!(!valid_bearer("Bearer wrong-token", token));
         assert!(!valid_bearer("", token));
+        assert!(!valid_bearer("Bearer ", ""));
+    }
+
+    #[tokio::test]
+    async fn test_valid_bearer_hashed() {
+        let hash = hash_token("hashed-token").unwrap();
+        assert!(hash.starts_with("$argon2id$"), "{}", hash);
+        assert!(check_token_hash(&hash).is_ok());
+        assert!(check_token_hash("plaintext").is_err());
+        // 同一 token 每次使用不同的盐
+        assert_ne!(hash, hash_token("hashed-token").unwrap());
+
+        let hashes = TokenHashes::new(&["not-a-hash".to_string(), hash]);
+        assert!(!hashes.recently_verified("hashed-token"));
+        assert!(valid_bearer_hashed("Bearer hashed-token", &hashes));
+        assert!(hashes.recently_verified("hashed-token"));
+        assert!(hashes.verify_bearer("bearer hashed-token ").await);
+        assert!(!hashes.verify_bearer("Bearer wrong-token").await);
+        assert!(!hashes.recently_verified("wrong-token"));
+        assert!(!valid_bearer_hashed("hashed-token", &hashes));
+        assert!(!valid_bearer_hashed("Bearer ", &hashes));
+        assert!(
+            !TokenHashes::default()
+                .verify_bearer("Bearer hashed-token")
+                .await
+        );
+    }
+
+    #[test]
+    fn test_recent_tokens_lru() {
+        let hashes = TokenHashes::new(&[hash_token("keep").unwrap()]);
+        assert!(hashes.verify("keep"));
+        // 模拟其他已验证的 token 填满队列，最近使用的 token 保留在队首
+        for i in 0..RECENT_TOKENS {
+            let digest: [u8; 32] = Sha256::digest(format!("other-{}", i).as_bytes()).into();
+            hashes.recent.lock().unwrap().push_back(digest);
+            assert!(hashes.verify("keep"));
+            hashes.recent.lock().unwrap().truncate(RECENT_TOKENS);
+        }
+        assert!(hashes.recently_verified("keep"));
+        assert_eq!(hashes.recent.lock().unwrap().len(), RECENT_TOKENS);
     }
 }
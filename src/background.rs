@@ -0,0 +1,36 @@
+//! 推迟到第一次使用时启动的后台任务
+//!
+//! 路由可能在 tokio 运行时之外构建（嵌入时在同步代码中调用 `build_router` 等），构建时无法
+//! `spawn`，因此先保存任务，等到在运行时中第一次使用时再启动
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+
+type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// 尚未启动的后台任务，[`DeferredTask::start`] 只会启动一次
+pub(crate) struct DeferredTask {
+    task: Mutex<Option<Task>>,
+}
+
+impl DeferredTask {
+    pub(crate) fn new(task: impl Future<Output = ()> + Send + 'static) -> Self {
+        Self {
+            task: Mutex::new(Some(Box::pin(task))),
+        }
+    }
+
+    /// 在当前 tokio 运行时中启动任务；不在运行时中时留待下次调用
+    pub(crate) fn start(&self) {
+        let mut task = self.task.lock().unwrap();
+        if task.is_none() {
+            return;
+        }
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            if let Some(task) = task.take() {
+                handle.spawn(task);
+            }
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use crate::cookies::rewrite_set_cookies;
+use crate::headers::{
+    apply_request_header_rules, apply_response_header_rules, copy_response_headers,
+};
+use crate::proxy::{
+    client_status, invalid_url_message, modify_location, parse_origin_url, send_spec, AppError,
+    AppState, ProxyRequestSpec, ProxyUrlStyle, RequestEnvelope,
+};
+use crate::AppConfig;
+use axum::{
+    extract::{rejection::JsonRejection, State},
+    http::HeaderMap,
+    Json,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures_util::{future::join_all, StreamExt};
+use serde::Serialize;
+use std::sync::Arc;
+use tracing::{debug, error, info};
+
+#[derive(Debug, Default, Serialize)]
+pub struct BatchResult {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body_base64: String,
+    error: Option<String>,
+}
+
+impl BatchResult {
+    fn failed(message: String) -> Self {
+        Self {
+            error: Some(message),
+            ..Self::default()
+        }
+    }
+}
+
+/// 执行单个批量子请求，失败时返回带 `error` 的结果而不是中断整个批次
+async fn execute_item(state: &AppState, envelope: RequestEnvelope) -> BatchResult {
+    let mut spec = match ProxyRequestSpec::try_from(envelope) {
+        Ok(spec) => spec,
+        Err(e) => return BatchResult::failed(e.into_message()),
+    };
+
+    let alias = match state.prepare_target(&mut spec.url) {
+        Ok(alias) => alias,
+        Err(e) => return BatchResult::failed(e.into_message()),
+    };
+
+    let origin_url = match parse_origin_url(&spec.url) {
+        Ok(origin) => origin,
+        Err(e) => return BatchResult::failed(invalid_url_message(&spec.url, e)),
+    };
+    apply_request_header_rules(&mut spec.headers, &state.config.add_request_headers);
+    state.apply_request_overrides(&mut spec);
+
+    let _permit = match state.batch_semaphore.acquire().await {
+        Ok(permit) => permit,
+        Err(e) => return BatchResult::failed(e.to_string()),
+    };
+
+    if state.config.log.access_log {
+        info!("批量代理请求: {} {}", spec.method, spec.url);
+    } else {
+        debug!("批量代理请求: {} {}", spec.method, spec.url);
+    }
+
+    let response = match send_spec(state, &spec).await {
+        Ok(response) => response,
+        Err(e) => {
+            error!("{}", e);
+            return BatchResult::failed(e.to_string());
+        }
+    };
+
+    let status_code = response.status;
+    let mut response_headers = HeaderMap::new();
+    copy_response_headers(
+        &response.headers,
+        &mut response_headers,
+        status_code,
+        &[],
+        &state.config.tun_rename_response_headers,
+    );
+    rewrite_set_cookies(&mut response_headers, &state.cookie_rewrite);
+    let origin_url = parse_origin_url(&response.url).unwrap_or(origin_url);
+    modify_location(
+        &mut response_headers,
+        &origin_url,
+        &state.base_path,
+        state.location_proxy_style(ProxyUrlStyle::Query),
+        alias.as_ref(),
+    );
+    apply_response_header_rules(
+        &mut response_headers,
+        &state.config.add_response_headers,
+        &state.config.remove_response_headers,
+    );
+
+    let mut result = BatchResult {
+        status: client_status(status_code).as_u16(),
+        headers: response_headers
+            .iter()
+            .map(|(name, value)| {
+                (
+                    name.to_string(),
+                    String::from_utf8_lossy(value.as_bytes()).to_string(),
+                )
+            })
+            .collect(),
+        ..BatchResult::default()
+    };
+
+    // 批量响应必须整体缓冲，超过单项大小上限时丢弃响应体并返回错误
+    let max_bytes = state.config.batch_max_response_bytes;
+    let mut body = Vec::new();
+    let mut stream = response.body;
+    while let Some(chunk) = stream.next().await {
+        match chunk {
+            Ok(chunk) => {
+                if body.len() + chunk.len() > max_bytes {
+                    result.error = Some(format!("响应体超过 {} 字节上限", max_bytes));
+                    return result;
+                }
+                body.extend_from_slice(&chunk);
+            }
+            Err(e) => {
+                result.error = Some(e.to_string());
+                return result;
+            }
+        }
+    }
+
+    result.body_base64 = STANDARD.encode(&body);
+    result
+}
+
+/// 并发执行所有子请求，结果顺序与请求顺序一致
+pub async fn execute_batch(state: &AppState, items: Vec<RequestEnvelope>) -> Vec<BatchResult> {
+    join_all(items.into_iter().map(|item| execute_item(state, item))).await
+}
+
+pub async fn batch_handler(
+    State(config): State<Arc<AppConfig>>,
+    payload: Result<Json<Vec<RequestEnvelope>>, JsonRejection>,
+) -> Result<Json<Vec<BatchResult>>, AppError> {
+    let Json(items) =
+        payload.map_err(|e| AppError::BadRequest(format!("批量请求格式错误: {}", e)))?;
+
+    let max_items = config.state.config.batch_max_items;
+    if items.len() > max_items {
+        return Err(AppError::BadRequest(format!(
+            "批量请求最多包含 {} 个请求，实际 {} 个",
+            max_items,
+            items.len()
+        )));
+    }
+
+    Ok(Json(execute_batch(&config.state, items).await))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use axum::{extract::Path, routing::get, Router};
+
+    async fn spawn_upstream() -> String {
+        let app = Router::new()
+            .route(
+                "/echo/:name",
+                get(|Path(name): Path<String>| async move { name }),
+            )
+            .route("/large", get(|| async { "x".repeat(1024) }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        format!("http://{}", addr)
+    }
+
+    fn test_state() -> AppState {
+        let config = Config {
+            batch_max_response_bytes: 100,
+            ..Config::default()
+        };
+        AppState::new(reqwest::Client::new(), &config)
+    }
+
+    fn envelopes(value: serde_json::Value) -> Vec<RequestEnvelope> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_batch_results_in_order() {
+        let upstream = spawn_upstream().await;
+        let items = envelopes(serde_json::json!([
+            {"method": "GET", "url": format!("{}/echo/first", upstream)},
+            {"url": format!("{}/echo/second", upstream)},
+        ]));
+
+        let results = execute_batch(&test_state(), items).await;
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].status, 200);
+        assert_eq!(STANDARD.decode(&results[0].body_base64).unwrap(), b"first");
+        assert_eq!(results[1].status, 200);
+        assert_eq!(STANDARD.decode(&results[1].body_base64).unwrap(), b"second");
+    }
+
+    #[tokio::test]
+    async fn test_batch_item_failure_does_not_fail_batch() {
+        let upstream = spawn_upstream().await;
+        let items = envelopes(serde_json::json!([
+            {"url": "not a url"},
+            {"url": format!("{}/echo/ok", upstream), "method": "BAD METHOD"},
+            {"url": format!("{}/echo/ok", upstream)},
+        ]));
+
+        let results = execute_batch(&test_state(), items).await;
+
+        assert!(results[0].error.as_deref().unwrap().starts_with("url:"));
+        assert!(results[1].error.as_deref().unwrap().starts_with("method:"));
+        assert_eq!(results[2].status, 200);
+        assert!(results[2].error.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_batch_response_size_cap() {
+        let upstream = spawn_upstream().await;
+        let items = envelopes(serde_json::json!([
+            {"url": format!("{}/large", upstream)},
+        ]));
+
+        let results = execute_batch(&test_state(), items).await;
+
+        assert_eq!(results[0].status, 200);
+        assert!(results[0].body_base64.is_empty());
+        assert!(results[0].error.is_some());
+    }
+}
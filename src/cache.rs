@@ -0,0 +1,380 @@
+use crate::proxy::{BoxError, ProxyRequestSpec, UpstreamResponse};
+use bytes::Bytes;
+use futures_util::Stream;
+use reqwest::header::HeaderMap;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// 单个缓存条目的响应体大小上限，超过时不缓存
+const MAX_ENTRY_BYTES: usize = 1024 * 1024;
+
+/// 参与缓存键计算的请求头，上游 `Vary` 超出此范围的响应不缓存
+const KEY_HEADERS: &[&str] = &["accept", "accept-encoding", "accept-language"];
+
+/// 缓存的上游响应
+pub struct CachedResponse {
+    pub status: u16,
+    pub headers: HeaderMap,
+    pub url: String,
+    pub version: reqwest::Version,
+    pub body: Bytes,
+}
+
+impl CachedResponse {
+    pub(crate) fn to_upstream(&self) -> UpstreamResponse {
+        let body = self.body.clone();
+        UpstreamResponse {
+            status: self.status,
+            headers: self.headers.clone(),
+            url: self.url.clone(),
+            version: self.version,
+            trailers: Default::default(),
+            local_address: None,
+            body: Box::pin(futures_util::stream::once(async move { Ok(body) })),
+        }
+    }
+}
+
+struct CacheEntry {
+    response: Arc<CachedResponse>,
+    expires_at: Instant,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CacheInner {
+    entries: HashMap<String, CacheEntry>,
+    tick: u64,
+}
+
+/// GET 响应的内存缓存，容量满时淘汰最久未使用的条目
+pub struct ResponseCache {
+    max_entries: usize,
+    default_ttl: Duration,
+    inner: Mutex<CacheInner>,
+}
+
+impl ResponseCache {
+    pub fn new(max_entries: usize, default_ttl: Duration) -> Self {
+        Self {
+            max_entries,
+            default_ttl,
+            inner: Mutex::new(CacheInner::default()),
+        }
+    }
+
+    /// 计算缓存键：规范化的目标地址加上参与缓存键的请求头，不可缓存的请求返回 None
+    pub(crate) fn key(spec: &ProxyRequestSpec) -> Option<String> {
+        if spec.method != reqwest::Method::GET
+            || spec.streaming
+            || !spec.body.is_empty()
+            || spec.streamed_body.is_some()
+        {
+            return None;
+        }
+        // 带凭据的请求可能返回因人而异的内容
+        if spec.headers.contains_key("authorization") || spec.headers.contains_key("cookie") {
+            return None;
+        }
+        // 范围请求不读写缓存，避免以完整响应回答范围请求或反过来
+        if spec.headers.contains_key("range") || spec.headers.contains_key("if-range") {
+            return None;
+        }
+        // 指定了连接地址的请求可能访问的是另一台服务器
+        if spec.resolve.is_some() {
+            return None;
+        }
+
+        let mut url = Url::parse(&spec.url).ok()?;
+        url.set_fragment(None);
+
+        let mut key = url.to_string();
+        for name in KEY_HEADERS {
+            key.push('\n');
+            key.push_str(name);
+            key.push(':');
+            for value in spec.headers.get_all(*name) {
+                key.push_str(&String::from_utf8_lossy(value.as_bytes()));
+            }
+        }
+        Some(key)
+    }
+
+    pub fn get(&self, key: &str) -> Option<Arc<CachedResponse>> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.tick += 1;
+        let tick = inner.tick;
+
+        let expired = match inner.entries.get_mut(key) {
+            Some(entry) if entry.expires_at > Instant::now() => {
+                entry.last_used = tick;
+                return Some(entry.response.clone());
+            }
+            Some(_) => true,
+            None => false,
+        };
+        if expired {
+            inner.entries.remove(key);
+        }
+        None
+    }
+
+    /// 根据上游响应头计算缓存时间，不可缓存时返回 None
+    ///
+    /// 遵循 `Cache-Control` 的 `no-store`/`no-cache`/`private`/`max-age`/`s-maxage`，
+    /// 未指定时间时使用默认 TTL
+    pub fn ttl_for(&self, status: u16, headers: &HeaderMap) -> Option<Duration> {
+        if status != 200 || headers.contains_key("set-cookie") {
+            return None;
+        }
+
+        for value in headers.get_all("vary") {
+            let vary = value.to_str().ok()?;
+            for name in vary.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                if !KEY_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(name)) {
+                    return None;
+                }
+            }
+        }
+
+        let mut max_age = None;
+        for value in headers.get_all("cache-control") {
+            for directive in value.to_str().ok()?.split(',') {
+                let directive = directive.trim().to_ascii_lowercase();
+                match directive.split_once('=') {
+                    Some(("max-age", secs)) if max_age.is_none() => {
+                        max_age = Some(secs.trim_matches('"').parse::<u64>().ok()?);
+                    }
+                    Some(("s-maxage", secs)) => {
+                        max_age = Some(secs.trim_matches('"').parse::<u64>().ok()?);
+                    }
+                    None if matches!(directive.as_str(), "no-store" | "no-cache" | "private") => {
+                        return None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        match max_age {
+            Some(0) => None,
+            Some(secs) => Some(Duration::from_secs(secs)),
+            None => Some(self.default_ttl),
+        }
+    }
+
+    pub fn insert(&self, key: String, response: CachedResponse, ttl: Duration) {
+        if self.max_entries == 0 {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.tick += 1;
+        let tick = inner.tick;
+
+        if !inner.entries.contains_key(&key) && inner.entries.len() >= self.max_entries {
+            let now = Instant::now();
+            inner.entries.retain(|_, entry| entry.expires_at > now);
+        }
+        while !inner.entries.contains_key(&key) && inner.entries.len() >= self.max_entries {
+            let oldest = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            match oldest {
+                Some(oldest) => inner.entries.remove(&oldest),
+                None => break,
+            };
+        }
+
+        inner.entries.insert(
+            key,
+            CacheEntry {
+                response: Arc::new(response),
+                expires_at: Instant::now() + ttl,
+                last_used: tick,
+            },
+        );
+    }
+}
+
+/// 边转发边缓冲响应体，完整读完后写入缓存；出错或超过大小上限则放弃缓存
+pub struct CachingStream<S> {
+    inner: S,
+    cache: Arc<ResponseCache>,
+    pending: Option<(String, CachedResponse, Duration)>,
+    body: Vec<u8>,
+}
+
+impl<S> CachingStream<S> {
+    /// `response` 为待缓存的状态码、头部与地址，响应体在读取过程中收集
+    pub fn new(
+        inner: S,
+        cache: Arc<ResponseCache>,
+        key: String,
+        response: CachedResponse,
+        ttl: Duration,
+    ) -> Self {
+        Self {
+            inner,
+            cache,
+            pending: Some((key, response, ttl)),
+            body: Vec::new(),
+        }
+    }
+}
+
+impl<S> Stream for CachingStream<S>
+where
+    S: Stream<Item = Result<Bytes, BoxError>> + Unpin,
+{
+    type Item = Result<Bytes, BoxError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.inner).poll_next(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) => {
+                if self.pending.is_some() {
+                    if self.body.len() + chunk.len() > MAX_ENTRY_BYTES {
+                        self.pending = None;
+                        self.body = Vec::new();
+                    } else {
+                        self.body.extend_from_slice(chunk);
+                    }
+                }
+            }
+            Poll::Ready(Some(Err(_))) => self.pending = None,
+            Poll::Ready(None) => {
+                if let Some((key, mut cached, ttl)) = self.pending.take() {
+                    cached.body = Bytes::from(std::mem::take(&mut self.body));
+                    self.cache.insert(key, cached, ttl);
+                }
+            }
+            Poll::Pending => {}
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn spec(url: &str) -> ProxyRequestSpec {
+        ProxyRequestSpec {
+            url: url.to_string(),
+            method: reqwest::Method::GET,
+            headers: HeaderMap::new(),
+            body: Bytes::new(),
+            timeout: None,
+            follow_redirects: false,
+            http_version: None,
+            ip_preference: None,
+            resolve: None,
+            upload_progress: None,
+            streamed_body: None,
+            http3: false,
+            streaming: false,
+        }
+    }
+
+    fn cached(body: &'static str) -> CachedResponse {
+        CachedResponse {
+            status: 200,
+            headers: HeaderMap::new(),
+            url: "https://example.com/".to_string(),
+            version: reqwest::Version::HTTP_11,
+            body: Bytes::from_static(body.as_bytes()),
+        }
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let key = ResponseCache::key(&spec("HTTPS://Example.com:443/a?b=1#frag")).unwrap();
+        assert_eq!(
+            key,
+            ResponseCache::key(&spec("https://example.com/a?b=1")).unwrap()
+        );
+
+        let mut with_accept = spec("https://example.com/a?b=1");
+        with_accept
+            .headers
+            .insert("accept", HeaderValue::from_static("image/webp"));
+        assert_ne!(ResponseCache::key(&with_accept).unwrap(), key);
+
+        let mut post = spec("https://example.com/");
+        post.method = reqwest::Method::POST;
+        assert!(ResponseCache::key(&post).is_none());
+
+        let mut with_cookie = spec("https://example.com/");
+        with_cookie
+            .headers
+            .insert("cookie", HeaderValue::from_static("a=1"));
+        assert!(ResponseCache::key(&with_cookie).is_none());
+
+        let mut with_range = spec("https://example.com/");
+        with_range
+            .headers
+            .insert("range", HeaderValue::from_static("bytes=0-99"));
+        assert!(ResponseCache::key(&with_range).is_none());
+    }
+
+    #[test]
+    fn test_cache_hit_miss_and_expiry() {
+        let cache = ResponseCache::new(10, Duration::from_secs(60));
+        assert!(cache.get("a").is_none());
+
+        cache.insert("a".to_string(), cached("fresh"), Duration::from_secs(60));
+        assert_eq!(cache.get("a").unwrap().body, "fresh");
+
+        cache.insert("b".to_string(), cached("stale"), Duration::from_millis(20));
+        std::thread::sleep(Duration::from_millis(40));
+        assert!(cache.get("b").is_none());
+    }
+
+    #[test]
+    fn test_cache_lru_eviction() {
+        let cache = ResponseCache::new(2, Duration::from_secs(60));
+        cache.insert("a".to_string(), cached("a"), Duration::from_secs(60));
+        cache.insert("b".to_string(), cached("b"), Duration::from_secs(60));
+        // 访问 a 后 b 成为最久未使用的条目
+        assert!(cache.get("a").is_some());
+        cache.insert("c".to_string(), cached("c"), Duration::from_secs(60));
+
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+    }
+
+    #[test]
+    fn test_ttl_for() {
+        let cache = ResponseCache::new(10, Duration::from_secs(60));
+        let mut headers = HeaderMap::new();
+        assert_eq!(cache.ttl_for(200, &headers), Some(Duration::from_secs(60)));
+        assert_eq!(cache.ttl_for(404, &headers), None);
+
+        headers.insert(
+            "cache-control",
+            HeaderValue::from_static("public, max-age=300"),
+        );
+        assert_eq!(cache.ttl_for(200, &headers), Some(Duration::from_secs(300)));
+
+        headers.insert("cache-control", HeaderValue::from_static("max-age=0"));
+        assert_eq!(cache.ttl_for(200, &headers), None);
+
+        headers.insert("cache-control", HeaderValue::from_static("no-store"));
+        assert_eq!(cache.ttl_for(200, &headers), None);
+
+        headers.remove("cache-control");
+        headers.insert("vary", HeaderValue::from_static("Accept-Encoding"));
+        assert!(cache.ttl_for(200, &headers).is_some());
+        headers.insert("vary", HeaderValue::from_static("User-Agent"));
+        assert_eq!(cache.ttl_for(200, &headers), None);
+    }
+}
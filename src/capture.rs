@@ -0,0 +1,203 @@
+use crate::headers::is_sensitive_header;
+use crate::proxy::ProxyRequestSpec;
+use axum::http::HeaderMap;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::Bytes;
+use futures_util::Stream;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tracing::info;
+
+/// 请求记录完整报文的控制头部，只在开启 `debug_capture` 时生效
+pub const DEBUG_HEADER: &str = "tun-debug";
+
+pub fn is_debug_requested(headers: &HeaderMap) -> bool {
+    headers
+        .get(DEBUG_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+}
+
+/// 每行一个头部，敏感头部（`authorization`、`cookie` 等）的值以 `***` 代替
+pub fn dump_headers(headers: &reqwest::header::HeaderMap) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if is_sensitive_header(name.as_str()) {
+                "***".into()
+            } else {
+                String::from_utf8_lossy(value.as_bytes())
+            };
+            format!("\n  {}: {}", name, value)
+        })
+        .collect()
+}
+
+/// 文本类型且为有效 UTF-8 时原样输出，其余以 base64 输出
+pub fn format_body(content_type: Option<&str>, body: &[u8]) -> String {
+    let is_text = content_type.is_some_and(|content_type| {
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        mime.starts_with("text/")
+            || mime.ends_with("json")
+            || mime.ends_with("xml")
+            || mime.ends_with("javascript")
+            || mime == "application/x-www-form-urlencoded"
+    });
+    match std::str::from_utf8(body) {
+        Ok(text) if is_text => text.to_string(),
+        // 截断处可能落在多字节字符中间
+        Err(e) if is_text && e.error_len().is_none() => {
+            String::from_utf8_lossy(&body[..e.valid_up_to()]).into_owned()
+        }
+        _ => format!("base64:{}", STANDARD.encode(body)),
+    }
+}
+
+fn content_type(headers: &reqwest::header::HeaderMap) -> Option<String> {
+    headers
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+fn body_summary(content_type: Option<&str>, body: &[u8], total: u64) -> String {
+    if total as usize > body.len() {
+        format!(
+            "（共 {} 字节，只记录前 {} 字节）: {}",
+            total,
+            body.len(),
+            format_body(content_type, body)
+        )
+    } else {
+        format!("（{} 字节）: {}", total, format_body(content_type, body))
+    }
+}
+
+/// 记录发往上游的请求：请求行、头部与不超过 `max_bytes` 的请求体
+pub fn log_request(spec: &ProxyRequestSpec, max_bytes: usize) {
+    let body = &spec.body[..spec.body.len().min(max_bytes)];
+    info!(
+        "调试捕获 请求: {} {}{}\n请求体{}",
+        spec.method,
+        spec.url,
+        dump_headers(&spec.headers),
+        body_summary(
+            content_type(&spec.headers).as_deref(),
+            body,
+            spec.body.len() as u64
+        )
+    );
+}
+
+/// 记录上游响应的状态码与头部，响应体由 [`CaptureStream`] 在读完后记录
+pub fn log_response_head(url: &str, status: u16, headers: &reqwest::header::HeaderMap) {
+    info!("调试捕获 响应: {} {}{}", status, url, dump_headers(headers));
+}
+
+/// 转发响应体的同时复制前 `limit` 字节，响应体结束或被丢弃时写入日志
+///
+/// 超出 `limit` 的部分只计数不复制，不会缓冲整个响应体
+pub struct CaptureStream<S> {
+    inner: S,
+    url: String,
+    content_type: Option<String>,
+    limit: usize,
+    captured: Vec<u8>,
+    total: u64,
+}
+
+impl<S> CaptureStream<S> {
+    pub fn new(inner: S, url: &str, headers: &reqwest::header::HeaderMap, limit: usize) -> Self {
+        Self {
+            inner,
+            url: url.to_string(),
+            content_type: content_type(headers),
+            limit,
+            captured: Vec::new(),
+            total: 0,
+        }
+    }
+}
+
+impl<S, E> Stream for CaptureStream<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    type Item = Result<Bytes, E>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let item = Pin::new(&mut self.inner).poll_next(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &item {
+            let room = self.limit.saturating_sub(self.captured.len());
+            let copied = &chunk[..chunk.len().min(room)];
+            self.captured.extend_from_slice(copied);
+            self.total += chunk.len() as u64;
+        }
+        item
+    }
+}
+
+impl<S> Drop for CaptureStream<S> {
+    fn drop(&mut self) {
+        info!(
+            "调试捕获 响应体: {}{}",
+            self.url,
+            body_summary(self.content_type.as_deref(), &self.captured, self.total)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+
+    #[tokio::test]
+    async fn test_capture_stream_bounded() {
+        let chunks = vec![
+            Ok::<_, std::io::Error>(Bytes::from_static(b"hello ")),
+            Ok(Bytes::from_static(b"world")),
+            Ok(Bytes::from_static(b"!")),
+        ];
+        let mut stream = CaptureStream::new(
+            futures_util::stream::iter(chunks),
+            "https://example.com/",
+            &reqwest::header::HeaderMap::new(),
+            8,
+        );
+        let mut forwarded = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            forwarded.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(forwarded, b"hello world!");
+        assert_eq!(stream.captured, b"hello wo");
+        assert_eq!(stream.total, 12);
+    }
+
+    #[test]
+    fn test_format_body_and_headers() {
+        assert_eq!(
+            format_body(Some("application/json; charset=utf-8"), b"{\"a\":1}"),
+            "{\"a\":1}"
+        );
+        assert_eq!(format_body(Some("text/plain"), b"ok"), "ok");
+        assert_eq!(format_body(Some("image/png"), b"ok"), "base64:b2s=");
+        assert_eq!(format_body(None, b"ok"), "base64:b2s=");
+        assert_eq!(format_body(Some("text/plain"), &[0xff]), "base64:/w==");
+        assert_eq!(format_body(Some("text/plain"), &"中文".as_bytes()[..4]), "中");
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("authorization", "Bearer secret".parse().unwrap());
+        headers.insert("accept", "*/*".parse().unwrap());
+        let dump = dump_headers(&headers);
+        assert!(dump.contains("authorization: ***"), "{}", dump);
+        assert!(dump.contains("accept: */*"), "{}", dump);
+        assert!(!dump.contains("secret"), "{}", dump);
+    }
+}
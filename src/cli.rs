@@ -0,0 +1,502 @@
+//! 命令行参数与不启动服务的子命令：`init`、`token rotate`、`token show`
+
+use anyhow::{bail, Context, Result};
+use remote_http_agent::auth::hash_token;
+use remote_http_agent::config::{
+    generate_token, Config, ConfigFormat, SECRET_ENV_PREFIX, SECRET_FILE_PREFIX,
+};
+use remote_http_agent::config_file::{template, ConfigFile};
+use remote_http_agent::{audit, telemetry};
+use std::fmt;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_CONFIG_PATH: &str = "config.json5";
+
+pub const USAGE: &str = "\
+用法:
+  remote_http_agent [--config <文件>] [--quiet] [--print-port <文件>]
+      启动服务，未指定 --config 时依次查找当前目录的
+      config.json5、config.toml、config.yaml、config.yml
+  remote_http_agent init [--path <文件>] [--listen <地址>] [--force] [--hashed]
+      生成带新 token 的配置文件，格式由扩展名决定（.json5、.toml、.yaml/.yml），
+      未指定 --listen 且在终端中运行时询问监听地址；--hashed 只写入 token 的哈希
+  remote_http_agent token rotate [--path <文件>]
+      为已有配置文件生成新 token，保留注释与其余字段
+  remote_http_agent token show [--path <文件>]
+      打印配置文件中的 token
+  remote_http_agent token hash [<token>]
+      打印 token 的 argon2id 哈希（用于 token_hash），未指定时从标准输入读取一行
+";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Serve(ServeArgs),
+    Init(InitArgs),
+    /// `path` 为 None 时与启动服务一样查找当前目录的配置文件
+    TokenRotate {
+        path: Option<PathBuf>,
+    },
+    TokenShow {
+        path: Option<PathBuf>,
+    },
+    /// `token` 为 None 时从标准输入读取
+    TokenHash {
+        token: Option<String>,
+    },
+    Help,
+}
+
+/// 启动服务时的参数
+#[derive(Debug, Default, PartialEq)]
+pub struct ServeArgs {
+    /// `--config <文件>`：配置文件路径，格式由扩展名决定
+    pub config: Option<PathBuf>,
+    /// `--print-port <文件>`（或 `--print-port=<文件>`）指定的文件，
+    /// 监听端绑定后把实际端口写入该文件，便于测试脚本使用 `:0` 让系统分配端口
+    pub print_port: Option<PathBuf>,
+    /// `--quiet`（或 `-q`）：不输出启动横幅，只保留警告与生效配置
+    pub quiet: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct InitArgs {
+    pub path: PathBuf,
+    pub listen: Option<String>,
+    /// 覆盖已存在的配置文件
+    pub force: bool,
+    /// 配置文件中只写入 token 的哈希（`token_hash`），明文只打印一次
+    pub hashed: bool,
+}
+
+/// 命令行用法错误，进程以退出码 2 结束
+#[derive(Debug, PartialEq)]
+pub struct UsageError(String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+fn usage_error(message: impl Into<String>) -> UsageError {
+    UsageError(message.into())
+}
+
+/// 取 `--name <值>` 或 `--name=<值>` 形式的参数值，`arg` 不是该参数时返回 `None`
+fn option_value(
+    arg: &str,
+    name: &str,
+    args: &mut impl Iterator<Item = String>,
+) -> Result<Option<String>, UsageError> {
+    if arg == name {
+        return match args.next() {
+            Some(value) => Ok(Some(value)),
+            None => Err(usage_error(format!("{} 需要指定值", name))),
+        };
+    }
+    Ok(arg
+        .strip_prefix(name)
+        .and_then(|rest| rest.strip_prefix('='))
+        .map(str::to_string))
+}
+
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, UsageError> {
+    let mut args = args.into_iter().peekable();
+    match args.peek().map(String::as_str) {
+        Some("init") => {
+            args.next();
+            parse_init(args).map(Command::Init)
+        }
+        Some("token") => {
+            args.next();
+            match args.next().as_deref() {
+                Some("rotate") => Ok(Command::TokenRotate {
+                    path: parse_path(args)?,
+                }),
+                Some("show") => Ok(Command::TokenShow {
+                    path: parse_path(args)?,
+                }),
+                Some("hash") => {
+                    let token = args.next();
+                    if let Some(arg) = args.next() {
+                        return Err(usage_error(format!("未知参数 {:?}", arg)));
+                    }
+                    Ok(Command::TokenHash { token })
+                }
+                Some(other) => Err(usage_error(format!("未知的 token 子命令 {:?}", other))),
+                None => Err(usage_error("token 需要子命令 rotate、show 或 hash")),
+            }
+        }
+        Some("help" | "--help" | "-h") => Ok(Command::Help),
+        _ => parse_serve(args).map(Command::Serve),
+    }
+}
+
+fn parse_serve(mut args: impl Iterator<Item = String>) -> Result<ServeArgs, UsageError> {
+    let mut parsed = ServeArgs::default();
+    while let Some(arg) = args.next() {
+        if let Some(path) = option_value(&arg, "--config", &mut args)? {
+            parsed.config = Some(path.into());
+        } else if let Some(path) = option_value(&arg, "--print-port", &mut args)? {
+            parsed.print_port = Some(path.into());
+        } else if arg == "--quiet" || arg == "-q" {
+            parsed.quiet = true;
+        } else {
+            return Err(usage_error(format!("未知参数 {:?}", arg)));
+        }
+    }
+    Ok(parsed)
+}
+
+fn parse_init(mut args: impl Iterator<Item = String>) -> Result<InitArgs, UsageError> {
+    let mut parsed = InitArgs {
+        path: DEFAULT_CONFIG_PATH.into(),
+        listen: None,
+        force: false,
+        hashed: false,
+    };
+    while let Some(arg) = args.next() {
+        if let Some(path) = option_value(&arg, "--path", &mut args)? {
+            parsed.path = path.into();
+        } else if let Some(listen) = option_value(&arg, "--listen", &mut args)? {
+            check_listen(&listen).map_err(|e| usage_error(format!("--listen: {}", e)))?;
+            parsed.listen = Some(listen);
+        } else if arg == "--force" {
+            parsed.force = true;
+        } else if arg == "--hashed" {
+            parsed.hashed = true;
+        } else if arg == "--tls" {
+            return Err(usage_error(
+                "--tls: 监听端暂不支持 TLS，请由前置的反向代理终止 TLS",
+            ));
+        } else {
+            return Err(usage_error(format!("未知参数 {:?}", arg)));
+        }
+    }
+    Ok(parsed)
+}
+
+fn parse_path(mut args: impl Iterator<Item = String>) -> Result<Option<PathBuf>, UsageError> {
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        match option_value(&arg, "--path", &mut args)? {
+            Some(value) => path = Some(value.into()),
+            None => return Err(usage_error(format!("未知参数 {:?}", arg))),
+        }
+    }
+    Ok(path)
+}
+
+/// 按配置校验规则检查监听地址
+fn check_listen(listen: &str) -> Result<()> {
+    Config {
+        listening: listen.to_string(),
+        ..Config::default()
+    }
+    .validate()
+}
+
+/// 执行不启动服务的子命令，`Serve` 由调用方处理
+pub fn run(command: Command) -> Result<()> {
+    match command {
+        Command::Serve(_) => Ok(()),
+        Command::Help => {
+            print!("{}", USAGE);
+            Ok(())
+        }
+        Command::Init(args) => init(args),
+        Command::TokenRotate { path } => {
+            let path = config_path(path);
+            println!("{}", rotate_token(&path)?);
+            // 只写入审计日志文件，不在标准输出上混入日志
+            if let Ok(config) = Config::load_from_file(&path) {
+                telemetry::init_audit(&config)?;
+                audit::admin_action(
+                    &config.audit,
+                    "token_rotate",
+                    None,
+                    &path.display().to_string(),
+                );
+                telemetry::shutdown();
+            }
+            Ok(())
+        }
+        Command::TokenShow { path } => {
+            let config = Config::load_from_file(config_path(path))?;
+            if config.token.is_empty() && !config.token_hash.is_empty() {
+                bail!("配置文件只保存了 token 的哈希（token_hash），无法显示 token");
+            }
+            if config.token.is_empty() {
+                bail!("配置文件没有设置 token（只使用 auth.jwt 认证）");
+            }
+            println!("{}", config.token);
+            Ok(())
+        }
+        Command::TokenHash { token } => {
+            let token = match token {
+                Some(token) => token,
+                None => {
+                    let mut line = String::new();
+                    std::io::stdin().lock().read_line(&mut line)?;
+                    line.trim().to_string()
+                }
+            };
+            if token.is_empty() {
+                bail!("token 不能为空");
+            }
+            println!("{}", hash_token(&token).map_err(anyhow::Error::msg)?);
+            Ok(())
+        }
+    }
+}
+
+/// 未指定路径时查找当前目录的配置文件
+fn config_path(path: Option<PathBuf>) -> PathBuf {
+    path.unwrap_or_else(|| Config::find_config_file(Path::new("")))
+}
+
+fn init(args: InitArgs) -> Result<()> {
+    if args.path.exists() && !args.force {
+        bail!("{} 已存在，如需覆盖请加 --force", args.path.display());
+    }
+    let listen = match args.listen {
+        Some(listen) => listen,
+        None => prompt_listen()?,
+    };
+    let token = generate_token();
+    let token_hash = match args.hashed {
+        true => Some(hash_token(&token).map_err(anyhow::Error::msg)?),
+        false => None,
+    };
+    let format = ConfigFormat::from_path(&args.path);
+    let source = template(format, &listen, &token, token_hash.as_deref());
+    ConfigFile::parse_as(source, format)?
+        .save(&args.path)
+        .with_context(|| format!("无法写入 {}", args.path.display()))?;
+    println!("已生成配置文件: {}", args.path.display());
+    println!("监听地址: {}", listen);
+    // token 只在此处打印一次，之后可用 `token show` 查看（--hashed 时无法再查看）
+    println!("token: {}", token);
+    Ok(())
+}
+
+/// 在终端中运行时询问监听地址，直接回车使用默认值；否则直接使用默认值
+fn prompt_listen() -> Result<String> {
+    let default = Config::default().listening;
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        return Ok(default);
+    }
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("监听地址 [{}]: ", default);
+        std::io::stdout().flush()?;
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => return Ok(default),
+        };
+        let listen = match line.trim() {
+            "" => return Ok(default),
+            listen => listen,
+        };
+        match check_listen(listen) {
+            Ok(()) => return Ok(listen.to_string()),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
+
+/// 为配置文件生成新 token 并写回，注释、格式与其余字段（包括本版本不认识的字段）原样保留
+fn rotate_token(path: &Path) -> Result<String> {
+    let mut file = ConfigFile::load(path)?;
+    let config = file.config()?;
+    if !config.token_hash.is_empty() {
+        bail!("配置文件使用 token_hash，请用 `token hash` 生成新哈希后替换");
+    }
+    let current = config.token;
+    if current.starts_with(SECRET_FILE_PREFIX) || current.starts_with(SECRET_ENV_PREFIX) {
+        bail!(
+            "token 引用了外部来源 {}，请直接更新该文件或环境变量",
+            current
+        );
+    }
+    let token = generate_token();
+    file.set_string("token", &token)?;
+    file.save(path)
+        .with_context(|| format!("无法写入 {}", path.display()))?;
+    Ok(token)
+}
+
+/// 先写临时文件再改名，读取方不会读到写了一半的内容；覆盖已有文件时沿用其权限
+pub fn write_atomic(path: &Path, contents: &str) -> std::io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    std::fs::write(&temp, contents)?;
+    if let Ok(metadata) = std::fs::metadata(path) {
+        std::fs::set_permissions(&temp, metadata.permissions())?;
+    }
+    std::fs::rename(&temp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Command, UsageError> {
+        parse(args.iter().map(|s| s.to_string()))
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rha-cli-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse_args(&[]).unwrap(),
+            Command::Serve(ServeArgs::default())
+        );
+        assert_eq!(
+            parse_args(&["--print-port", "/tmp/port", "-q"]).unwrap(),
+            Command::Serve(ServeArgs {
+                config: None,
+                print_port: Some("/tmp/port".into()),
+                quiet: true,
+            })
+        );
+        assert_eq!(
+            parse_args(&["--print-port=port.txt", "--config", "/etc/rha/config.toml"]).unwrap(),
+            Command::Serve(ServeArgs {
+                config: Some("/etc/rha/config.toml".into()),
+                print_port: Some("port.txt".into()),
+                quiet: false,
+            })
+        );
+        assert!(parse_args(&["--print-port"]).is_err());
+        assert!(parse_args(&["--unknown"]).is_err());
+
+        assert_eq!(
+            parse_args(&["init", "--listen=127.0.0.1:9000", "--path", "a.json5"]).unwrap(),
+            Command::Init(InitArgs {
+                path: "a.json5".into(),
+                listen: Some("127.0.0.1:9000".to_string()),
+                force: false,
+                hashed: false,
+            })
+        );
+        let error = parse_args(&["init", "--listen", "nope"]).unwrap_err();
+        assert!(error.to_string().starts_with("--listen:"), "{}", error);
+        assert!(parse_args(&["init", "--tls"]).is_err());
+
+        assert_eq!(
+            parse_args(&["token", "show"]).unwrap(),
+            Command::TokenShow { path: None }
+        );
+        assert_eq!(
+            parse_args(&["token", "rotate", "--path=b.json5"]).unwrap(),
+            Command::TokenRotate {
+                path: Some("b.json5".into())
+            }
+        );
+        assert_eq!(
+            parse_args(&["token", "hash", "secret"]).unwrap(),
+            Command::TokenHash {
+                token: Some("secret".to_string())
+            }
+        );
+        assert!(parse_args(&["token", "hash", "a", "b"]).is_err());
+        assert!(matches!(
+            parse_args(&["init", "--hashed"]).unwrap(),
+            Command::Init(InitArgs { hashed: true, .. })
+        ));
+        assert!(parse_args(&["token"]).is_err());
+        assert!(parse_args(&["token", "delete"]).is_err());
+        assert_eq!(parse_args(&["--help"]).unwrap(), Command::Help);
+    }
+
+    #[test]
+    fn test_init_and_rotate() {
+        let path = temp_path("config.json5");
+        let _ = std::fs::remove_file(&path);
+        let init_args = || InitArgs {
+            path: path.clone(),
+            listen: Some("127.0.0.1:0".to_string()),
+            force: false,
+            hashed: false,
+        };
+        init(init_args()).unwrap();
+        let config = Config::load_from_file(&path).unwrap();
+        assert_eq!(config.listening, "127.0.0.1:0");
+        assert!(config.validate().is_ok());
+        assert!(init(init_args()).is_err());
+
+        // 改写 token 时保留其余字段，包括本版本不认识的字段
+        std::fs::write(
+            &path,
+            "// comment\n{ listening: '127.0.0.1:0', token: 'old', future_field: [1, 2] }",
+        )
+        .unwrap();
+        let token = rotate_token(&path).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("// comment\n"));
+        let value: serde_json::Value = json5::from_str(&content).unwrap();
+        assert_eq!(value["token"], token.as_str());
+        assert_eq!(value["listening"], "127.0.0.1:0");
+        assert_eq!(value["future_field"], serde_json::json!([1, 2]));
+
+        // 引用外部来源的 token 不会被改写成明文
+        std::fs::write(&path, "{ token: '@env:RHA_AGENT_TOKEN' }").unwrap();
+        assert!(rotate_token(&path).is_err());
+        assert!(std::fs::read_to_string(&path).unwrap().contains("@env:"));
+
+        let _ = std::fs::remove_file(&path);
+        assert!(rotate_token(&path).is_err());
+
+        // 按扩展名生成对应格式的配置文件
+        let path = temp_path("config.toml");
+        let _ = std::fs::remove_file(&path);
+        init(InitArgs {
+            path: path.clone(),
+            listen: Some("127.0.0.1:0".to_string()),
+            force: false,
+            hashed: false,
+        })
+        .unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(
+            content.contains("\nlistening = \"127.0.0.1:0\"\n"),
+            "{}",
+            content
+        );
+        let token = rotate_token(&path).unwrap();
+        assert_eq!(Config::load_from_file(&path).unwrap().token, token);
+
+        // --hashed 只写入哈希，token 子命令不会显示或改写明文
+        init(InitArgs {
+            path: path.clone(),
+            listen: Some("127.0.0.1:0".to_string()),
+            force: true,
+            hashed: true,
+        })
+        .unwrap();
+        let config = Config::load_from_file(&path).unwrap();
+        assert!(config.token.is_empty());
+        assert_eq!(config.token_hash.len(), 1);
+        assert!(config.validate().is_ok());
+        assert!(rotate_token(&path).is_err());
+        assert!(run(Command::TokenShow {
+            path: Some(path.clone())
+        })
+        .is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_write_atomic() {
+        let path = temp_path("port");
+        write_atomic(&path, "43210\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "43210\n");
+        let _ = std::fs::remove_file(&path);
+    }
+}
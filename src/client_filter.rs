@@ -0,0 +1,262 @@
+//! 按客户端 IP 限制访问（`client_allow_cidrs`、`client_deny_cidrs`）；代理位于反向代理之后时，
+//! 只在直连对端属于 `trusted_proxies` 时采用 `trust_proxy_header` 中的地址
+
+use crate::config::Config;
+use axum::http::{HeaderMap, HeaderName};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// IPv4 或 IPv6 网段，不带前缀长度的单个地址视为 `/32` 或 `/128`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s, None),
+        };
+        let network: IpAddr = address
+            .parse()
+            .map_err(|_| format!("invalid CIDR {:?}", s))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!("invalid CIDR {:?}", s))?,
+            None => max,
+        };
+        Ok(Self { network, prefix })
+    }
+}
+
+impl Cidr {
+    /// IPv4 映射的 IPv6 地址（`::ffff:a.b.c.d`）按对应的 IPv4 地址匹配，也能匹配写成映射形式的网段
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => prefix_eq(
+                u32::from(network).into(),
+                u32::from(ip).into(),
+                32,
+                self.prefix,
+            ),
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                prefix_eq(network.into(), ip.into(), 128, self.prefix)
+            }
+            (IpAddr::V6(network), IpAddr::V4(ip)) => {
+                prefix_eq(network.into(), ip.to_ipv6_mapped().into(), 128, self.prefix)
+            }
+            (IpAddr::V4(_), IpAddr::V6(_)) => false,
+        }
+    }
+}
+
+/// `bits` 位的地址 `a`、`b` 的前 `prefix` 位是否相同
+fn prefix_eq(a: u128, b: u128, bits: u32, prefix: u8) -> bool {
+    let shift = bits - u32::from(prefix);
+    shift >= bits || (a >> shift) == (b >> shift)
+}
+
+/// 解析 CIDR 列表，错误信息为 `field[i]: ...` 形式
+pub fn parse_cidrs(field: &str, cidrs: &[String]) -> Result<Vec<Cidr>, Vec<String>> {
+    let mut parsed = Vec::new();
+    let mut errors = Vec::new();
+    for (i, cidr) in cidrs.iter().enumerate() {
+        match cidr.parse() {
+            Ok(cidr) => parsed.push(cidr),
+            Err(e) => errors.push(format!("{}[{}]: {}", field, i, e)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(parsed)
+    } else {
+        Err(errors)
+    }
+}
+
+/// 客户端 IP 访问规则
+#[derive(Debug, Clone, Default)]
+pub struct ClientFilter {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+    proxy_header: Option<HeaderName>,
+    trusted_proxies: Vec<Cidr>,
+}
+
+impl ClientFilter {
+    /// 配置校验已检查过各项，这里忽略无法解析的条目
+    pub fn new(config: &Config) -> Self {
+        let cidrs = |field: &str, cidrs: &[String]| parse_cidrs(field, cidrs).unwrap_or_default();
+        Self {
+            allow: cidrs("client_allow_cidrs", &config.client_allow_cidrs),
+            deny: cidrs("client_deny_cidrs", &config.client_deny_cidrs),
+            proxy_header: config
+                .trust_proxy_header
+                .as_deref()
+                .and_then(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok()),
+            trusted_proxies: cidrs("trusted_proxies", &config.trusted_proxies),
+        }
+    }
+
+    /// 是否配置了允许或拒绝列表
+    pub fn is_enabled(&self) -> bool {
+        !self.allow.is_empty() || !self.deny.is_empty()
+    }
+
+    /// 确定客户端地址：直连对端属于 `trusted_proxies` 且 `trust_proxy_header` 中有合法地址时采用
+    /// 该地址（有多个时取最后一个，即受信任的代理追加的地址），否则为直连对端
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let Some(name) = &self.proxy_header else {
+            return peer;
+        };
+        if !self.trusted_proxies.iter().any(|cidr| cidr.contains(peer)) {
+            return peer;
+        }
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .and_then(|ip| ip.trim().parse().ok())
+            .unwrap_or(peer)
+    }
+
+    /// 检查客户端地址：命中拒绝列表，或配置了允许列表而不在其中时返回 false
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|cidr| cidr.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_cidr() {
+        assert!(cidr("10.0.0.0/8").contains(ip("10.1.2.3")));
+        assert!(!cidr("10.0.0.0/8").contains(ip("11.0.0.1")));
+        assert!(cidr("203.0.113.7").contains(ip("203.0.113.7")));
+        assert!(!cidr("203.0.113.7").contains(ip("203.0.113.8")));
+        assert!(cidr("0.0.0.0/0").contains(ip("198.51.100.1")));
+        assert!(!cidr("0.0.0.0/0").contains(ip("2001:db8::1")));
+        assert!(cidr("2001:db8::/32").contains(ip("2001:db8:1::1")));
+        assert!(!cidr("2001:db8::/32").contains(ip("2001:db9::1")));
+        assert!(cidr("::/0").contains(ip("2001:db8::1")));
+
+        for invalid in [
+            "",
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0.0/x",
+            "example.com",
+            "10.0.0/8",
+        ] {
+            assert!(invalid.parse::<Cidr>().is_err(), "{}", invalid);
+        }
+        assert_eq!(
+            parse_cidrs("client_allow_cidrs", &["10.0.0.0/8".into(), "bad".into()]),
+            Err(vec![
+                "client_allow_cidrs[1]: invalid CIDR \"bad\"".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_v4_mapped_v6_peer() {
+        // 双栈监听时 IPv4 客户端表现为 ::ffff:a.b.c.d
+        let mapped = ip("::ffff:203.0.113.7");
+        assert!(cidr("203.0.113.0/24").contains(mapped));
+        assert!(!cidr("198.51.100.0/24").contains(mapped));
+        assert!(cidr("::ffff:203.0.113.0/120").contains(mapped));
+        assert!(cidr("::ffff:203.0.113.0/120").contains(ip("203.0.113.7")));
+        assert!(!cidr("2001:db8::/32").contains(mapped));
+
+        let filter = ClientFilter {
+            allow: vec![cidr("203.0.113.7")],
+            ..ClientFilter::default()
+        };
+        assert!(filter.allows(mapped));
+        assert!(!filter.allows(ip("::ffff:203.0.113.8")));
+    }
+
+    #[test]
+    fn test_allows() {
+        let filter = ClientFilter {
+            allow: vec![cidr("10.0.0.0/8"), cidr("2001:db8::/32")],
+            deny: vec![cidr("10.0.0.66")],
+            ..ClientFilter::default()
+        };
+        assert!(filter.is_enabled());
+        assert!(filter.allows(ip("10.0.0.1")));
+        assert!(filter.allows(ip("2001:db8::1")));
+        assert!(!filter.allows(ip("10.0.0.66")));
+        assert!(!filter.allows(ip("192.168.1.1")));
+
+        // 只配置拒绝列表时其他地址均允许
+        let filter = ClientFilter {
+            deny: vec![cidr("192.168.0.0/16")],
+            ..ClientFilter::default()
+        };
+        assert!(filter.allows(ip("10.0.0.1")));
+        assert!(!filter.allows(ip("::ffff:192.168.1.1")));
+        assert!(!ClientFilter::default().is_enabled());
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let filter = ClientFilter {
+            proxy_header: Some(HeaderName::from_static("x-real-ip")),
+            trusted_proxies: vec![cidr("127.0.0.1"), cidr("::1")],
+            ..ClientFilter::default()
+        };
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-real-ip", value.parse().unwrap());
+            headers
+        };
+
+        assert_eq!(
+            filter.client_ip(ip("127.0.0.1"), &headers("203.0.113.7")),
+            ip("203.0.113.7")
+        );
+        assert_eq!(
+            filter.client_ip(ip("::ffff:127.0.0.1"), &headers("203.0.113.7")),
+            ip("203.0.113.7")
+        );
+        assert_eq!(
+            filter.client_ip(ip("::1"), &headers("198.51.100.1, 203.0.113.7")),
+            ip("203.0.113.7")
+        );
+        // 对端不受信任、头部缺失或无法解析时使用对端地址
+        assert_eq!(
+            filter.client_ip(ip("10.0.0.1"), &headers("203.0.113.7")),
+            ip("10.0.0.1")
+        );
+        assert_eq!(
+            filter.client_ip(ip("127.0.0.1"), &HeaderMap::new()),
+            ip("127.0.0.1")
+        );
+        assert_eq!(
+            filter.client_ip(ip("127.0.0.1"), &headers("unknown")),
+            ip("127.0.0.1")
+        );
+    }
+}
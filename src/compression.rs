@@ -0,0 +1,358 @@
+use crate::headers::is_grpc_content_type;
+use crate::proxy::{BoxError, UpstreamResponse};
+use async_compression::tokio::bufread::{BrotliDecoder, BrotliEncoder, GzipDecoder, GzipEncoder};
+use axum::http::{HeaderMap, HeaderValue};
+use futures_util::TryStreamExt;
+use tokio::io::{AsyncBufRead, AsyncRead};
+use tokio_util::io::{ReaderStream, StreamReader};
+
+/// 请求代理解压上游响应体的控制头部
+pub const DECOMPRESS_HEADER: &str = "tun-decompress";
+
+/// 代理能够解压的上游编码
+const UPSTREAM_ACCEPT_ENCODING: &str = "gzip, br";
+
+/// 客户端真实的 `Accept-Encoding`，同时会以 `Accept-Encoding` 转发到上游
+const CLIENT_ACCEPT_ENCODING_HEADER: &str = "tun-accept-encoding";
+
+/// 请求头 `tun-decompress` 优先，未携带时使用配置的默认值
+pub fn is_decompress_requested(headers: &HeaderMap, default: bool) -> bool {
+    match headers.get(DECOMPRESS_HEADER).and_then(|v| v.to_str().ok()) {
+        Some(value) => value.trim().eq_ignore_ascii_case("true"),
+        None => default,
+    }
+}
+
+/// 客户端是否通过 `tun-accept-encoding` 声明了自己能够解码的编码
+pub fn has_client_accept_encoding(headers: &HeaderMap) -> bool {
+    headers.contains_key(CLIENT_ACCEPT_ENCODING_HEADER)
+}
+
+/// 向上游只请求未压缩的内容
+pub fn set_identity_accept_encoding(headers: &mut reqwest::header::HeaderMap) {
+    headers.insert(
+        "accept-encoding",
+        reqwest::header::HeaderValue::from_static("identity"),
+    );
+}
+
+/// 向上游只声明代理能够解压的编码
+pub fn set_upstream_accept_encoding(headers: &mut reqwest::header::HeaderMap) {
+    headers.insert(
+        "accept-encoding",
+        reqwest::header::HeaderValue::from_static(UPSTREAM_ACCEPT_ENCODING),
+    );
+}
+
+fn body_reader(response: &mut UpstreamResponse) -> impl AsyncBufRead + Send + Unpin {
+    let body = std::mem::replace(&mut response.body, Box::pin(futures_util::stream::empty()));
+    StreamReader::new(body.map_err(std::io::Error::other))
+}
+
+fn set_body<R: AsyncRead + Send + 'static>(response: &mut UpstreamResponse, reader: R) {
+    response.body = Box::pin(ReaderStream::new(Box::pin(reader)).map_err(BoxError::from));
+}
+
+/// 边读取边解压 gzip/br 响应体，并去掉 `Content-Encoding` 与 `Content-Length`
+///
+/// 其他编码原样转发；上游头部同步去掉 `Content-Encoding`，之后的处理（如链接改写）按未压缩内容对待
+pub(crate) fn decompress_body(response: &mut UpstreamResponse, response_headers: &mut HeaderMap) {
+    let encoding = match response
+        .headers
+        .get("content-encoding")
+        .and_then(|v| v.to_str().ok())
+    {
+        Some(encoding) => encoding.trim().to_ascii_lowercase(),
+        None => return,
+    };
+
+    match encoding.as_str() {
+        "gzip" | "x-gzip" => {
+            let reader = body_reader(response);
+            set_body(response, GzipDecoder::new(reader));
+        }
+        "br" => {
+            let reader = body_reader(response);
+            set_body(response, BrotliDecoder::new(reader));
+        }
+        _ => return,
+    }
+
+    response.headers.remove("content-encoding");
+    response.headers.remove("content-length");
+    response_headers.remove("content-encoding");
+    response_headers.remove("content-length");
+}
+
+/// 返回给客户端的压缩编码
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Coding {
+    Brotli,
+    Gzip,
+}
+
+impl Coding {
+    fn as_str(self) -> &'static str {
+        match self {
+            Coding::Brotli => "br",
+            Coding::Gzip => "gzip",
+        }
+    }
+}
+
+/// 按客户端的 `Accept-Encoding` 选择压缩编码，权重相同时优先 br；`q=0` 表示拒绝
+///
+/// 客户端携带 `tun-accept-encoding` 时以它为准，否则使用 `Accept-Encoding`
+fn negotiate_coding(headers: &HeaderMap) -> Option<Coding> {
+    let name = if headers.contains_key(CLIENT_ACCEPT_ENCODING_HEADER) {
+        CLIENT_ACCEPT_ENCODING_HEADER
+    } else {
+        "accept-encoding"
+    };
+
+    let mut best: Option<(Coding, f32)> = None;
+    for item in headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+    {
+        let mut parts = item.split(';').map(str::trim);
+        let coding = match parts
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "br" | "*" => Coding::Brotli,
+            "gzip" | "x-gzip" => Coding::Gzip,
+            _ => continue,
+        };
+        let q = parts
+            .find_map(|p| p.strip_prefix("q="))
+            .map_or(Some(1.0), |q| q.parse::<f32>().ok())
+            .unwrap_or(0.0);
+        if q <= 0.0 {
+            continue;
+        }
+        let better = match best {
+            Some((current, best_q)) => {
+                q > best_q || (q == best_q && current == Coding::Gzip && coding == Coding::Brotli)
+            }
+            None => true,
+        };
+        if better {
+            best = Some((coding, q));
+        }
+    }
+    best.map(|(coding, _)| coding)
+}
+
+/// 文本类响应才值得压缩，图片、视频等通常已经压缩过
+fn is_compressible(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers.get("content-type").and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    if mime == "text/event-stream" || is_grpc_content_type(&mime) {
+        return false;
+    }
+    mime.starts_with("text/")
+        || mime.ends_with("+json")
+        || mime.ends_with("+xml")
+        || matches!(
+            mime.as_str(),
+            "application/json" | "application/javascript" | "application/xml" | "image/svg+xml"
+        )
+}
+
+/// 客户端接受 br 或 gzip 且响应为文本类型时压缩返回
+///
+/// 已带有 `Content-Encoding` 的响应不再压缩；`Content-Length` 小于 `min_bytes` 的响应不压缩，
+/// 长度未知时照常压缩
+pub(crate) fn compress_body(
+    response: &mut UpstreamResponse,
+    inbound: &HeaderMap,
+    response_headers: &mut HeaderMap,
+    min_bytes: usize,
+) {
+    if response_headers.contains_key("content-encoding") || !is_compressible(response_headers) {
+        return;
+    }
+    let Some(coding) = negotiate_coding(inbound) else {
+        return;
+    };
+    let length = response_headers
+        .get("content-length")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if length.is_some_and(|length| length < min_bytes) {
+        return;
+    }
+
+    let reader = body_reader(response);
+    match coding {
+        Coding::Brotli => set_body(response, BrotliEncoder::new(reader)),
+        Coding::Gzip => set_body(response, GzipEncoder::new(reader)),
+    }
+
+    response_headers.remove("content-length");
+    response_headers.insert(
+        "content-encoding",
+        HeaderValue::from_static(coding.as_str()),
+    );
+    let varies = response_headers
+        .get_all("vary")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|v| v.trim().eq_ignore_ascii_case("accept-encoding"));
+    if !varies {
+        response_headers.append("vary", HeaderValue::from_static("Accept-Encoding"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use futures_util::StreamExt;
+    use tokio::io::AsyncReadExt;
+
+    fn upstream(headers: &[(&str, &str)], body: Vec<u8>) -> UpstreamResponse {
+        let mut upstream_headers = reqwest::header::HeaderMap::new();
+        for (name, value) in headers {
+            upstream_headers.insert(
+                reqwest::header::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                value.parse().unwrap(),
+            );
+        }
+        UpstreamResponse {
+            status: 200,
+            headers: upstream_headers,
+            url: "https://example.com/".to_string(),
+            version: reqwest::Version::HTTP_11,
+            trailers: Default::default(),
+            local_address: None,
+            body: Box::pin(futures_util::stream::once(async move {
+                Ok::<_, BoxError>(Bytes::from(body))
+            })),
+        }
+    }
+
+    async fn collect(response: UpstreamResponse) -> Vec<u8> {
+        let chunks: Vec<Bytes> = response.body.map(|c| c.unwrap()).collect().await;
+        chunks.concat()
+    }
+
+    async fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        GzipEncoder::new(data).read_to_end(&mut out).await.unwrap();
+        out
+    }
+
+    async fn gunzip(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        GzipDecoder::new(data).read_to_end(&mut out).await.unwrap();
+        out
+    }
+
+    #[test]
+    fn test_negotiate_coding() {
+        let negotiate = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("accept-encoding", HeaderValue::from_static(value));
+            negotiate_coding(&headers)
+        };
+        assert_eq!(negotiate("gzip, deflate, br"), Some(Coding::Brotli));
+        assert_eq!(negotiate("gzip, deflate"), Some(Coding::Gzip));
+        assert_eq!(negotiate("br;q=0.5, gzip;q=0.8"), Some(Coding::Gzip));
+        assert_eq!(negotiate("*"), Some(Coding::Brotli));
+        assert_eq!(negotiate("gzip;q=0, deflate"), None);
+        assert_eq!(negotiate("identity"), None);
+        assert_eq!(negotiate_coding(&HeaderMap::new()), None);
+
+        // 客户端真实的 Accept-Encoding 优先
+        let mut headers = HeaderMap::new();
+        headers.insert("accept-encoding", HeaderValue::from_static("identity"));
+        headers.insert(
+            CLIENT_ACCEPT_ENCODING_HEADER,
+            HeaderValue::from_static("gzip"),
+        );
+        assert_eq!(negotiate_coding(&headers), Some(Coding::Gzip));
+    }
+
+    #[tokio::test]
+    async fn test_decompress_body() {
+        let text = b"hello hello hello".to_vec();
+        let mut response = upstream(
+            &[("content-encoding", "gzip"), ("content-type", "text/plain")],
+            gzip(&text).await,
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert("content-encoding", HeaderValue::from_static("gzip"));
+        headers.insert("content-length", HeaderValue::from_static("31"));
+
+        decompress_body(&mut response, &mut headers);
+        assert!(headers.get("content-encoding").is_none());
+        assert!(headers.get("content-length").is_none());
+        assert!(response.headers.get("content-encoding").is_none());
+        assert_eq!(collect(response).await, text);
+
+        // 不支持的编码原样转发
+        let mut response = upstream(&[("content-encoding", "zstd")], b"raw".to_vec());
+        let mut headers = HeaderMap::new();
+        headers.insert("content-encoding", HeaderValue::from_static("zstd"));
+        decompress_body(&mut response, &mut headers);
+        assert_eq!(headers.get("content-encoding").unwrap(), "zstd");
+        assert_eq!(collect(response).await, b"raw");
+    }
+
+    #[tokio::test]
+    async fn test_compress_body() {
+        let text = vec![b'a'; 2048];
+        let mut inbound = HeaderMap::new();
+        inbound.insert("accept-encoding", HeaderValue::from_static("gzip"));
+        let response_headers = |content_type: &'static str, length: usize| {
+            let mut headers = HeaderMap::new();
+            headers.insert("content-type", HeaderValue::from_static(content_type));
+            headers.insert("content-length", HeaderValue::from(length));
+            headers
+        };
+
+        let mut response = upstream(&[], text.clone());
+        let mut headers = response_headers("application/json", text.len());
+        compress_body(&mut response, &inbound, &mut headers, 1024);
+        assert_eq!(headers.get("content-encoding").unwrap(), "gzip");
+        assert_eq!(headers.get("vary").unwrap(), "Accept-Encoding");
+        assert!(headers.get("content-length").is_none());
+        assert_eq!(gunzip(&collect(response).await).await, text);
+
+        // 已压缩、过小、非文本类型或客户端不接受 gzip 时不压缩
+        let mut headers = response_headers("text/html", text.len());
+        headers.insert("content-encoding", HeaderValue::from_static("br"));
+        let mut response = upstream(&[], text.clone());
+        compress_body(&mut response, &inbound, &mut headers, 1024);
+        assert_eq!(headers.get("content-encoding").unwrap(), "br");
+        assert_eq!(collect(response).await, text);
+
+        for (content_type, min_bytes, inbound) in [
+            ("text/html", 4096, inbound.clone()),
+            ("image/png", 1024, inbound.clone()),
+            ("application/grpc-web+json", 1024, inbound.clone()),
+            ("text/html", 1024, HeaderMap::new()),
+        ] {
+            let mut headers = response_headers(content_type, text.len());
+            let mut response = upstream(&[], text.clone());
+            compress_body(&mut response, &inbound, &mut headers, min_bytes);
+            assert!(headers.get("content-encoding").is_none());
+            assert_eq!(collect(response).await, text);
+        }
+    }
+}
@@ -0,0 +1,599 @@
+//! 在保留注释、格式与未知字段的前提下改写 JSON5、TOML 或 YAML 配置文件
+//!
+//! 反序列化为 [`Config`] 再序列化会丢掉注释以及当前版本不认识的字段（例如新版本才有的配置项），
+//! 因此改写时直接在原文中替换字段的值
+
+use crate::config::{Config, ConfigFormat};
+use anyhow::{anyhow, bail, Context, Result};
+use std::fmt::Write;
+use std::ops::Range;
+use std::path::Path;
+
+/// 保留原文的配置文件
+#[derive(Debug, Clone)]
+pub struct ConfigFile {
+    source: String,
+    format: ConfigFormat,
+}
+
+impl ConfigFile {
+    /// 按扩展名识别格式
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {:?}", path))?;
+        Self::parse_as(source, ConfigFormat::from_path(path))
+            .with_context(|| format!("Failed to parse config file {}", path.display()))
+    }
+
+    /// 解析 JSON5 配置
+    pub fn parse(source: String) -> Result<Self> {
+        Self::parse_as(source, ConfigFormat::Json5)
+    }
+
+    pub fn parse_as(source: String, format: ConfigFormat) -> Result<Self> {
+        let is_object = match format {
+            ConfigFormat::Json5 => Scanner::new(&source).find_top_level("").is_some(),
+            ConfigFormat::Toml => true,
+            ConfigFormat::Yaml => serde_yaml::from_str::<serde_json::Value>(&source)
+                .map_or(true, |value| value.is_object()),
+        };
+        if !is_object {
+            bail!("config file: top level is not an object");
+        }
+        let file = ConfigFile { source, format };
+        file.config()?;
+        Ok(file)
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn format(&self) -> ConfigFormat {
+        self.format
+    }
+
+    pub fn config(&self) -> Result<Config> {
+        Config::parse(&self.source, self.format).map_err(|e| anyhow!(e))
+    }
+
+    /// 设置顶层的字符串字段，字段不存在时插入到开头
+    pub fn set_string(&mut self, key: &str, value: &str) -> Result<()> {
+        // JSON 字符串同时也是合法的 TOML 基本字符串与 YAML 双引号字符串
+        let literal = serde_json::to_string(value)?;
+        let (field, insert_at) = match self.format {
+            ConfigFormat::Json5 => match Scanner::new(&self.source).find_top_level(key) {
+                Some((object_start, field)) => (field, object_start + 1),
+                None => bail!("config file: top level is not an object"),
+            },
+            ConfigFormat::Toml | ConfigFormat::Yaml => (
+                find_line_field(&self.source, self.format, key),
+                leading_comments_end(&self.source),
+            ),
+        };
+        let source = match field {
+            Some(range) => format!(
+                "{}{}{}",
+                &self.source[..range.start],
+                literal,
+                &self.source[range.end..]
+            ),
+            None => {
+                let field = match self.format {
+                    ConfigFormat::Json5 => {
+                        format!("\n  {}: {},", serde_json::to_string(key)?, literal)
+                    }
+                    ConfigFormat::Toml => format!("{} = {}\n", key, literal),
+                    ConfigFormat::Yaml => format!("{}: {}\n", key, literal),
+                };
+                format!(
+                    "{}{}{}",
+                    &self.source[..insert_at],
+                    field,
+                    &self.source[insert_at..]
+                )
+            }
+        };
+        *self = Self::parse_as(source, self.format)?;
+        Ok(())
+    }
+
+    /// 先写临时文件再改名，覆盖已有文件时沿用其权限
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let path = path.as_ref();
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        std::fs::write(&temp, &self.source)?;
+        if let Ok(metadata) = std::fs::metadata(path) {
+            std::fs::set_permissions(&temp, metadata.permissions())?;
+        }
+        std::fs::rename(&temp, path)
+    }
+}
+
+/// `init` 生成的配置文件，常用字段附带说明，其余字段使用默认值；
+/// 指定 `token_hash` 时只写入哈希，`token` 为空字符串
+pub fn template(
+    format: ConfigFormat,
+    listening: &str,
+    token: &str,
+    token_hash: Option<&str>,
+) -> String {
+    let defaults = Config::default();
+    let json = |value: &str| serde_json::to_string(value).unwrap_or_default();
+    let token_field: (&[&str], &str, String) = match token_hash {
+        Some(hash) => (
+            &[
+                "Bearer 认证 Token 的 argon2id 哈希，配置文件中不保存明文",
+                "可用 `remote_http_agent token hash <token>` 生成",
+            ],
+            "token_hash",
+            format!("[{}]", json(hash)),
+        ),
+        None => (
+            &[
+                "Bearer 认证 Token，客户端以 \"Authorization: Bearer <token>\" 访问",
+                "可用 `remote_http_agent token rotate` 重新生成",
+            ],
+            "token",
+            json(token),
+        ),
+    };
+    // 各字段的值都写成 JSON 字面量，三种格式通用
+    let mut fields: Vec<(&[&str], &str, String)> = vec![
+        (
+            &["监听地址，\"unix:/path/to.sock\" 表示监听 Unix socket，端口为 0 时由系统分配"],
+            "listening",
+            json(listening),
+        ),
+        token_field,
+        (
+            &["上游 HTTP 代理（可选），如 \"http://127.0.0.1:9000\""],
+            "http_proxy",
+            json(&defaults.http_proxy),
+        ),
+        (
+            &["跳过所有上游的 TLS 证书验证（不安全），只需跳过少数自签名证书的主机时改用 insecure_hosts"],
+            "skip_tls",
+            defaults.skip_tls.to_string(),
+        ),
+        (&[], "insecure_hosts", "[]".to_string()),
+        (
+            &["上游请求超时（秒）"],
+            "upstream_timeout_secs",
+            defaults.upstream_timeout_secs.to_string(),
+        ),
+    ];
+    if token_hash.is_some() {
+        fields.insert(2, (&[], "token", json("")));
+    }
+    let (comment, name, indent) = match format {
+        ConfigFormat::Json5 => ("//", "JSON5，支持注释与结尾逗号", "  "),
+        ConfigFormat::Toml => ("#", "TOML", ""),
+        ConfigFormat::Yaml => ("#", "YAML", ""),
+    };
+
+    let mut out = String::new();
+    let _ = writeln!(out, "{} remote_http_agent 配置文件（{}）", comment, name);
+    let _ = writeln!(
+        out,
+        "{} 完整的配置项及说明见 config.example.json5 与 README",
+        comment
+    );
+    if format == ConfigFormat::Json5 {
+        out.push_str("{\n");
+    }
+    for (index, (comments, key, value)) in fields.iter().enumerate() {
+        if index > 0 && !comments.is_empty() {
+            out.push('\n');
+        }
+        for line in comments.iter() {
+            let _ = writeln!(out, "{}{} {}", indent, comment, line);
+        }
+        let _ = match format {
+            ConfigFormat::Json5 => writeln!(out, "  \"{}\": {},", key, value),
+            ConfigFormat::Toml => writeln!(out, "{} = {}", key, value),
+            ConfigFormat::Yaml => writeln!(out, "{}: {}", key, value),
+        };
+    }
+    out.push('\n');
+    out.push_str(match format {
+        ConfigFormat::Json5 => {
+            r#"  "log": {
+    "level": "info",   // error、warn、info、debug、trace
+    "format": "text",  // text、json 或 compact
+  },
+}
+"#
+        }
+        ConfigFormat::Toml => {
+            r#"[log]
+level = "info"   # error、warn、info、debug、trace
+format = "text"  # text、json 或 compact
+"#
+        }
+        ConfigFormat::Yaml => {
+            r#"log:
+  level: "info"   # error、warn、info、debug、trace
+  format: "text"  # text、json 或 compact
+"#
+        }
+    });
+    out
+}
+
+/// TOML 与 YAML 的顶层字段各占一行：返回字段 `key` 的值在原文中的位置。
+/// TOML 只查找第一个表头（`[table]`）之前的行，YAML 只查找没有缩进的行
+fn find_line_field(source: &str, format: ConfigFormat, key: &str) -> Option<Range<usize>> {
+    let separator = match format {
+        ConfigFormat::Toml => '=',
+        _ => ':',
+    };
+    let quoted = serde_json::to_string(key).ok()?;
+    let mut offset = 0;
+    for line in source.split_inclusive('\n') {
+        let start = offset;
+        offset += line.len();
+        let text = match format {
+            ConfigFormat::Toml => {
+                let trimmed = line.trim_start();
+                if trimmed.starts_with('[') {
+                    return None;
+                }
+                trimmed
+            }
+            _ => line,
+        };
+        let Some(rest) = text
+            .strip_prefix(key)
+            .or_else(|| text.strip_prefix(quoted.as_str()))
+        else {
+            continue;
+        };
+        let Some(rest) = rest.trim_start_matches([' ', '\t']).strip_prefix(separator) else {
+            continue;
+        };
+        let value = rest.trim_start_matches([' ', '\t']);
+        let value_start = start + line.len() - text.len() + (text.len() - value.len());
+        return Some(value_start..value_start + value_len(value));
+    }
+    None
+}
+
+/// 单行值的长度：引号字符串到结束引号为止，其余到行尾注释为止
+fn value_len(value: &str) -> usize {
+    let bytes = value.as_bytes();
+    match bytes.first() {
+        Some(&quote @ (b'"' | b'\'')) => {
+            let mut pos = 1;
+            while pos < bytes.len() && bytes[pos] != b'\n' {
+                match bytes[pos] {
+                    b'\\' if quote == b'"' => pos += 2,
+                    c if c == quote => return pos + 1,
+                    _ => pos += 1,
+                }
+            }
+            pos.min(bytes.len())
+        }
+        _ => {
+            let line = value.split(['\r', '\n']).next().unwrap_or_default();
+            let end = line.find(" #").unwrap_or(line.len());
+            line[..end].trim_end().len()
+        }
+    }
+}
+
+/// 开头的注释、空行与 YAML 文档起始标记之后的位置，新字段插入在此处
+fn leading_comments_end(source: &str) -> usize {
+    let mut offset = 0;
+    for line in source.split_inclusive('\n') {
+        let trimmed = line.trim();
+        // 没有换行结尾的最后一行之后无法插入新行
+        if !line.ends_with('\n')
+            || !(trimmed.is_empty() || trimmed.starts_with('#') || trimmed == "---")
+        {
+            break;
+        }
+        offset += line.len();
+    }
+    offset
+}
+
+/// 只识别定位字段所需的 JSON5 结构：字符串、注释与嵌套的对象、数组
+struct Scanner<'a> {
+    source: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(source: &'a str) -> Self {
+        Scanner {
+            source: source.as_bytes(),
+            pos: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.source.get(self.pos).copied()
+    }
+
+    fn starts_with(&self, prefix: &[u8]) -> bool {
+        self.source[self.pos..].starts_with(prefix)
+    }
+
+    /// 跳过空白与注释
+    fn skip_trivia(&mut self) {
+        loop {
+            if self.starts_with(b"//") {
+                while self.peek().is_some_and(|c| c != b'\n') {
+                    self.pos += 1;
+                }
+            } else if self.starts_with(b"/*") {
+                self.pos += 2;
+                while self.pos < self.source.len() && !self.starts_with(b"*/") {
+                    self.pos += 1;
+                }
+                self.pos = (self.pos + 2).min(self.source.len());
+            } else if self.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+                self.pos += 1;
+            } else {
+                return;
+            }
+        }
+    }
+
+    /// 跳过以单引号或双引号开始的字符串，返回引号内的原文
+    fn string(&mut self) -> Option<&'a [u8]> {
+        let quote = self.peek()?;
+        let start = self.pos + 1;
+        self.pos = start;
+        loop {
+            match self.peek()? {
+                b'\\' => self.pos += 2,
+                c if c == quote => {
+                    self.pos += 1;
+                    return Some(&self.source[start..self.pos - 1]);
+                }
+                _ => self.pos += 1,
+            }
+        }
+    }
+
+    /// 对象的键：字符串或标识符
+    fn key(&mut self) -> Option<&'a [u8]> {
+        match self.peek()? {
+            b'"' | b'\'' => self.string(),
+            _ => {
+                let start = self.pos;
+                while self
+                    .peek()
+                    .is_some_and(|c| c.is_ascii_alphanumeric() || c == b'_' || c == b'$')
+                {
+                    self.pos += 1;
+                }
+                (self.pos > start).then(|| &self.source[start..self.pos])
+            }
+        }
+    }
+
+    fn value(&mut self) -> Option<()> {
+        match self.peek()? {
+            b'"' | b'\'' => self.string().map(|_| ()),
+            b'{' => self.object(|_, _| {}),
+            b'[' => {
+                self.pos += 1;
+                loop {
+                    self.skip_trivia();
+                    if self.peek()? == b']' {
+                        self.pos += 1;
+                        return Some(());
+                    }
+                    self.value()?;
+                    self.separator(b']')?;
+                }
+            }
+            _ => {
+                // 数字、true/false/null 等
+                let start = self.pos;
+                while self.peek().is_some_and(|c| {
+                    !c.is_ascii_whitespace() && !matches!(c, b',' | b'}' | b']' | b'/')
+                }) {
+                    self.pos += 1;
+                }
+                (self.pos > start).then_some(())
+            }
+        }
+    }
+
+    /// 跳过元素之后的逗号，遇到结束符时不消费
+    fn separator(&mut self, close: u8) -> Option<()> {
+        self.skip_trivia();
+        match self.peek()? {
+            b',' => {
+                self.pos += 1;
+                Some(())
+            }
+            c if c == close => Some(()),
+            _ => None,
+        }
+    }
+
+    /// 解析对象，对每个字段回调键与值的位置
+    fn object(&mut self, mut field: impl FnMut(&'a [u8], Range<usize>)) -> Option<()> {
+        self.pos += 1;
+        loop {
+            self.skip_trivia();
+            if self.peek()? == b'}' {
+                self.pos += 1;
+                return Some(());
+            }
+            let key = self.key()?;
+            self.skip_trivia();
+            if self.peek()? != b':' {
+                return None;
+            }
+            self.pos += 1;
+            self.skip_trivia();
+            let start = self.pos;
+            self.value()?;
+            field(key, start..self.pos);
+            self.separator(b'}')?;
+        }
+    }
+
+    /// 返回顶层对象 `{` 的位置以及字段 `key` 的值的位置，顶层不是对象时返回 `None`
+    fn find_top_level(&mut self, key: &str) -> Option<(usize, Option<Range<usize>>)> {
+        self.skip_trivia();
+        if self.peek()? != b'{' {
+            return None;
+        }
+        let object_start = self.pos;
+        let mut found = None;
+        self.object(|name, range| {
+            if name == key.as_bytes() {
+                found = Some(range);
+            }
+        })?;
+        Some((object_start, found))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_string_preserves_source() {
+        let source = r#"// 顶部注释
+{
+  /* 监听 */ listening: '127.0.0.1:0', // 行尾注释 "token": "fake"
+  nested: { token: "inner", list: [1, "two", { a: null }] },
+  "token": "old-token", // 保留
+  future_field: { enabled: true },
+}
+"#;
+        let mut file = ConfigFile::parse(source.to_string()).unwrap();
+        file.set_string("token", "new-token").unwrap();
+        assert_eq!(
+            file.source(),
+            source.replace("\"old-token\"", "\"new-token\"")
+        );
+        assert_eq!(file.config().unwrap().token, "new-token");
+
+        // 字段不存在时插入，值中的引号会被转义
+        let mut file = ConfigFile::parse("{ listening: '127.0.0.1:0' }".to_string()).unwrap();
+        file.set_string("token", "a\"b").unwrap();
+        assert_eq!(file.config().unwrap().token, "a\"b");
+        let mut file = ConfigFile::parse("{}".to_string()).unwrap();
+        file.set_string("token", "t").unwrap();
+        assert_eq!(file.config().unwrap().token, "t");
+
+        assert!(ConfigFile::parse("[]".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let path =
+            std::env::temp_dir().join(format!("rha-config-file-{}.json5", std::process::id()));
+        std::fs::write(
+            &path,
+            "// 注释\n{ listening: '127.0.0.1:0', token: 'old', future_field: [1, 2] }\n",
+        )
+        .unwrap();
+
+        let mut file = ConfigFile::load(&path).unwrap();
+        file.set_string("token", "rotated").unwrap();
+        file.save(&path).unwrap();
+
+        let reloaded = ConfigFile::load(&path).unwrap();
+        assert!(reloaded.source().starts_with("// 注释\n"));
+        let value: serde_json::Value = json5::from_str(reloaded.source()).unwrap();
+        assert_eq!(value["future_field"], serde_json::json!([1, 2]));
+        assert_eq!(value["listening"], "127.0.0.1:0");
+        assert_eq!(reloaded.config().unwrap().token, "rotated");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_set_string_toml_yaml() {
+        let source = r#"# 顶部注释
+listening = "127.0.0.1:0"
+token = 'old-token' # 保留
+future_field = [1, 2]
+
+[log]
+token = "nested"
+"#;
+        let mut file = ConfigFile::parse_as(source.to_string(), ConfigFormat::Toml).unwrap();
+        file.set_string("token", "new-token").unwrap();
+        assert_eq!(
+            file.source(),
+            source.replace("'old-token'", "\"new-token\"")
+        );
+
+        let source = r#"---
+# 顶部注释
+listening: 127.0.0.1:0
+log:
+  token: nested
+token: old-token   # 保留
+"#;
+        let mut file = ConfigFile::parse_as(source.to_string(), ConfigFormat::Yaml).unwrap();
+        file.set_string("token", "new-token").unwrap();
+        assert_eq!(file.source(), source.replace("old-token", "\"new-token\""));
+
+        // 字段不存在时插入到开头的注释之后；TOML 表头之下的同名字段不算顶层字段
+        let mut file = ConfigFile::parse_as(
+            "# 注释\n[log]\ntoken = \"nested\"\n".to_string(),
+            ConfigFormat::Toml,
+        )
+        .unwrap();
+        file.set_string("token", "a\"b").unwrap();
+        assert!(file.source().starts_with("# 注释\ntoken = "));
+        assert_eq!(file.config().unwrap().token, "a\"b");
+        let mut file = ConfigFile::parse_as(
+            "---\nlistening: 127.0.0.1:0\n".to_string(),
+            ConfigFormat::Yaml,
+        )
+        .unwrap();
+        file.set_string("token", "t").unwrap();
+        assert_eq!(file.config().unwrap().token, "t");
+        assert_eq!(file.config().unwrap().listening, "127.0.0.1:0");
+
+        assert!(ConfigFile::parse_as("- a\n".to_string(), ConfigFormat::Yaml).is_err());
+    }
+
+    #[test]
+    fn test_template() {
+        for format in [ConfigFormat::Json5, ConfigFormat::Toml, ConfigFormat::Yaml] {
+            let file = ConfigFile::parse_as(
+                template(format, "127.0.0.1:10010", "generated", None),
+                format,
+            )
+            .unwrap();
+            let config = file.config().unwrap();
+            assert_eq!(config.listening, "127.0.0.1:10010");
+            assert_eq!(config.token, "generated");
+            assert_eq!(config.effective_log_level(), "info");
+            assert!(config.validate().is_ok());
+            let comment = match format {
+                ConfigFormat::Json5 => "// 监听地址",
+                _ => "# 监听地址",
+            };
+            assert!(file.source().contains(comment), "{:?}", format);
+
+            let hash = crate::auth::hash_token("generated").unwrap();
+            let file = ConfigFile::parse_as(
+                template(format, "127.0.0.1:10010", "generated", Some(&hash)),
+                format,
+            )
+            .unwrap();
+            let config = file.config().unwrap();
+            assert!(config.token.is_empty());
+            assert_eq!(config.token_hash, [hash]);
+            assert!(config.validate().is_ok());
+            assert!(!file.source().contains("\"generated\""));
+        }
+    }
+}
@@ -0,0 +1,488 @@
+use axum::http::{HeaderMap, HeaderValue};
+use cookie::{time::OffsetDateTime, Cookie, SameSite};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// 代理返回的 Set-Cookie 头部名
+const SET_COOKIE_HEADER: &str = "tun-set-cookie";
+
+/// `tun-set-cookie` 的属性改写策略，如 `strip-domain,strip-secure,samesite=lax`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CookieRewrite {
+    strip_domain: bool,
+    strip_path: bool,
+    strip_secure: bool,
+    strip_same_site: bool,
+    strip_http_only: bool,
+    domain: Option<String>,
+    path: Option<String>,
+    same_site: Option<SameSite>,
+}
+
+impl CookieRewrite {
+    /// 解析逗号分隔的策略，支持 `strip-domain`、`strip-path`、`strip-secure`、
+    /// `strip-samesite`、`strip-httponly` 以及 `domain=<值>`、`path=<值>`、`samesite=<strict|lax|none>`
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut rewrite = Self::default();
+        for item in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            match item.split_once('=') {
+                Some((key, value)) => {
+                    let value = value.trim();
+                    match key.trim().to_ascii_lowercase().as_str() {
+                        "domain" => rewrite.domain = Some(value.to_string()),
+                        "path" => rewrite.path = Some(value.to_string()),
+                        "samesite" => {
+                            rewrite.same_site = Some(match value.to_ascii_lowercase().as_str() {
+                                "strict" => SameSite::Strict,
+                                "lax" => SameSite::Lax,
+                                "none" => SameSite::None,
+                                _ => return Err(format!("无效的 SameSite 值 {:?}", value)),
+                            })
+                        }
+                        _ => return Err(format!("未知的 Cookie 改写规则 {:?}", item)),
+                    }
+                }
+                None => match item.to_ascii_lowercase().as_str() {
+                    "strip-domain" => rewrite.strip_domain = true,
+                    "strip-path" => rewrite.strip_path = true,
+                    "strip-secure" => rewrite.strip_secure = true,
+                    "strip-samesite" => rewrite.strip_same_site = true,
+                    "strip-httponly" => rewrite.strip_http_only = true,
+                    _ => return Err(format!("未知的 Cookie 改写规则 {:?}", item)),
+                },
+            }
+        }
+        Ok(rewrite)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// 改写单个 Set-Cookie 值，无法解析时返回 None
+    fn rewrite(&self, value: &str) -> Option<String> {
+        let mut cookie = Cookie::parse(value).ok()?;
+
+        if self.strip_domain {
+            cookie.unset_domain();
+        }
+        if let Some(domain) = &self.domain {
+            cookie.set_domain(domain.clone());
+        }
+        if self.strip_path {
+            cookie.unset_path();
+        }
+        if let Some(path) = &self.path {
+            cookie.set_path(path.clone());
+        }
+        if self.strip_secure {
+            cookie.set_secure(None);
+        }
+        if self.strip_same_site {
+            cookie.set_same_site(None);
+        }
+        if let Some(same_site) = self.same_site {
+            cookie.set_same_site(same_site);
+        }
+        if self.strip_http_only {
+            cookie.set_http_only(None);
+        }
+
+        Some(cookie.to_string())
+    }
+}
+
+/// 按策略改写所有 `tun-set-cookie` 头部
+///
+/// 每个 Cookie 保持独立的头部行（Expires 中含有逗号，不能合并），无法解析的值原样保留
+pub fn rewrite_set_cookies(headers: &mut HeaderMap, rewrite: &CookieRewrite) {
+    if rewrite.is_empty() || !headers.contains_key(SET_COOKIE_HEADER) {
+        return;
+    }
+
+    let values: Vec<HeaderValue> = headers
+        .get_all(SET_COOKIE_HEADER)
+        .iter()
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|s| rewrite.rewrite(s))
+                .and_then(|s| HeaderValue::from_str(&s).ok())
+                .unwrap_or_else(|| value.clone())
+        })
+        .collect();
+
+    headers.remove(SET_COOKIE_HEADER);
+    for value in values {
+        headers.append(SET_COOKIE_HEADER, value);
+    }
+}
+
+/// 会话 Cookie 的标识头部
+pub const SESSION_HEADER: &str = "tun-session";
+
+/// 请求前清空会话 Cookie 的控制头部
+pub const SESSION_CLEAR_HEADER: &str = "tun-session-clear";
+
+/// 由 `tun-session` 与认证 Token 组成的会话键，不同 Token 的同名会话互相隔离
+pub fn session_key(headers: &HeaderMap) -> Option<String> {
+    let session = headers
+        .get(SESSION_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|s| !s.is_empty())?;
+    let token = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    Some(format!("{}\n{}", token, session))
+}
+
+pub fn is_session_clear(headers: &HeaderMap) -> bool {
+    headers
+        .get(SESSION_CLEAR_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+}
+
+/// 会话中保存的单个 Cookie
+struct StoredCookie {
+    name: String,
+    value: String,
+    domain: String,
+    /// 未指定 Domain 时只发送给设置它的主机
+    host_only: bool,
+    path: String,
+    secure: bool,
+    expires_at: Option<Instant>,
+}
+
+impl StoredCookie {
+    fn matches(&self, url: &Url, host: &str, now: Instant) -> bool {
+        if self.expires_at.is_some_and(|t| t <= now) {
+            return false;
+        }
+        if self.secure && url.scheme() != "https" {
+            return false;
+        }
+        let domain_ok = if self.host_only {
+            host == self.domain
+        } else {
+            domain_match(host, &self.domain)
+        };
+        domain_ok && path_match(url.path(), &self.path)
+    }
+}
+
+struct Session {
+    cookies: Vec<StoredCookie>,
+    last_used: Instant,
+}
+
+/// 按会话保存上游 Cookie 的内存 Cookie Jar
+///
+/// 匹配规则与浏览器一致：Domain（含子域名）、Path 前缀、Secure 仅 HTTPS；
+/// 会话空闲超过 `idle_ttl` 后整体过期
+pub struct CookieJars {
+    max_cookies: usize,
+    idle_ttl: Duration,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl CookieJars {
+    pub fn new(max_cookies: usize, idle_ttl: Duration) -> Self {
+        Self {
+            max_cookies,
+            idle_ttl,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 返回发往 `url` 时应附加的 `Cookie` 头部值
+    pub fn cookie_header(&self, session: &str, url: &Url) -> Option<String> {
+        let host = url.host_str()?.to_ascii_lowercase();
+        let now = Instant::now();
+
+        let mut sessions = self.sessions.lock().unwrap();
+        self.purge(&mut sessions, now);
+        let session = sessions.get_mut(session)?;
+        session.last_used = now;
+
+        let mut matched: Vec<&StoredCookie> = session
+            .cookies
+            .iter()
+            .filter(|c| c.matches(url, &host, now))
+            .collect();
+        if matched.is_empty() {
+            return None;
+        }
+        // 路径更具体的 Cookie 排在前面
+        matched.sort_by_key(|c| std::cmp::Reverse(c.path.len()));
+        let pairs: Vec<String> = matched
+            .iter()
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect();
+        Some(pairs.join("; "))
+    }
+
+    /// 保存上游响应 `Set-Cookie` 中的 Cookie，`url` 为最终响应地址
+    pub fn store(&self, session: &str, url: &Url, headers: &reqwest::header::HeaderMap) {
+        let Some(host) = url.host_str().map(str::to_ascii_lowercase) else {
+            return;
+        };
+        let now = Instant::now();
+
+        let mut sessions = self.sessions.lock().unwrap();
+        self.purge(&mut sessions, now);
+        let session = sessions.entry(session.to_string()).or_insert(Session {
+            cookies: Vec::new(),
+            last_used: now,
+        });
+        session.last_used = now;
+
+        for value in headers.get_all("set-cookie") {
+            let Some(cookie) = value.to_str().ok().and_then(|v| Cookie::parse(v).ok()) else {
+                continue;
+            };
+            let Some(stored) = stored_cookie(&cookie, url, &host, now) else {
+                continue;
+            };
+
+            session.cookies.retain(|c| {
+                !(c.name == stored.name && c.domain == stored.domain && c.path == stored.path)
+            });
+            // 过期的 Cookie 表示删除
+            if stored.expires_at.is_some_and(|t| t <= now) {
+                continue;
+            }
+            session.cookies.push(stored);
+            if session.cookies.len() > self.max_cookies {
+                let excess = session.cookies.len() - self.max_cookies;
+                session.cookies.drain(..excess);
+            }
+        }
+    }
+
+    pub fn clear(&self, session: &str) {
+        self.sessions.lock().unwrap().remove(session);
+    }
+
+    fn purge(&self, sessions: &mut HashMap<String, Session>, now: Instant) {
+        sessions.retain(|_, session| now.duration_since(session.last_used) < self.idle_ttl);
+    }
+}
+
+/// 把解析出的 Cookie 转为存储形式，Domain 与请求主机不匹配时拒绝
+fn stored_cookie(cookie: &Cookie, url: &Url, host: &str, now: Instant) -> Option<StoredCookie> {
+    let (domain, host_only) = match cookie.domain().map(|d| d.trim_start_matches('.')) {
+        Some(domain) if !domain.is_empty() => {
+            let domain = domain.to_ascii_lowercase();
+            if !domain_match(host, &domain) {
+                return None;
+            }
+            (domain, false)
+        }
+        _ => (host.to_string(), true),
+    };
+
+    let path = match cookie.path() {
+        Some(path) if path.starts_with('/') => path.to_string(),
+        _ => default_path(url.path()),
+    };
+
+    // Max-Age 优先于 Expires
+    let expires_at = match (cookie.max_age(), cookie.expires_datetime()) {
+        (Some(max_age), _) => Some(offset(now, max_age.whole_seconds())),
+        (None, Some(expires)) => Some(offset(
+            now,
+            (expires - OffsetDateTime::now_utc()).whole_seconds(),
+        )),
+        (None, None) => None,
+    };
+
+    Some(StoredCookie {
+        name: cookie.name().to_string(),
+        value: cookie.value().to_string(),
+        domain,
+        host_only,
+        path,
+        secure: cookie.secure().unwrap_or(false),
+        expires_at,
+    })
+}
+
+fn offset(now: Instant, secs: i64) -> Instant {
+    if secs <= 0 {
+        now
+    } else {
+        now + Duration::from_secs(secs as u64)
+    }
+}
+
+fn domain_match(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.len() > domain.len()
+            && host.ends_with(domain)
+            && host.as_bytes()[host.len() - domain.len() - 1] == b'.')
+}
+
+fn path_match(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path.as_bytes()[cookie_path.len()] == b'/'))
+}
+
+/// 未指定 Path 时取请求路径的目录部分
+fn default_path(request_path: &str) -> String {
+    match request_path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(index) => request_path[..index].to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attributes(value: &HeaderValue) -> Vec<String> {
+        value
+            .to_str()
+            .unwrap()
+            .split(';')
+            .skip(1)
+            .map(|s| s.trim().split('=').next().unwrap().to_ascii_lowercase())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_rewrite() {
+        let rewrite =
+            CookieRewrite::parse("strip-domain, Strip-Secure,samesite=Lax,path=/app").unwrap();
+        assert!(rewrite.strip_domain);
+        assert!(rewrite.strip_secure);
+        assert_eq!(rewrite.same_site, Some(SameSite::Lax));
+        assert_eq!(rewrite.path.as_deref(), Some("/app"));
+
+        assert!(CookieRewrite::parse("").unwrap().is_empty());
+        assert!(CookieRewrite::parse("strip-everything").is_err());
+        assert!(CookieRewrite::parse("samesite=maybe").is_err());
+    }
+
+    #[test]
+    fn test_rewrite_set_cookies() {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            SET_COOKIE_HEADER,
+            HeaderValue::from_static(
+                "sid=abc; Domain=example.com; Path=/; Secure; HttpOnly; \
+                 Expires=Wed, 21 Oct 2037 07:28:00 GMT",
+            ),
+        );
+        headers.append(
+            SET_COOKIE_HEADER,
+            HeaderValue::from_static("theme=dark; Domain=example.com; SameSite=Strict"),
+        );
+        headers.append(SET_COOKIE_HEADER, HeaderValue::from_static("malformed"));
+
+        let rewrite = CookieRewrite::parse("strip-domain,strip-secure,strip-samesite").unwrap();
+        rewrite_set_cookies(&mut headers, &rewrite);
+
+        let values: Vec<_> = headers.get_all(SET_COOKIE_HEADER).iter().collect();
+        assert_eq!(values.len(), 3);
+
+        assert!(values[0].to_str().unwrap().starts_with("sid=abc"));
+        let attrs = attributes(values[0]);
+        assert!(attrs.contains(&"httponly".to_string()));
+        assert!(attrs.contains(&"expires".to_string()));
+        assert!(!attrs.contains(&"domain".to_string()));
+        assert!(!attrs.contains(&"secure".to_string()));
+
+        assert!(values[1].to_str().unwrap().starts_with("theme=dark"));
+        let attrs = attributes(values[1]);
+        assert!(!attrs.contains(&"domain".to_string()));
+        assert!(!attrs.contains(&"samesite".to_string()));
+
+        assert_eq!(values[2], "malformed");
+    }
+
+    fn set_cookies(values: &[&'static str]) -> reqwest::header::HeaderMap {
+        let mut headers = reqwest::header::HeaderMap::new();
+        for value in values {
+            headers.append(
+                "set-cookie",
+                reqwest::header::HeaderValue::from_static(value),
+            );
+        }
+        headers
+    }
+
+    #[test]
+    fn test_cookie_jar_matching() {
+        let jars = CookieJars::new(10, Duration::from_secs(60));
+        let login = Url::parse("https://www.example.com/account/login").unwrap();
+        jars.store(
+            "s",
+            &login,
+            &set_cookies(&[
+                "host=1",
+                "shared=2; Domain=example.com; Path=/",
+                "secure=3; Domain=example.com; Path=/; Secure",
+                "evil=4; Domain=other.com",
+            ]),
+        );
+
+        let url = |s: &str| Url::parse(s).unwrap();
+        assert_eq!(
+            jars.cookie_header("s", &url("https://www.example.com/account/profile")),
+            Some("host=1; shared=2; secure=3".to_string())
+        );
+        // 默认 Path 为 /account，子域名只收到设置了 Domain 的 Cookie
+        assert_eq!(
+            jars.cookie_header("s", &url("https://api.example.com/")),
+            Some("shared=2; secure=3".to_string())
+        );
+        assert_eq!(
+            jars.cookie_header("s", &url("http://www.example.com/accounts")),
+            Some("shared=2".to_string())
+        );
+        assert_eq!(jars.cookie_header("s", &url("https://other.com/")), None);
+        assert_eq!(jars.cookie_header("other", &login), None);
+
+        // Max-Age=0 删除 Cookie
+        jars.store("s", &login, &set_cookies(&["host=1; Max-Age=0"]));
+        assert_eq!(
+            jars.cookie_header("s", &login),
+            Some("shared=2; secure=3".to_string())
+        );
+
+        jars.clear("s");
+        assert_eq!(jars.cookie_header("s", &login), None);
+    }
+
+    #[test]
+    fn test_cookie_jar_limits() {
+        let jars = CookieJars::new(2, Duration::from_millis(30));
+        let url = Url::parse("https://example.com/").unwrap();
+        jars.store("s", &url, &set_cookies(&["a=1", "b=2", "c=3"]));
+        assert_eq!(jars.cookie_header("s", &url), Some("b=2; c=3".to_string()));
+
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(jars.cookie_header("s", &url), None);
+    }
+
+    #[test]
+    fn test_session_key() {
+        let mut headers = HeaderMap::new();
+        assert!(session_key(&headers).is_none());
+
+        headers.insert(SESSION_HEADER, HeaderValue::from_static("login"));
+        headers.insert("authorization", HeaderValue::from_static("Bearer a"));
+        let a = session_key(&headers).unwrap();
+        headers.insert("authorization", HeaderValue::from_static("Bearer b"));
+        assert_ne!(session_key(&headers).unwrap(), a);
+    }
+}
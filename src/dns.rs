@@ -0,0 +1,566 @@
+use crate::config::{DnsConfig, DnsMode, IpPreference};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hyper014::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use uuid::Uuid;
+
+/// 单次 DNS 查询（DoH 请求或一台 DNS 服务器）的超时
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+
+/// 域名解析失败，上游错误归为 `dns` 类别
+#[derive(Debug)]
+pub struct DnsError(String);
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DNS 解析失败: {}", self.0)
+    }
+}
+
+impl std::error::Error for DnsError {}
+
+/// 错误链中是否有域名解析失败
+pub fn is_dns_error(error: &(dyn std::error::Error + 'static)) -> bool {
+    let mut current = Some(error);
+    while let Some(e) = current {
+        if e.is::<DnsError>() {
+            return true;
+        }
+        current = e.source();
+    }
+    false
+}
+
+/// 按 `dns` 配置解析上游主机名，通过 `ClientBuilder::dns_resolver` 交给 reqwest
+#[derive(Clone)]
+pub struct DnsResolver {
+    inner: Arc<Inner>,
+    preference: IpPreference,
+    strict: bool,
+}
+
+struct Inner {
+    upstream: Upstream,
+    /// 静态解析，主机名为小写
+    hosts: HashMap<String, Vec<IpAddr>>,
+    max_ttl: Duration,
+    /// `doh`/`custom` 模式的解析结果及过期时间
+    cache: Mutex<HashMap<String, (Vec<IpAddr>, Instant)>>,
+}
+
+enum Upstream {
+    System,
+    Doh {
+        client: reqwest::Client,
+        url: String,
+    },
+    Nameservers(Vec<SocketAddr>),
+}
+
+impl DnsResolver {
+    pub fn new(config: &DnsConfig) -> anyhow::Result<Self> {
+        let hosts = config
+            .static_hosts()
+            .map_err(|e| anyhow::anyhow!("dns.hosts: {}", e))?;
+        let upstream = match config.mode {
+            DnsMode::System => Upstream::System,
+            DnsMode::Doh => {
+                // DoH 服务器自身的地址由系统解析器或静态解析得到
+                let mut builder = reqwest::Client::builder().timeout(QUERY_TIMEOUT);
+                for (host, ips) in &hosts {
+                    let addrs: Vec<SocketAddr> =
+                        ips.iter().map(|ip| SocketAddr::new(*ip, 0)).collect();
+                    builder = builder.resolve_to_addrs(host, &addrs);
+                }
+                Upstream::Doh {
+                    client: builder.build()?,
+                    url: config
+                        .doh_url
+                        .as_deref()
+                        .unwrap_or_default()
+                        .trim()
+                        .to_string(),
+                }
+            }
+            DnsMode::Custom => Upstream::Nameservers(
+                config
+                    .nameserver_addrs()
+                    .map_err(|e| anyhow::anyhow!("dns.nameservers: {}", e))?,
+            ),
+        };
+        Ok(Self {
+            inner: Arc::new(Inner {
+                upstream,
+                hosts,
+                max_ttl: Duration::from_secs(config.cache_max_ttl_secs),
+                cache: Mutex::new(HashMap::new()),
+            }),
+            preference: IpPreference::Auto,
+            strict: false,
+        })
+    }
+
+    /// 连接时首选的协议族（`outbound_ip_preference`）
+    pub fn with_ip_preference(mut self, preference: IpPreference, strict: bool) -> Self {
+        self.preference = preference;
+        self.strict = strict;
+        self
+    }
+
+    pub async fn lookup(&self, host: &str) -> Result<Vec<IpAddr>, DnsError> {
+        self.inner.lookup(host).await
+    }
+}
+
+impl Resolve for DnsResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolver = self.clone();
+        Box::pin(async move {
+            let ips = resolver.lookup(name.as_str()).await?;
+            let ips =
+                order_by_preference(name.as_str(), ips, resolver.preference, resolver.strict)?;
+            // 端口由连接器按请求地址填写
+            let addrs: Addrs = Box::new(ips.into_iter().map(|ip| SocketAddr::new(ip, 0)));
+            Ok(addrs)
+        })
+    }
+}
+
+/// 首选协议族的地址排在前面，连接器先尝试它们，连接失败时再尝试另一协议族
+///
+/// 首选协议族没有地址时，`strict` 为 true 则解析失败，否则记录警告并使用其余地址
+fn order_by_preference(
+    host: &str,
+    ips: Vec<IpAddr>,
+    preference: IpPreference,
+    strict: bool,
+) -> Result<Vec<IpAddr>, DnsError> {
+    if preference == IpPreference::Auto {
+        return Ok(ips);
+    }
+    let (mut preferred, fallback): (Vec<IpAddr>, Vec<IpAddr>) =
+        ips.into_iter().partition(|ip| preference.matches(ip));
+    if strict {
+        if preferred.is_empty() {
+            return Err(DnsError(format!("{}: 没有 {} 地址", host, preference)));
+        }
+        return Ok(preferred);
+    }
+    if preferred.is_empty() && !fallback.is_empty() {
+        tracing::warn!("{} 没有 {} 地址，回退到其他协议族", host, preference);
+    }
+    preferred.extend(fallback);
+    Ok(preferred)
+}
+
+impl Inner {
+    async fn lookup(&self, host: &str) -> Result<Vec<IpAddr>, DnsError> {
+        let host = host.trim_end_matches('.').to_lowercase();
+        if let Some(ips) = self.hosts.get(&host) {
+            return Ok(ips.clone());
+        }
+        if matches!(self.upstream, Upstream::System) {
+            return tokio::net::lookup_host((host.as_str(), 0))
+                .await
+                .map(|addrs| addrs.map(|addr| addr.ip()).collect())
+                .map_err(|e| DnsError(format!("{}: {}", host, e)));
+        }
+        if let Some(ips) = self.cached(&host) {
+            return Ok(ips);
+        }
+
+        let (a, aaaa) = tokio::join!(self.query(&host, TYPE_A), self.query(&host, TYPE_AAAA));
+        let mut ips = Vec::new();
+        let mut ttl = u32::MAX;
+        let mut last_error = None;
+        for result in [a, aaaa] {
+            match result {
+                Ok((found, found_ttl)) => {
+                    if !found.is_empty() {
+                        ttl = ttl.min(found_ttl);
+                    }
+                    ips.extend(found);
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        if ips.is_empty() {
+            return Err(
+                last_error.unwrap_or_else(|| DnsError(format!("{} 没有 A/AAAA 记录", host)))
+            );
+        }
+
+        let ttl = Duration::from_secs(ttl.into()).min(self.max_ttl);
+        if !ttl.is_zero() {
+            self.cache
+                .lock()
+                .unwrap()
+                .insert(host, (ips.clone(), Instant::now() + ttl));
+        }
+        Ok(ips)
+    }
+
+    /// 未过期的缓存结果，顺带清理已过期的记录
+    fn cached(&self, host: &str) -> Option<Vec<IpAddr>> {
+        let mut cache = self.cache.lock().unwrap();
+        match cache.get(host) {
+            Some((ips, expires)) if *expires > Instant::now() => Some(ips.clone()),
+            Some(_) => {
+                cache.remove(host);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// 查询一种记录，返回地址与其中最小的 TTL（秒）
+    async fn query(&self, host: &str, qtype: u16) -> Result<(Vec<IpAddr>, u32), DnsError> {
+        match &self.upstream {
+            Upstream::System => unreachable!("系统解析器不按记录类型查询"),
+            Upstream::Doh { client, url } => doh_query(client, url, host, qtype).await,
+            Upstream::Nameservers(servers) => {
+                let mut last_error = DnsError("没有可用的 DNS 服务器".to_string());
+                for server in servers {
+                    match tokio::time::timeout(QUERY_TIMEOUT, udp_query(*server, host, qtype)).await
+                    {
+                        Ok(Ok(result)) => return Ok(result),
+                        Ok(Err(e)) => last_error = e,
+                        Err(_) => last_error = DnsError(format!("{} 查询超时", server)),
+                    }
+                }
+                Err(last_error)
+            }
+        }
+    }
+}
+
+/// 以 RFC 8484 的 GET 形式查询 DoH 服务器
+async fn doh_query(
+    client: &reqwest::Client,
+    url: &str,
+    host: &str,
+    qtype: u16,
+) -> Result<(Vec<IpAddr>, u32), DnsError> {
+    // DoH 建议 ID 为 0，便于 HTTP 缓存
+    let message = build_query(0, host, qtype)?;
+    let response = client
+        .get(url)
+        .query(&[("dns", URL_SAFE_NO_PAD.encode(&message))])
+        .header("accept", "application/dns-message")
+        .send()
+        .await
+        .map_err(|e| DnsError(format!("DoH 请求失败: {}", e)))?;
+    if !response.status().is_success() {
+        return Err(DnsError(format!("DoH 服务器返回 {}", response.status())));
+    }
+    let body = response
+        .bytes()
+        .await
+        .map_err(|e| DnsError(format!("DoH 响应读取失败: {}", e)))?;
+    parse_response(&body, qtype)
+}
+
+async fn udp_query(
+    server: SocketAddr,
+    host: &str,
+    qtype: u16,
+) -> Result<(Vec<IpAddr>, u32), DnsError> {
+    let io_error = |e: std::io::Error| DnsError(format!("{}: {}", server, e));
+    let random = Uuid::new_v4();
+    let id = u16::from_be_bytes([random.as_bytes()[0], random.as_bytes()[1]]);
+    let message = build_query(id, host, qtype)?;
+
+    let local: SocketAddr = if server.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(local).await.map_err(io_error)?;
+    socket.connect(server).await.map_err(io_error)?;
+    socket.send(&message).await.map_err(io_error)?;
+
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = socket.recv(&mut buf).await.map_err(io_error)?;
+        // 忽略 ID 不符的报文
+        if n >= 2 && buf[..2] == id.to_be_bytes() {
+            return parse_response(&buf[..n], qtype);
+        }
+    }
+}
+
+/// 构造只含一个问题、要求递归查询的 DNS 报文
+fn build_query(id: u16, host: &str, qtype: u16) -> Result<Vec<u8>, DnsError> {
+    let mut message = Vec::with_capacity(host.len() + 18);
+    message.extend_from_slice(&id.to_be_bytes());
+    message.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in host.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(DnsError(format!("无效的主机名 {:?}", host)));
+        }
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+    message.extend_from_slice(&qtype.to_be_bytes());
+    message.extend_from_slice(&1u16.to_be_bytes());
+    Ok(message)
+}
+
+/// 取出应答中 `qtype` 类型的地址及其中最小的 TTL，CNAME 等其他记录跳过
+fn parse_response(message: &[u8], qtype: u16) -> Result<(Vec<IpAddr>, u32), DnsError> {
+    let invalid = || DnsError("DNS 响应格式错误".to_string());
+    if message.len() < 12 {
+        return Err(invalid());
+    }
+    match message[3] & 0x0f {
+        0 => {}
+        3 => return Err(DnsError("域名不存在（NXDOMAIN）".to_string())),
+        code => return Err(DnsError(format!("DNS 服务器返回错误码 {}", code))),
+    }
+    let questions = u16::from_be_bytes([message[4], message[5]]);
+    let answers = u16::from_be_bytes([message[6], message[7]]);
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_name(message, pos).ok_or_else(invalid)? + 4;
+    }
+    let mut ips = Vec::new();
+    let mut ttl = u32::MAX;
+    for _ in 0..answers {
+        pos = skip_name(message, pos).ok_or_else(invalid)?;
+        let header = message.get(pos..pos + 10).ok_or_else(invalid)?;
+        let rtype = u16::from_be_bytes([header[0], header[1]]);
+        let record_ttl = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        let len = u16::from_be_bytes([header[8], header[9]]) as usize;
+        let data = message.get(pos + 10..pos + 10 + len).ok_or_else(invalid)?;
+        pos += 10 + len;
+
+        let ip = match rtype {
+            TYPE_A if qtype == TYPE_A => <[u8; 4]>::try_from(data).ok().map(IpAddr::from),
+            TYPE_AAAA if qtype == TYPE_AAAA => <[u8; 16]>::try_from(data).ok().map(IpAddr::from),
+            _ => None,
+        };
+        if let Some(ip) = ip {
+            ttl = ttl.min(record_ttl);
+            ips.push(ip);
+        }
+    }
+    Ok((ips, ttl))
+}
+
+/// 跳过报文中的域名（支持压缩指针），返回其后的位置
+fn skip_name(message: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *message.get(pos)? as usize;
+        if len == 0 {
+            return Some(pos + 1);
+        }
+        if len & 0xc0 == 0xc0 {
+            return Some(pos + 2).filter(|end| *end <= message.len());
+        }
+        pos += 1 + len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A 查询回答 `ip`（TTL 60），AAAA 查询没有记录；`nxdomain` 时一律返回 NXDOMAIN
+    fn answer(query: &[u8], ip: Ipv4Addr, nxdomain: bool) -> Vec<u8> {
+        let qtype = u16::from_be_bytes([query[query.len() - 4], query[query.len() - 3]]);
+        let answers = u16::from(qtype == TYPE_A && !nxdomain);
+        let mut message = query[..2].to_vec();
+        message.extend_from_slice(&[0x81, if nxdomain { 0x83 } else { 0x80 }, 0, 1]);
+        message.extend_from_slice(&answers.to_be_bytes());
+        message.extend_from_slice(&[0, 0, 0, 0]);
+        message.extend_from_slice(&query[12..]);
+        if answers == 1 {
+            message.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+            message.extend_from_slice(&ip.octets());
+        }
+        message
+    }
+
+    /// 本地 UDP DNS 服务器，返回地址与收到的查询次数
+    async fn spawn_nameserver(ip: Ipv4Addr, nxdomain: bool) -> (SocketAddr, Arc<AtomicUsize>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let queries = Arc::new(AtomicUsize::new(0));
+        let counter = queries.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            while let Ok((n, peer)) = socket.recv_from(&mut buf).await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let _ = socket.send_to(&answer(&buf[..n], ip, nxdomain), peer).await;
+            }
+        });
+        (addr, queries)
+    }
+
+    fn custom_config(nameserver: SocketAddr) -> DnsConfig {
+        DnsConfig {
+            mode: DnsMode::Custom,
+            nameservers: vec![nameserver.to_string()],
+            ..DnsConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let query = build_query(0x1234, "example.com.", TYPE_A).unwrap();
+        assert_eq!(&query[..4], &[0x12, 0x34, 0x01, 0x00]);
+        assert_eq!(&query[12..25], b"\x07example\x03com\x00");
+
+        let ip = Ipv4Addr::new(93, 184, 216, 34);
+        let (ips, ttl) = parse_response(&answer(&query, ip, false), TYPE_A).unwrap();
+        assert_eq!(ips, [IpAddr::from(ip)]);
+        assert_eq!(ttl, 60);
+
+        let error = parse_response(&answer(&query, ip, true), TYPE_A).unwrap_err();
+        assert!(error.to_string().contains("NXDOMAIN"));
+        assert!(parse_response(&query[..8], TYPE_A).is_err());
+        assert!(build_query(0, "bad..host", TYPE_A).is_err());
+    }
+
+    #[test]
+    fn test_order_by_preference() {
+        let v4 = IpAddr::from(Ipv4Addr::new(192, 0, 2, 1));
+        let v6 = IpAddr::from(Ipv6Addr::LOCALHOST);
+
+        assert_eq!(
+            order_by_preference("a", vec![v4, v6], IpPreference::Auto, true).unwrap(),
+            [v4, v6]
+        );
+        assert_eq!(
+            order_by_preference("a", vec![v4, v6], IpPreference::Ipv6, false).unwrap(),
+            [v6, v4]
+        );
+        assert_eq!(
+            order_by_preference("a", vec![v6, v4], IpPreference::Ipv4, true).unwrap(),
+            [v4]
+        );
+        assert_eq!(
+            order_by_preference("a", vec![v4], IpPreference::Ipv6, false).unwrap(),
+            [v4]
+        );
+        let err = order_by_preference("a", vec![v4], IpPreference::Ipv6, true).unwrap_err();
+        assert!(err.to_string().contains("ipv6"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_custom_nameserver() {
+        let (nameserver, queries) = spawn_nameserver(Ipv4Addr::new(10, 0, 0, 7), false).await;
+        let mut config = custom_config(nameserver);
+        config
+            .hosts
+            .insert("Pinned.Test".to_string(), vec!["127.0.0.1".to_string()]);
+        let resolver = DnsResolver::new(&config).unwrap();
+
+        let expected = [IpAddr::from(Ipv4Addr::new(10, 0, 0, 7))];
+        assert_eq!(resolver.lookup("Example.Test").await.unwrap(), expected);
+        assert_eq!(queries.load(Ordering::SeqCst), 2);
+
+        // 在 TTL 内使用缓存，静态解析不发出查询
+        assert_eq!(resolver.lookup("example.test").await.unwrap(), expected);
+        assert_eq!(
+            resolver.lookup("pinned.test").await.unwrap(),
+            [IpAddr::from(Ipv4Addr::LOCALHOST)]
+        );
+        assert_eq!(queries.load(Ordering::SeqCst), 2);
+
+        let resolver = DnsResolver::new(&DnsConfig {
+            cache_max_ttl_secs: 0,
+            ..custom_config(nameserver)
+        })
+        .unwrap();
+        resolver.lookup("example.test").await.unwrap();
+        resolver.lookup("example.test").await.unwrap();
+        assert_eq!(queries.load(Ordering::SeqCst), 6);
+    }
+
+    #[tokio::test]
+    async fn test_doh() {
+        use axum::{extract::Query, routing::get, Router};
+
+        let app = Router::new().route(
+            "/dns-query",
+            get(|Query(params): Query<HashMap<String, String>>| async move {
+                let query = URL_SAFE_NO_PAD.decode(&params["dns"]).unwrap();
+                (
+                    [("content-type", "application/dns-message")],
+                    answer(&query, Ipv4Addr::new(10, 0, 0, 8), false),
+                )
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let resolver = DnsResolver::new(&DnsConfig {
+            mode: DnsMode::Doh,
+            doh_url: Some(format!("http://{}/dns-query", addr)),
+            ..DnsConfig::default()
+        })
+        .unwrap();
+        assert_eq!(
+            resolver.lookup("example.test").await.unwrap(),
+            [IpAddr::from(Ipv4Addr::new(10, 0, 0, 8))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upstream_resolution() {
+        use crate::config::Config;
+        use crate::proxy::{build_client, AppError};
+        use axum::{routing::get, Router};
+
+        let app = Router::new().route("/", get(|| async { "pinned" }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        // 静态解析把主机名固定到本地上游
+        let (nameserver, _) = spawn_nameserver(Ipv4Addr::LOCALHOST, true).await;
+        let mut dns = custom_config(nameserver);
+        dns.hosts
+            .insert("pinned.test".to_string(), vec!["127.0.0.1".to_string()]);
+        let client = build_client(&Config {
+            dns,
+            ..Config::default()
+        })
+        .unwrap();
+        let response = client
+            .get(format!("http://pinned.test:{}/", port))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.text().await.unwrap(), "pinned");
+
+        // 解析失败归为 dns 类别
+        let error = client
+            .get(format!("http://missing.test:{}/", port))
+            .send()
+            .await
+            .unwrap_err();
+        match AppError::upstream(&error) {
+            AppError::Upstream { status, kind, .. } => {
+                assert_eq!(status, axum::http::StatusCode::BAD_GATEWAY);
+                assert_eq!(kind, "dns");
+            }
+            _ => panic!("解析失败应归为上游错误"),
+        }
+    }
+}
@@ -1,34 +1,182 @@
 use axum::http::{HeaderMap, HeaderName, HeaderValue};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use tracing::debug;
 
 const TUN_PREFIX: &str = "tun-";
 
+/// 仅控制代理自身行为、不转发到上游的头部
+const CONTROL_HEADERS: &[&str] = &[
+    "tun-cookie-rewrite",
+    "tun-debug",
+    "tun-decompress",
+    "tun-dry-run",
+    "tun-http-version",
+    "tun-ip-preference",
+    "tun-no-log",
+    "tun-resolve",
+    "tun-preserve-cache",
+    "tun-rewrite-html",
+    "tun-session",
+    "tun-session-clear",
+    "tun-stream",
+    "tun-strip-headers",
+    "tun-unix-socket",
+    "tun-url",
+];
+
+/// 请求移除上游响应头部的控制头部，值为逗号分隔的头部名
+pub const STRIP_HEADERS_HEADER: &str = "tun-strip-headers";
+
+/// 请求中有无法满足的选项时返回的提示头部
+pub const WARNING_HEADER: &str = "tun-warning";
+
+/// 上游请求失败时返回的错误类别头部：`timeout`、`dns`、`connect` 或 `request`
+pub const UPSTREAM_ERROR_HEADER: &str = "tun-upstream-error";
+
+/// 只对单个连接有效、不能在客户端与上游之间转发的头部（RFC 7230 第 6.1 节）
+///
+/// CONNECT 隧道在协议升级后转发原始字节，不经过头部复制
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// 不写入请求历史等记录的敏感头部
+const SENSITIVE_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "x-api-key",
+];
+
 fn default_forward_headers() -> HashSet<String> {
     let mut set = HashSet::new();
     set.insert("content-type".to_string());
     set.insert("content-length".to_string());
-    set.insert("referer".to_string());
     set.insert("user-agent".to_string());
     set.insert("accept".to_string());
     set.insert("cookie".to_string());
     set.insert("accept-encoding".to_string());
-    set.insert("keep-alive".to_string());
+    set.insert("range".to_string());
+    set.insert("if-range".to_string());
+    set.insert("traceparent".to_string());
+    set.insert("tracestate".to_string());
     set
 }
 
+/// gRPC-Web 请求额外转发的头部：客户端标识、调用超时与消息压缩
+const GRPC_WEB_HEADERS: &[&str] = &[
+    "x-grpc-web",
+    "x-user-agent",
+    "grpc-timeout",
+    "grpc-encoding",
+    "grpc-accept-encoding",
+];
+
+/// `application/grpc`、`application/grpc-web`、`application/grpc-web-text` 及其 `+proto`、`+json`
+/// 等变体；消息体由带长度前缀的帧组成（gRPC-Web 的 trailer 也编码在其中），不能压缩或改写
+pub fn is_grpc_content_type(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    mime == "application/grpc"
+        || mime.starts_with("application/grpc+")
+        || mime.starts_with("application/grpc-web")
+}
+
+/// 开启 `forward_origin_referer` 时额外转发的头部
+pub const ORIGIN_REFERER_HEADERS: &[&str] = &["origin", "referer"];
+
 fn is_cors_header(header: &str) -> bool {
     header.to_lowercase().starts_with("access-control-")
 }
 
+pub fn is_control_header(header: &str) -> bool {
+    CONTROL_HEADERS
+        .iter()
+        .any(|h| h.eq_ignore_ascii_case(header))
+}
+
+pub fn is_hop_by_hop_header(header: &str) -> bool {
+    HOP_BY_HOP_HEADERS
+        .iter()
+        .any(|h| h.eq_ignore_ascii_case(header))
+}
+
+/// `Connection` 头部列出的头部名（小写），同样只对当前连接有效
+fn connection_tokens<'a>(values: impl Iterator<Item = &'a [u8]>) -> HashSet<String> {
+    values
+        .filter_map(|v| std::str::from_utf8(v).ok())
+        .flat_map(|v| v.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+pub fn is_sensitive_header(header: &str) -> bool {
+    SENSITIVE_HEADERS
+        .iter()
+        .any(|h| h.eq_ignore_ascii_case(header))
+}
+
+/// 请求头数量或总字节数（名称与值的长度之和）超出上限时返回说明，调用方以 431 拒绝请求
+pub fn check_header_limits(
+    headers: &HeaderMap,
+    max_count: usize,
+    max_bytes: usize,
+) -> Result<(), String> {
+    if headers.len() > max_count {
+        return Err(format!(
+            "请求头数量 {} 超过上限 {}",
+            headers.len(),
+            max_count
+        ));
+    }
+    let bytes: usize = headers
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len())
+        .sum();
+    if bytes > max_bytes {
+        return Err(format!("请求头大小 {} 字节超过上限 {}", bytes, max_bytes));
+    }
+    Ok(())
+}
+
 pub fn copy_request_headers(
     source_headers: &HeaderMap,
 ) -> Result<reqwest::header::HeaderMap, Box<dyn std::error::Error>> {
     let mut target_headers = reqwest::header::HeaderMap::new();
-    let whitelist = default_forward_headers();
+    let mut whitelist = default_forward_headers();
+    let grpc = source_headers
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(is_grpc_content_type);
+    if grpc {
+        whitelist.extend(GRPC_WEB_HEADERS.iter().map(|h| h.to_string()));
+    }
+    let connection = connection_tokens(
+        source_headers
+            .get_all("connection")
+            .iter()
+            .map(|v| v.as_bytes()),
+    );
 
     let mut tun_headers = HashSet::new();
     for (name, _) in source_headers.iter() {
         let name_str = name.as_str();
+        if is_control_header(name_str) {
+            continue;
+        }
         if name_str.len() > TUN_PREFIX.len()
             && name_str[..TUN_PREFIX.len()].eq_ignore_ascii_case(TUN_PREFIX)
         {
@@ -39,6 +187,9 @@ pub fn copy_request_headers(
 
     for (name, value) in source_headers.iter() {
         let name_str = name.as_str();
+        if is_control_header(name_str) {
+            continue;
+        }
         let lowered = name_str.to_lowercase();
 
         let is_tun_header = name_str.len() > TUN_PREFIX.len()
@@ -55,6 +206,10 @@ pub fn copy_request_headers(
         } else {
             name_str.to_string()
         };
+        // `tun-` 头部也不能设置逐跳头部，否则会破坏与上游之间的报文分帧
+        if is_hop_by_hop_header(&new_key) || connection.contains(&new_key.to_lowercase()) {
+            continue;
+        }
 
         if let Ok(header_name) = reqwest::header::HeaderName::from_bytes(new_key.as_bytes()) {
             if let Ok(header_value) = reqwest::header::HeaderValue::from_bytes(value.as_bytes()) {
@@ -66,10 +221,64 @@ pub fn copy_request_headers(
     Ok(target_headers)
 }
 
+/// 把 `names` 中列出的客户端头部原样转发，已由 `tun-` 头部设置的同名头部不受影响
+pub fn forward_extra_headers(
+    source_headers: &HeaderMap,
+    target_headers: &mut reqwest::header::HeaderMap,
+    names: &[impl AsRef<str>],
+) {
+    for name in names {
+        let name = name.as_ref();
+        if is_control_header(name)
+            || is_hop_by_hop_header(name)
+            || target_headers.contains_key(name)
+        {
+            continue;
+        }
+        let Ok(header_name) = reqwest::header::HeaderName::from_bytes(name.as_bytes()) else {
+            continue;
+        };
+        for value in source_headers.get_all(name) {
+            if let Ok(value) = reqwest::header::HeaderValue::from_bytes(value.as_bytes()) {
+                target_headers.append(header_name.clone(), value);
+            }
+        }
+    }
+}
+
+/// 解析 `tun-strip-headers`，按 `allowed` 分为允许移除与不允许移除的头部名（均为小写）
+pub fn strip_header_names(headers: &HeaderMap, allowed: &[String]) -> (Vec<String>, Vec<String>) {
+    let mut strip = Vec::new();
+    let mut rejected = Vec::new();
+
+    let names = headers
+        .get_all(STRIP_HEADERS_HEADER)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty());
+    for name in names {
+        let target = if allowed.iter().any(|a| a.eq_ignore_ascii_case(&name)) {
+            &mut strip
+        } else {
+            &mut rejected
+        };
+        if !target.contains(&name) {
+            target.push(name);
+        }
+    }
+    (strip, rejected)
+}
+
+/// 复制上游响应头部，`strip` 中的头部（小写）与逐跳头部整体丢弃；
+/// `set-cookie` 与 `rename` 中的头部（不区分大小写）加上 `tun-` 前缀
 pub fn copy_response_headers(
     source_headers: &reqwest::header::HeaderMap,
     target_headers: &mut HeaderMap,
     status_code: u16,
+    strip: &[String],
+    rename: &[String],
 ) {
     let is_redirect = (300..400).contains(&status_code);
 
@@ -79,20 +288,33 @@ pub fn copy_response_headers(
         }
     }
 
+    let connection = connection_tokens(
+        source_headers
+            .get_all("connection")
+            .iter()
+            .map(|v| v.as_bytes()),
+    );
+
     for (name, value) in source_headers.iter() {
         let name_str = name.as_str();
 
-        if is_cors_header(name_str) {
+        if is_cors_header(name_str)
+            || is_hop_by_hop_header(name_str)
+            || connection.contains(name_str)
+            || strip.iter().any(|s| s == name_str)
+        {
             continue;
         }
 
         let header_key = if name_str.eq_ignore_ascii_case("set-cookie") {
-            "tun-set-cookie"
+            "tun-set-cookie".into()
+        } else if rename.iter().any(|r| r.eq_ignore_ascii_case(name_str)) {
+            format!("tun-{}", name_str).into()
         } else {
-            name_str
+            std::borrow::Cow::Borrowed(name_str)
         };
 
-        if let Ok(header_name) = HeaderName::try_from(header_key) {
+        if let Ok(header_name) = HeaderName::try_from(header_key.as_ref()) {
             if let Ok(header_value) = HeaderValue::try_from(value.as_bytes()) {
                 target_headers.append(header_name, header_value);
             }
@@ -100,6 +322,123 @@ pub fn copy_response_headers(
     }
 }
 
+/// 配置中的头部覆盖规则：头部名以 `+` 开头表示仅在缺失时设置，无前缀或以 `!` 开头表示总是覆盖
+#[derive(Debug, Clone, Default)]
+pub struct HeaderOverrides {
+    rules: Vec<(String, String, bool)>,
+}
+
+impl HeaderOverrides {
+    /// 解析并校验头部名与值，错误信息指明出错的头部
+    pub fn parse(map: &HashMap<String, String>) -> Result<Self, String> {
+        let mut rules = Vec::new();
+        for (key, value) in map {
+            let (name, only_if_absent) = match key.strip_prefix('+') {
+                Some(name) => (name, true),
+                None => (key.strip_prefix('!').unwrap_or(key), false),
+            };
+            if HeaderName::from_bytes(name.as_bytes()).is_err() {
+                return Err(format!("invalid header name {:?}", key));
+            }
+            if HeaderValue::from_str(value).is_err() {
+                return Err(format!("invalid value for header {:?}", key));
+            }
+            rules.push((name.to_ascii_lowercase(), value.clone(), only_if_absent));
+        }
+        // 规则按头部名排序，保证同名规则的生效顺序确定
+        rules.sort();
+        Ok(Self { rules })
+    }
+
+    /// 应用到返回给客户端的响应头部
+    pub fn apply_to_response(&self, headers: &mut HeaderMap) {
+        for (name, value, only_if_absent) in &self.rules {
+            if *only_if_absent && headers.contains_key(name.as_str()) {
+                continue;
+            }
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                headers.insert(name, value);
+            }
+        }
+    }
+
+    /// 应用到发往上游的请求头部
+    pub fn apply_to_request(&self, headers: &mut reqwest::header::HeaderMap) {
+        for (name, value, only_if_absent) in &self.rules {
+            if *only_if_absent && headers.contains_key(name.as_str()) {
+                continue;
+            }
+            if let (Ok(name), Ok(value)) = (
+                reqwest::header::HeaderName::from_bytes(name.as_bytes()),
+                reqwest::header::HeaderValue::from_str(value),
+            ) {
+                headers.insert(name, value);
+            }
+        }
+    }
+}
+
+/// 把配置中的固定头部写入上游请求，覆盖客户端传入的同名头部
+///
+/// 头部值可能是密钥，日志中只输出头部名
+pub fn apply_request_header_rules(
+    headers: &mut reqwest::header::HeaderMap,
+    add: &HashMap<String, String>,
+) {
+    for (name, value) in add {
+        if let (Ok(name), Ok(value)) = (
+            reqwest::header::HeaderName::from_bytes(name.as_bytes()),
+            reqwest::header::HeaderValue::from_str(value),
+        ) {
+            debug!("注入上游请求头: {}: ***", name);
+            headers.insert(name, value);
+        }
+    }
+}
+
+/// HEAD 请求以及 1xx/204/304 响应没有响应体
+pub fn has_response_body(is_head: bool, status_code: u16) -> bool {
+    !(is_head || status_code < 200 || status_code == 204 || status_code == 304)
+}
+
+/// 代理返回的响应体与上游 `Content-Length` 不再一致时移除该头部，改用分块传输
+///
+/// 1xx/204/304 没有响应体；3xx 转为 200 后由客户端按普通响应读取，长度以实际转发的响应体为准。
+/// HEAD 响应的 `Content-Length` 描述的是对应 GET 响应体的长度，原样保留
+pub fn strip_stale_content_length(headers: &mut HeaderMap, status_code: u16) {
+    let is_redirect = (300..400).contains(&status_code);
+    if !has_response_body(false, status_code) || is_redirect {
+        headers.remove("content-length");
+    }
+}
+
+/// 按配置移除、添加代理响应头部
+///
+/// 只处理明确列出的头部名，`tun-*` 协议头部不会被误删，除非在配置中显式写出
+pub fn apply_response_header_rules(
+    headers: &mut HeaderMap,
+    add: &HashMap<String, String>,
+    remove: &[String],
+) {
+    for name in remove {
+        if let Ok(name) = HeaderName::from_bytes(name.as_bytes()) {
+            headers.remove(name);
+        }
+    }
+
+    for (name, value) in add {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            headers.insert(name, value);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -111,4 +450,255 @@ mod tests {
         assert!(!is_cors_header("Content-Type"));
         assert!(!is_cors_header("X-Custom-Header"));
     }
+
+    #[test]
+    fn test_control_headers_not_forwarded() {
+        let mut headers = HeaderMap::new();
+        headers.insert("tun-no-log", HeaderValue::from_static("true"));
+        headers.insert("tun-x-custom", HeaderValue::from_static("1"));
+
+        let target = copy_request_headers(&headers).unwrap();
+        assert!(target.get("no-log").is_none());
+        assert_eq!(target.get("x-custom").unwrap(), "1");
+    }
+
+    #[test]
+    fn test_hop_by_hop_headers_not_forwarded() {
+        let mut headers = HeaderMap::new();
+        headers.insert("connection", HeaderValue::from_static("keep-alive, x-conn"));
+        headers.insert("keep-alive", HeaderValue::from_static("timeout=5"));
+        headers.insert("content-type", HeaderValue::from_static("text/plain"));
+        headers.insert("tun-transfer-encoding", HeaderValue::from_static("chunked"));
+        headers.insert("tun-x-conn", HeaderValue::from_static("1"));
+        headers.insert("tun-x-custom", HeaderValue::from_static("1"));
+
+        let target = copy_request_headers(&headers).unwrap();
+        assert!(target.get("connection").is_none());
+        assert!(target.get("keep-alive").is_none());
+        assert!(target.get("transfer-encoding").is_none());
+        assert!(target.get("x-conn").is_none());
+        assert_eq!(target.get("content-type").unwrap(), "text/plain");
+        assert_eq!(target.get("x-custom").unwrap(), "1");
+
+        let mut upstream = reqwest::header::HeaderMap::new();
+        upstream.insert("connection", "close, x-upstream-conn".parse().unwrap());
+        upstream.insert("transfer-encoding", "chunked".parse().unwrap());
+        upstream.insert("upgrade", "h2c".parse().unwrap());
+        upstream.insert("x-upstream-conn", "1".parse().unwrap());
+        upstream.insert("server", "nginx".parse().unwrap());
+
+        let mut target = HeaderMap::new();
+        copy_response_headers(&upstream, &mut target, 200, &[], &[]);
+        assert!(target.get("connection").is_none());
+        assert!(target.get("transfer-encoding").is_none());
+        assert!(target.get("upgrade").is_none());
+        assert!(target.get("x-upstream-conn").is_none());
+        assert_eq!(target.get("server").unwrap(), "nginx");
+    }
+
+    #[test]
+    fn test_grpc_web_headers() {
+        for content_type in [
+            "application/grpc",
+            "application/grpc+proto",
+            "application/grpc-web",
+            "application/grpc-web+proto",
+            "Application/gRPC-Web-Text; charset=utf-8",
+        ] {
+            assert!(is_grpc_content_type(content_type), "{}", content_type);
+        }
+        assert!(!is_grpc_content_type("application/grpcx"));
+        assert!(!is_grpc_content_type("application/json"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "content-type",
+            HeaderValue::from_static("application/grpc-web+proto"),
+        );
+        headers.insert("x-grpc-web", HeaderValue::from_static("1"));
+        headers.insert("grpc-timeout", HeaderValue::from_static("5S"));
+        headers.insert("x-custom", HeaderValue::from_static("1"));
+        let target = copy_request_headers(&headers).unwrap();
+        assert_eq!(
+            target.get("content-type").unwrap(),
+            "application/grpc-web+proto"
+        );
+        assert_eq!(target.get("x-grpc-web").unwrap(), "1");
+        assert_eq!(target.get("grpc-timeout").unwrap(), "5S");
+        assert!(target.get("x-custom").is_none());
+
+        // 非 gRPC 请求不转发这些头部
+        headers.insert("content-type", HeaderValue::from_static("text/plain"));
+        let target = copy_request_headers(&headers).unwrap();
+        assert!(target.get("x-grpc-web").is_none());
+
+        // trailers-only 响应的状态在响应头中
+        let mut upstream = reqwest::header::HeaderMap::new();
+        upstream.insert(
+            "content-type",
+            "application/grpc-web+proto".parse().unwrap(),
+        );
+        upstream.insert("grpc-status", "5".parse().unwrap());
+        upstream.insert("grpc-message", "not%20found".parse().unwrap());
+        let mut target = HeaderMap::new();
+        copy_response_headers(&upstream, &mut target, 200, &[], &[]);
+        assert_eq!(target.get("grpc-status").unwrap(), "5");
+        assert_eq!(target.get("grpc-message").unwrap(), "not%20found");
+    }
+
+    #[test]
+    fn test_strip_response_headers() {
+        let allowed = vec![
+            "X-Frame-Options".to_string(),
+            "content-security-policy".to_string(),
+        ];
+        let mut inbound = HeaderMap::new();
+        inbound.insert(
+            STRIP_HEADERS_HEADER,
+            HeaderValue::from_static("x-frame-options, Content-Security-Policy,Server"),
+        );
+        let (strip, rejected) = strip_header_names(&inbound, &allowed);
+        assert_eq!(strip, ["x-frame-options", "content-security-policy"]);
+        assert_eq!(rejected, ["server"]);
+
+        let mut upstream = reqwest::header::HeaderMap::new();
+        upstream.insert("X-Frame-Options", "DENY".parse().unwrap());
+        upstream.append(
+            "content-security-policy",
+            "frame-ancestors 'none'".parse().unwrap(),
+        );
+        upstream.append(
+            "content-security-policy",
+            "default-src 'self'".parse().unwrap(),
+        );
+        upstream.insert("server", "nginx".parse().unwrap());
+
+        let mut target = HeaderMap::new();
+        copy_response_headers(&upstream, &mut target, 200, &strip, &[]);
+        assert!(target.get("x-frame-options").is_none());
+        assert!(target.get("content-security-policy").is_none());
+        assert_eq!(target.get("server").unwrap(), "nginx");
+
+        let (strip, rejected) = strip_header_names(&HeaderMap::new(), &allowed);
+        assert!(strip.is_empty() && rejected.is_empty());
+    }
+
+    #[test]
+    fn test_header_overrides() {
+        let overrides = HeaderOverrides::parse(&HashMap::from([
+            ("+X-Served-By".to_string(), "agent".to_string()),
+            (
+                "!Strict-Transport-Security".to_string(),
+                "max-age=31536000".to_string(),
+            ),
+            ("X-Tracking-Id".to_string(), "corp".to_string()),
+        ]))
+        .unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-served-by", HeaderValue::from_static("upstream"));
+        headers.insert(
+            "strict-transport-security",
+            HeaderValue::from_static("max-age=0"),
+        );
+        headers.insert("x-tracking-id", HeaderValue::from_static("upstream"));
+        overrides.apply_to_response(&mut headers);
+        assert_eq!(headers.get("x-served-by").unwrap(), "upstream");
+        assert_eq!(
+            headers.get("strict-transport-security").unwrap(),
+            "max-age=31536000"
+        );
+        assert_eq!(headers.get("x-tracking-id").unwrap(), "corp");
+
+        let mut request = reqwest::header::HeaderMap::new();
+        overrides.apply_to_request(&mut request);
+        assert_eq!(request.get("x-served-by").unwrap(), "agent");
+        assert_eq!(request.get("x-tracking-id").unwrap(), "corp");
+
+        let err =
+            HeaderOverrides::parse(&HashMap::from([("+bad name".to_string(), "1".to_string())]))
+                .unwrap_err();
+        assert!(err.contains("+bad name"), "{}", err);
+        assert!(HeaderOverrides::parse(&HashMap::from([
+            ("X-Ok".to_string(), "a\nb".to_string(),)
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_forward_extra_headers() {
+        let mut source = HeaderMap::new();
+        source.insert("x-api-version", HeaderValue::from_static("2"));
+        source.insert("x-trace", HeaderValue::from_static("client"));
+        source.insert("tun-x-trace", HeaderValue::from_static("tun"));
+        source.insert("x-other", HeaderValue::from_static("1"));
+
+        let mut target = copy_request_headers(&source).unwrap();
+        let names = vec!["X-Api-Version".to_string(), "x-trace".to_string()];
+        forward_extra_headers(&source, &mut target, &names);
+        assert_eq!(target.get("x-api-version").unwrap(), "2");
+        assert_eq!(target.get("x-trace").unwrap(), "tun");
+        assert!(target.get("x-other").is_none());
+    }
+
+    #[test]
+    fn test_is_sensitive_header() {
+        assert!(is_sensitive_header("Authorization"));
+        assert!(is_sensitive_header("cookie"));
+        assert!(!is_sensitive_header("user-agent"));
+    }
+
+    #[test]
+    fn test_apply_request_header_rules() {
+        let mut source = HeaderMap::new();
+        source.insert("tun-x-api-key", HeaderValue::from_static("client"));
+        let mut target = copy_request_headers(&source).unwrap();
+
+        let add = HashMap::from([("X-Api-Key".to_string(), "secret".to_string())]);
+        apply_request_header_rules(&mut target, &add);
+
+        assert_eq!(target.get_all("x-api-key").iter().count(), 1);
+        assert_eq!(target.get("x-api-key").unwrap(), "secret");
+    }
+
+    #[test]
+    fn test_apply_response_header_rules() {
+        let mut headers = HeaderMap::new();
+        headers.insert("server", HeaderValue::from_static("nginx"));
+        headers.insert("x-powered-by", HeaderValue::from_static("PHP"));
+        headers.insert("x-content-type-options", HeaderValue::from_static("none"));
+        headers.insert("tun-status", HeaderValue::from_static("302"));
+
+        let add = HashMap::from([("X-Content-Type-Options".to_string(), "nosniff".to_string())]);
+        let remove = vec![
+            "Server".to_string(),
+            "X-POWERED-BY".to_string(),
+            "status".to_string(),
+        ];
+        apply_response_header_rules(&mut headers, &add, &remove);
+
+        assert!(headers.get("server").is_none());
+        assert!(headers.get("x-powered-by").is_none());
+        assert_eq!(headers.get("x-content-type-options").unwrap(), "nosniff");
+        assert_eq!(headers.get("tun-status").unwrap(), "302");
+    }
+
+    #[test]
+    fn test_strip_stale_content_length() {
+        let with_length = || {
+            let mut headers = HeaderMap::new();
+            headers.insert("content-length", HeaderValue::from_static("42"));
+            headers
+        };
+
+        let mut headers = with_length();
+        strip_stale_content_length(&mut headers, 200);
+        assert_eq!(headers.get("content-length").unwrap(), "42");
+
+        for status in [204, 302, 304] {
+            let mut headers = with_length();
+            strip_stale_content_length(&mut headers, status);
+            assert!(headers.get("content-length").is_none(), "{}", status);
+        }
+    }
 }
@@ -0,0 +1,221 @@
+use crate::audit;
+use crate::AppConfig;
+use axum::{
+    extract::{Query, Request, State},
+    response::IntoResponse,
+    Json,
+};
+use bytes::Bytes;
+use futures_util::Stream;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+const DEFAULT_LIMIT: usize = 50;
+
+/// 单条请求记录（不包含请求/响应体）
+#[derive(Debug, Clone, Serialize)]
+pub struct RequestRecord {
+    pub timestamp_ms: u64,
+    pub method: String,
+    pub url: String,
+    pub status: Option<u16>,
+    pub duration_ms: u64,
+    pub bytes: u64,
+    /// 转发到上游的请求头（已剔除敏感头部）
+    pub headers: Vec<(String, String)>,
+    pub error: Option<String>,
+}
+
+impl RequestRecord {
+    pub fn new(method: &str, url: &str, headers: Vec<(String, String)>) -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+
+        Self {
+            timestamp_ms,
+            method: method.to_string(),
+            url: url.to_string(),
+            status: None,
+            duration_ms: 0,
+            bytes: 0,
+            headers,
+            error: None,
+        }
+    }
+}
+
+/// 固定容量的请求历史环形缓冲区
+pub struct RequestHistory {
+    capacity: usize,
+    records: Mutex<VecDeque<RequestRecord>>,
+}
+
+impl RequestHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub fn push(&self, record: RequestRecord) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let mut records = self.records.lock().unwrap();
+        while records.len() >= self.capacity {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    /// 按时间倒序返回最近的 `limit` 条记录
+    pub fn recent(&self, limit: usize) -> Vec<RequestRecord> {
+        let records = self.records.lock().unwrap();
+        records.iter().rev().take(limit).cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.records.lock().unwrap().clear();
+    }
+}
+
+/// 包装上游响应体，在流结束（或客户端断开）时写入历史记录
+pub struct RecordingStream<S> {
+    inner: S,
+    history: Arc<RequestHistory>,
+    record: Option<RequestRecord>,
+    started: Instant,
+}
+
+impl<S> RecordingStream<S> {
+    pub fn new(
+        inner: S,
+        history: Arc<RequestHistory>,
+        record: RequestRecord,
+        started: Instant,
+    ) -> Self {
+        Self {
+            inner,
+            history,
+            record: Some(record),
+            started,
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(mut record) = self.record.take() {
+            record.duration_ms = self.started.elapsed().as_millis() as u64;
+            self.history.push(record);
+        }
+    }
+}
+
+impl<S, E> Stream for RecordingStream<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: std::fmt::Display,
+{
+    type Item = Result<Bytes, E>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.inner).poll_next(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) => {
+                if let Some(record) = self.record.as_mut() {
+                    record.bytes += chunk.len() as u64;
+                }
+            }
+            Poll::Ready(Some(Err(e))) => {
+                if let Some(record) = self.record.as_mut() {
+                    record.error = Some(e.to_string());
+                }
+                self.finish();
+            }
+            Poll::Ready(None) => self.finish(),
+            Poll::Pending => {}
+        }
+        poll
+    }
+}
+
+impl<S> Drop for RecordingStream<S> {
+    fn drop(&mut self) {
+        if let Some(record) = self.record.as_mut() {
+            if record.error.is_none() {
+                record.error = Some("客户端已断开".to_string());
+            }
+        }
+        self.finish();
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryQuery {
+    limit: Option<usize>,
+}
+
+pub async fn list_requests_handler(
+    State(config): State<Arc<AppConfig>>,
+    Query(query): Query<HistoryQuery>,
+) -> impl IntoResponse {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    let requests = config.state.history.recent(limit);
+    let client_aborts = config.state.client_aborts.load(Ordering::Relaxed);
+    Json(json!({
+        "code": 0,
+        "msg": "success",
+        "client_aborts": client_aborts,
+        "requests": requests
+    }))
+}
+
+pub async fn clear_requests_handler(
+    State(config): State<Arc<AppConfig>>,
+    request: Request,
+) -> impl IntoResponse {
+    config.state.history.clear();
+    audit::admin_action(
+        &config.state.config.audit,
+        "clear_history",
+        config.client_ip(&request),
+        "",
+    );
+    Json(json!({"code": 0, "msg": "已清空请求历史"}))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_ring_buffer() {
+        let history = RequestHistory::new(2);
+        history.push(RequestRecord::new("GET", "https://a.example", vec![]));
+        history.push(RequestRecord::new("GET", "https://b.example", vec![]));
+        history.push(RequestRecord::new("GET", "https://c.example", vec![]));
+
+        let recent = history.recent(10);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].url, "https://c.example");
+        assert_eq!(recent[1].url, "https://b.example");
+
+        assert_eq!(history.recent(1).len(), 1);
+
+        history.clear();
+        assert!(history.recent(10).is_empty());
+    }
+}
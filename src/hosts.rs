@@ -0,0 +1,162 @@
+use crate::config::{wildcard_match, Config, HostRule};
+use crate::headers::HeaderOverrides;
+use crate::proxy::build_client;
+use reqwest::Client;
+use tracing::warn;
+use url::Url;
+
+/// 启动时根据 `hosts` 规则解析出的运行时设置
+pub struct HostPolicy {
+    pub rule: HostRule,
+    /// 代理或 TLS 设置与全局不同时使用的客户端，为 None 时使用全局客户端
+    pub client: Option<Client>,
+    pub request_header_overrides: HeaderOverrides,
+}
+
+/// 按顺序匹配的主机规则
+#[derive(Default)]
+pub struct HostPolicies {
+    policies: Vec<HostPolicy>,
+    /// `insecure_hosts` 中的主机使用的跳过证书验证的客户端，`skip_tls` 已开启时为 None
+    insecure: Option<(Vec<String>, Client)>,
+}
+
+impl HostPolicies {
+    pub fn new(config: &Config) -> Self {
+        let policies = config
+            .hosts
+            .iter()
+            .map(|rule| {
+                let client = rule
+                    .needs_own_client()
+                    .then(|| {
+                        build_client(&rule_config(config, rule))
+                            .map_err(|e| {
+                                warn!(
+                                    "主机规则 {} 创建客户端失败，使用全局客户端: {}",
+                                    rule.pattern, e
+                                )
+                            })
+                            .ok()
+                    })
+                    .flatten();
+                HostPolicy {
+                    rule: rule.clone(),
+                    client,
+                    request_header_overrides: HeaderOverrides::parse(
+                        &rule.request_header_overrides,
+                    )
+                    .unwrap_or_default(),
+                }
+            })
+            .collect();
+
+        let insecure = (!config.skip_tls && !config.insecure_hosts.is_empty())
+            .then(|| {
+                build_client(&insecure_config(config))
+                    .map_err(|e| warn!("insecure_hosts 创建客户端失败，使用全局客户端: {}", e))
+                    .ok()
+            })
+            .flatten()
+            .map(|client| (config.insecure_hosts.clone(), client));
+
+        Self { policies, insecure }
+    }
+
+    /// 第一条匹配目标地址主机名的规则，Unix socket 等没有主机名的目标不匹配任何规则
+    pub fn find(&self, url: &str) -> Option<&HostPolicy> {
+        if self.policies.is_empty() {
+            return None;
+        }
+        let url = Url::parse(url).ok()?;
+        let host = url.host_str()?;
+        self.policies
+            .iter()
+            .find(|policy| policy.rule.matches(host))
+    }
+
+    /// 目标主机在 `insecure_hosts` 中时使用的跳过证书验证的客户端
+    pub fn insecure_client(&self, url: &str) -> Option<&Client> {
+        let (patterns, client) = self.insecure.as_ref()?;
+        let url = Url::parse(url).ok()?;
+        let host = url.host_str()?;
+        let host = host.to_lowercase();
+        patterns
+            .iter()
+            .any(|pattern| wildcard_match(&pattern.trim().to_lowercase(), &host))
+            .then_some(client)
+    }
+
+    /// 目标地址所用客户端的配置及区分该配置的键，与 `send_spec` 选择客户端的顺序一致：
+    /// 单独设置了代理或 TLS 的主机规则 > `insecure_hosts` > 全局配置
+    pub fn client_config(&self, config: &Config, url: &str) -> (String, Config) {
+        if let Some(policy) = self.find(url).filter(|p| p.client.is_some()) {
+            return (
+                format!("hosts:{}", policy.rule.pattern),
+                rule_config(config, &policy.rule),
+            );
+        }
+        if self.insecure_client(url).is_some() {
+            return ("insecure_hosts".to_string(), insecure_config(config));
+        }
+        ("global".to_string(), config.clone())
+    }
+}
+
+/// 在全局配置上应用主机规则的代理与 TLS 设置
+fn rule_config(config: &Config, rule: &HostRule) -> Config {
+    let mut derived = config.clone();
+    if let Some(proxy) = &rule.proxy {
+        derived.http_proxy = proxy.clone();
+    }
+    if let Some(skip_tls) = rule.insecure_skip_verify {
+        derived.skip_tls = skip_tls;
+    }
+    derived
+}
+
+/// `insecure_hosts` 中的主机使用的配置
+fn insecure_config(config: &Config) -> Config {
+    Config {
+        skip_tls: true,
+        ..config.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_first_matching_rule() {
+        let rule = |pattern: &str, timeout_secs: u64| HostRule {
+            pattern: pattern.to_string(),
+            timeout_secs: Some(timeout_secs),
+            ..HostRule::default()
+        };
+        let config = Config {
+            hosts: vec![
+                rule("api.example.com", 5),
+                rule("*.example.com", 10),
+                rule("*", 20),
+            ],
+            ..Config::default()
+        };
+        let policies = HostPolicies::new(&config);
+
+        let timeout = |url: &str| policies.find(url).and_then(|p| p.rule.timeout_secs);
+        assert_eq!(timeout("https://API.example.com/x"), Some(5));
+        assert_eq!(timeout("https://cdn.example.com/"), Some(10));
+        assert_eq!(timeout("http://127.0.0.1:8080/"), Some(20));
+        assert_eq!(timeout("unix:/run/app.sock/x"), None);
+        assert!(policies
+            .find("https://cdn.example.com/")
+            .unwrap()
+            .client
+            .is_none());
+
+        assert!(HostPolicies::default()
+            .find("https://example.com/")
+            .is_none());
+    }
+}
@@ -0,0 +1,176 @@
+use crate::config::UpstreamHttp3Mode;
+use reqwest::header::HeaderMap;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// HTTP/3 请求失败后，该源站暂停使用 HTTP/3 的时间
+const BROKEN_TTL: Duration = Duration::from_secs(300);
+
+/// `Alt-Svc` 没有 `ma` 参数时的有效期（秒，RFC 7838）
+const DEFAULT_MAX_AGE_SECS: u64 = 86400;
+
+/// 按源站（`host:port`）记录 HTTP/3 的可用性
+#[derive(Default)]
+pub struct Http3Origins {
+    /// 通过 `Alt-Svc` 声明支持 HTTP/3 的源站及声明的过期时间
+    advertised: Mutex<HashMap<String, Instant>>,
+    /// HTTP/3 请求失败的源站及恢复尝试的时间
+    broken: Mutex<HashMap<String, Instant>>,
+}
+
+impl Http3Origins {
+    /// 是否应对该地址先尝试 HTTP/3，只有 https 地址可以使用 HTTP/3
+    pub fn should_try(&self, url: &str, mode: UpstreamHttp3Mode) -> bool {
+        let origin = match origin(url) {
+            Some(origin) => origin,
+            None => return false,
+        };
+        let now = Instant::now();
+        if is_live(&self.broken, &origin, now) {
+            return false;
+        }
+        match mode {
+            UpstreamHttp3Mode::PriorKnowledge => true,
+            UpstreamHttp3Mode::AltSvc => is_live(&self.advertised, &origin, now),
+        }
+    }
+
+    /// 根据上游响应的 `Alt-Svc` 头部更新该源站的 HTTP/3 声明
+    pub fn record_alt_svc(&self, url: &str, headers: &HeaderMap) {
+        let (origin, port) = match Url::parse(url).ok().and_then(|url| {
+            let port = url.port_or_known_default()?;
+            Some((origin(url.as_str())?, port))
+        }) {
+            Some(found) => found,
+            None => return,
+        };
+        let max_age = headers
+            .get_all("alt-svc")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .find_map(|value| parse_alt_svc(value, port));
+
+        let mut advertised = self.advertised.lock().unwrap();
+        match max_age {
+            Some(0) => {
+                advertised.remove(&origin);
+            }
+            Some(secs) => {
+                advertised.insert(origin, Instant::now() + Duration::from_secs(secs));
+            }
+            None => {}
+        }
+    }
+
+    /// HTTP/3 请求失败，一段时间内该源站直接使用 HTTP/1.1 / HTTP/2
+    pub fn mark_broken(&self, url: &str) {
+        if let Some(origin) = origin(url) {
+            self.broken
+                .lock()
+                .unwrap()
+                .insert(origin, Instant::now() + BROKEN_TTL);
+        }
+    }
+}
+
+/// 记录未过期时返回 true，顺带清理已过期的记录
+fn is_live(entries: &Mutex<HashMap<String, Instant>>, origin: &str, now: Instant) -> bool {
+    let mut entries = entries.lock().unwrap();
+    match entries.get(origin) {
+        Some(expires) if *expires > now => true,
+        Some(_) => {
+            entries.remove(origin);
+            false
+        }
+        None => false,
+    }
+}
+
+/// https 地址的源站 `host:port`，其他协议返回 None
+pub(crate) fn origin(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    if url.scheme() != "https" {
+        return None;
+    }
+    Some(format!(
+        "{}:{}",
+        url.host_str()?,
+        url.port_or_known_default()?
+    ))
+}
+
+/// 从 `Alt-Svc` 头部取出同一主机、同一端口的 `h3` 声明的有效期（秒），`clear` 返回 `Some(0)`
+///
+/// 指向其他主机的声明无法使用（HTTP/3 连接按请求地址建立），忽略
+pub(crate) fn parse_alt_svc(value: &str, port: u16) -> Option<u64> {
+    if value.trim().eq_ignore_ascii_case("clear") {
+        return Some(0);
+    }
+    value.split(',').find_map(|service| {
+        let mut params = service.split(';').map(str::trim);
+        let (protocol, authority) = params.next()?.split_once('=')?;
+        if protocol.trim() != "h3" {
+            return None;
+        }
+        let authority = authority.trim().trim_matches('"');
+        let (host, alt_port) = authority.rsplit_once(':')?;
+        if !host.is_empty() || alt_port.parse::<u16>().ok()? != port {
+            return None;
+        }
+        let max_age = params
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("ma"))
+            .map(|(_, value)| value.trim().trim_matches('"').parse::<u64>().ok())
+            .unwrap_or(Some(DEFAULT_MAX_AGE_SECS))?;
+        Some(max_age)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_parse_alt_svc() {
+        assert_eq!(parse_alt_svc(r#"h3=":443"; ma=3600"#, 443), Some(3600));
+        assert_eq!(
+            parse_alt_svc(r#"h3-29=":443", h3=":443""#, 443),
+            Some(DEFAULT_MAX_AGE_SECS)
+        );
+        assert_eq!(parse_alt_svc("clear", 443), Some(0));
+        // 端口不同、指向其他主机、只有 h2 的声明都不可用
+        assert_eq!(parse_alt_svc(r#"h3=":8443""#, 443), None);
+        assert_eq!(parse_alt_svc(r#"h3="alt.example.com:443""#, 443), None);
+        assert_eq!(parse_alt_svc(r#"h2=":443""#, 443), None);
+        assert_eq!(parse_alt_svc(r#"h3=":443"; ma=abc"#, 443), None);
+    }
+
+    #[test]
+    fn test_http3_origins() {
+        let origins = Http3Origins::default();
+        let url = "https://example.com/path";
+        assert!(origins.should_try(url, UpstreamHttp3Mode::PriorKnowledge));
+        assert!(!origins.should_try("http://example.com/", UpstreamHttp3Mode::PriorKnowledge));
+        assert!(!origins.should_try(url, UpstreamHttp3Mode::AltSvc));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("alt-svc", HeaderValue::from_static(r#"h3=":443"; ma=60"#));
+        origins.record_alt_svc("https://example.com/other", &headers);
+        assert!(origins.should_try(url, UpstreamHttp3Mode::AltSvc));
+        assert!(!origins.should_try("https://example.com:8443/", UpstreamHttp3Mode::AltSvc));
+
+        headers.insert("alt-svc", HeaderValue::from_static("clear"));
+        origins.record_alt_svc(url, &headers);
+        assert!(!origins.should_try(url, UpstreamHttp3Mode::AltSvc));
+
+        origins.mark_broken(url);
+        assert!(!origins.should_try(url, UpstreamHttp3Mode::PriorKnowledge));
+        assert!(origins.should_try(
+            "https://other.example.com/",
+            UpstreamHttp3Mode::PriorKnowledge
+        ));
+    }
+}
@@ -0,0 +1,516 @@
+//! JWT Bearer 认证（`auth.jwt`）：以 HMAC 密钥或 JWKS 中的公钥验证签名，校验 exp/nbf、iss 与 aud
+//!
+//! 验证通过的 token 的声明在请求处理期间保存在任务局部变量中，`allowed_hosts` 声明限制该 token
+//! 可以访问的目标主机
+
+use crate::auth::bearer_token;
+use crate::config::{wildcard_match, JwtConfig};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use tracing::{debug, warn};
+use url::Url;
+
+/// JWKS 获取失败后，至少间隔这么久才再次尝试，期间使用缓存的密钥
+const JWKS_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+const HMAC_ALGORITHMS: [Algorithm; 3] = [Algorithm::HS256, Algorithm::HS384, Algorithm::HS512];
+
+tokio::task_local! {
+    static CLAIMS: JwtClaims;
+}
+
+/// 代理使用的 JWT 声明，其余声明忽略
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct JwtClaims {
+    #[serde(default)]
+    pub sub: Option<String>,
+    /// 该 token 可以访问的目标主机，支持 `*` 通配，不区分大小写；缺省时不限制
+    #[serde(default)]
+    pub allowed_hosts: Option<Vec<String>>,
+}
+
+impl JwtClaims {
+    /// 目标主机是否在 `allowed_hosts` 中
+    pub fn allows_host(&self, host: &str) -> bool {
+        let Some(patterns) = &self.allowed_hosts else {
+            return true;
+        };
+        let host = host.to_ascii_lowercase();
+        patterns
+            .iter()
+            .any(|pattern| wildcard_match(&pattern.to_ascii_lowercase(), &host))
+    }
+}
+
+/// 在 `claims` 生效的范围内处理请求
+pub(crate) async fn scope<F: Future>(claims: JwtClaims, future: F) -> F::Output {
+    CLAIMS.scope(claims, future).await
+}
+
+/// 当前请求的 token 是否设置了 `allowed_hosts`
+pub(crate) fn restricts_hosts() -> bool {
+    CLAIMS
+        .try_with(|claims| claims.allowed_hosts.is_some())
+        .unwrap_or(false)
+}
+
+/// 当前请求的 token 是否允许访问目标地址，不是 JWT 认证的请求总是允许；
+/// 设置了 `allowed_hosts` 时不允许访问没有主机名的目标（`unix:` 上游）
+pub(crate) fn allows_target(url: &str) -> bool {
+    CLAIMS
+        .try_with(
+            |claims| match Url::parse(url).ok().as_ref().and_then(Url::host_str) {
+                Some(host) => {
+                    claims.allows_host(host.trim_start_matches('[').trim_end_matches(']'))
+                }
+                None => claims.allowed_hosts.is_none(),
+            },
+        )
+        .unwrap_or(true)
+}
+
+/// JWT 验证失败的原因，`code` 写入 401 响应的 `code` 字段
+#[derive(Debug, Clone, PartialEq)]
+pub enum JwtError {
+    Expired,
+    NotYetValid,
+    InvalidAudience,
+    InvalidIssuer,
+    Invalid(String),
+    /// JWKS 从未成功获取，或其中没有 token 使用的密钥
+    KeyUnavailable(String),
+}
+
+impl JwtError {
+    /// JWKS 不可用是服务端的问题，返回 503 而不是 401
+    pub fn status(&self) -> axum::http::StatusCode {
+        match self {
+            JwtError::KeyUnavailable(_) => axum::http::StatusCode::SERVICE_UNAVAILABLE,
+            _ => axum::http::StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            JwtError::Expired => "jwt_expired",
+            JwtError::NotYetValid => "jwt_not_yet_valid",
+            JwtError::InvalidAudience => "jwt_invalid_audience",
+            JwtError::InvalidIssuer => "jwt_invalid_issuer",
+            JwtError::Invalid(_) => "jwt_invalid",
+            JwtError::KeyUnavailable(_) => "jwt_key_unavailable",
+        }
+    }
+}
+
+impl std::fmt::Display for JwtError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JwtError::Expired => f.write_str("JWT 已过期"),
+            JwtError::NotYetValid => f.write_str("JWT 尚未生效"),
+            JwtError::InvalidAudience => f.write_str("JWT 的 aud 不在允许的范围内"),
+            JwtError::InvalidIssuer => f.write_str("JWT 的 iss 不在允许的范围内"),
+            JwtError::Invalid(reason) => write!(f, "JWT 无效: {}", reason),
+            JwtError::KeyUnavailable(reason) => write!(f, "无法获取 JWT 验证密钥: {}", reason),
+        }
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for JwtError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        match e.kind() {
+            ErrorKind::ExpiredSignature => JwtError::Expired,
+            ErrorKind::ImmatureSignature => JwtError::NotYetValid,
+            ErrorKind::InvalidAudience => JwtError::InvalidAudience,
+            ErrorKind::InvalidIssuer => JwtError::InvalidIssuer,
+            _ => JwtError::Invalid(e.to_string()),
+        }
+    }
+}
+
+/// 形如 `header.payload.signature` 的 token 才尝试按 JWT 验证
+pub fn looks_like_jwt(token: &str) -> bool {
+    token.split('.').count() == 3 && !token.contains(char::is_whitespace)
+}
+
+/// 按 `auth.jwt` 验证 token
+#[derive(Clone)]
+pub struct JwtVerifier {
+    key: KeySource,
+    issuers: Vec<String>,
+    audiences: Vec<String>,
+    leeway_secs: u64,
+}
+
+#[derive(Clone)]
+enum KeySource {
+    Secret(DecodingKey),
+    Jwks(Arc<Jwks>),
+}
+
+impl JwtVerifier {
+    /// JWKS 在第一次验证时获取，之后按 `jwks_refresh_secs` 刷新
+    pub fn new(config: &JwtConfig, client: reqwest::Client) -> Self {
+        let key = match (&config.secret, &config.jwks_url) {
+            (Some(secret), _) => KeySource::Secret(DecodingKey::from_secret(secret.as_bytes())),
+            (None, url) => KeySource::Jwks(Arc::new(Jwks::new(
+                url.clone().unwrap_or_default(),
+                client,
+                Duration::from_secs(config.jwks_refresh_secs),
+            ))),
+        };
+        JwtVerifier {
+            key,
+            issuers: config.issuers.clone(),
+            audiences: config.audiences.clone(),
+            leeway_secs: config.leeway_secs,
+        }
+    }
+
+    fn validation(&self, algorithms: &[Algorithm]) -> Validation {
+        let mut validation = Validation::new(algorithms[0]);
+        validation.algorithms = algorithms.to_vec();
+        validation.leeway = self.leeway_secs;
+        validation.validate_nbf = true;
+        if self.audiences.is_empty() {
+            validation.validate_aud = false;
+        } else {
+            validation.set_audience(&self.audiences);
+        }
+        if !self.issuers.is_empty() {
+            validation.set_issuer(&self.issuers);
+        }
+        validation
+    }
+
+    /// `Authorization` 头部不是 `Bearer <JWT>` 时返回 None
+    pub async fn verify_bearer(
+        &self,
+        authorization_header: &str,
+    ) -> Option<Result<JwtClaims, JwtError>> {
+        let token = bearer_token(authorization_header).filter(|token| looks_like_jwt(token))?;
+        Some(self.verify(token).await)
+    }
+
+    pub async fn verify(&self, token: &str) -> Result<JwtClaims, JwtError> {
+        let data = match &self.key {
+            KeySource::Secret(key) => {
+                jsonwebtoken::decode(token, key, &self.validation(&HMAC_ALGORITHMS))?
+            }
+            KeySource::Jwks(jwks) => {
+                let header = jsonwebtoken::decode_header(token)?;
+                let key = jwks.key(header.kid.as_deref()).await?;
+                jsonwebtoken::decode(token, &key, &self.validation(&[header.alg]))?
+            }
+        };
+        Ok(data.claims)
+    }
+}
+
+/// 缓存的 JWKS，获取失败时继续使用上一次成功获取的密钥
+struct Jwks {
+    url: String,
+    client: reqwest::Client,
+    refresh: Duration,
+    cache: RwLock<JwksCache>,
+}
+
+#[derive(Default)]
+struct JwksCache {
+    keys: Option<JwkSet>,
+    fetched_at: Option<Instant>,
+    failed_at: Option<Instant>,
+}
+
+impl JwksCache {
+    fn needs_refresh(&self, refresh: Duration, kid_missing: bool) -> bool {
+        if self
+            .failed_at
+            .is_some_and(|at| at.elapsed() < JWKS_RETRY_INTERVAL)
+        {
+            return false;
+        }
+        // 找不到 token 使用的密钥时可能是签发方已轮换密钥，提前刷新
+        kid_missing || self.fetched_at.is_none_or(|at| at.elapsed() >= refresh)
+    }
+}
+
+impl Jwks {
+    fn new(url: String, client: reqwest::Client, refresh: Duration) -> Self {
+        Jwks {
+            url,
+            client,
+            refresh,
+            cache: RwLock::new(JwksCache::default()),
+        }
+    }
+
+    /// 查找 `kid` 对应的密钥，token 未指定 `kid` 时使用 JWKS 中唯一的密钥
+    async fn key(&self, kid: Option<&str>) -> Result<DecodingKey, JwtError> {
+        {
+            let cache = self.cache.read().await;
+            let found = find_key(cache.keys.as_ref(), kid);
+            if !cache.needs_refresh(self.refresh, found.is_none()) {
+                return found.ok_or_else(|| missing_key(cache.keys.is_some(), kid))?;
+            }
+        }
+
+        let mut cache = self.cache.write().await;
+        // 等待写锁期间其他请求可能已经刷新过
+        if cache.needs_refresh(self.refresh, find_key(cache.keys.as_ref(), kid).is_none()) {
+            match self.fetch().await {
+                Ok(keys) => {
+                    debug!("已获取 JWKS: {}（{} 个密钥）", self.url, keys.keys.len());
+                    cache.keys = Some(keys);
+                    cache.fetched_at = Some(Instant::now());
+                    cache.failed_at = None;
+                }
+                Err(e) => {
+                    warn!("获取 JWKS 失败，继续使用缓存的密钥: {}: {}", self.url, e);
+                    cache.failed_at = Some(Instant::now());
+                }
+            }
+        }
+        find_key(cache.keys.as_ref(), kid).ok_or_else(|| missing_key(cache.keys.is_some(), kid))?
+    }
+
+    async fn fetch(&self) -> Result<JwkSet, String> {
+        let response = self
+            .client
+            .get(&self.url)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("HTTP {}", response.status()));
+        }
+        let body = response.bytes().await.map_err(|e| e.to_string())?;
+        serde_json::from_slice(&body).map_err(|e| e.to_string())
+    }
+}
+
+fn find_key(keys: Option<&JwkSet>, kid: Option<&str>) -> Option<Result<DecodingKey, JwtError>> {
+    let keys = keys?;
+    let jwk = match kid {
+        Some(kid) => keys.find(kid)?,
+        None if keys.keys.len() == 1 => &keys.keys[0],
+        None => return None,
+    };
+    Some(DecodingKey::from_jwk(jwk).map_err(JwtError::from))
+}
+
+fn missing_key(fetched: bool, kid: Option<&str>) -> JwtError {
+    if !fetched {
+        return JwtError::KeyUnavailable("JWKS 尚未成功获取".to_string());
+    }
+    match kid {
+        Some(kid) => JwtError::Invalid(format!("JWKS 中没有 kid 为 {:?} 的密钥", kid)),
+        None => JwtError::Invalid("token 未指定 kid，且 JWKS 中不止一个密钥".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{EncodingKey, Header};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn sign(header: Header, claims: serde_json::Value, secret: &str) -> String {
+        jsonwebtoken::encode(
+            &header,
+            &claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_verify_secret() {
+        let verifier = JwtVerifier::new(
+            &JwtConfig {
+                secret: Some("jwt-secret".to_string()),
+                issuers: vec!["https://issuer.example".to_string()],
+                audiences: vec!["proxy".to_string()],
+                ..JwtConfig::default()
+            },
+            reqwest::Client::new(),
+        );
+        let claims = serde_json::json!({
+            "sub": "alice",
+            "iss": "https://issuer.example",
+            "aud": "proxy",
+            "exp": now() + 600,
+            "allowed_hosts": ["api.example.com", "*.example.org"],
+        });
+
+        let token = sign(Header::default(), claims.clone(), "jwt-secret");
+        let verified = verifier
+            .verify_bearer(&format!("Bearer {}", token))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(verified.sub.as_deref(), Some("alice"));
+        assert!(verified.allows_host("API.example.com"));
+        assert!(verified.allows_host("a.example.org"));
+        assert!(!verified.allows_host("example.net"));
+
+        // 不像 JWT 的 token 交给其他认证方式
+        assert!(verifier
+            .verify_bearer("Bearer static-token")
+            .await
+            .is_none());
+        assert!(verifier.verify_bearer(&token).await.is_none());
+
+        let expired = sign(
+            Header::default(),
+            serde_json::json!({"iss": "https://issuer.example", "aud": "proxy", "exp": now() - 600}),
+            "jwt-secret",
+        );
+        assert_eq!(verifier.verify(&expired).await, Err(JwtError::Expired));
+        assert_eq!(JwtError::Expired.code(), "jwt_expired");
+
+        let mut other = claims.clone();
+        other["aud"] = "other".into();
+        let token = sign(Header::default(), other, "jwt-secret");
+        assert_eq!(
+            verifier.verify(&token).await,
+            Err(JwtError::InvalidAudience)
+        );
+
+        let mut other = claims.clone();
+        other["iss"] = "https://evil.example".into();
+        let token = sign(Header::default(), other, "jwt-secret");
+        assert_eq!(verifier.verify(&token).await, Err(JwtError::InvalidIssuer));
+
+        let token = sign(Header::default(), claims, "wrong-secret");
+        let err = verifier.verify(&token).await.unwrap_err();
+        assert_eq!(err.code(), "jwt_invalid");
+        assert_eq!(err.status(), axum::http::StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_allows_target() {
+        assert!(allows_target("https://anything.example/"));
+        assert!(!restricts_hosts());
+
+        let claims = JwtClaims {
+            sub: None,
+            allowed_hosts: Some(vec!["api.example.com".to_string(), "::1".to_string()]),
+        };
+        scope(claims, async {
+            assert!(allows_target("https://api.example.com/v1"));
+            assert!(allows_target("http://[::1]:8080/"));
+            assert!(!allows_target("https://other.example.com/"));
+            assert!(!allows_target("unix:/run/app.sock:/health"));
+            assert!(restricts_hosts());
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_jwks() {
+        use axum::{routing::get, Router};
+
+        // "jwks-signing-key" 的 base64url 编码
+        let jwks = serde_json::json!({
+            "keys": [{"kty": "oct", "kid": "k1", "alg": "HS256", "k": "andrcy1zaWduaW5nLWtleQ"}]
+        });
+        let available = Arc::new(AtomicBool::new(true));
+        let app = Router::new().route(
+            "/jwks.json",
+            get({
+                let available = available.clone();
+                move || async move {
+                    if available.load(Ordering::SeqCst) {
+                        Ok(axum::Json(jwks))
+                    } else {
+                        Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR)
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let mut verifier = JwtVerifier::new(
+            &JwtConfig {
+                jwks_url: Some(format!("http://{}/jwks.json", addr)),
+                ..JwtConfig::default()
+            },
+            reqwest::Client::new(),
+        );
+        // 每次验证都尝试刷新，以检查获取失败时使用缓存的密钥
+        let jwks = Arc::new(Jwks::new(
+            format!("http://{}/jwks.json", addr),
+            reqwest::Client::new(),
+            Duration::ZERO,
+        ));
+        verifier.key = KeySource::Jwks(jwks.clone());
+
+        let mut header = Header {
+            kid: Some("k1".to_string()),
+            ..Header::default()
+        };
+        let token = sign(
+            header.clone(),
+            serde_json::json!({"sub": "svc", "exp": now() + 600}),
+            "jwks-signing-key",
+        );
+        assert_eq!(
+            verifier.verify(&token).await.unwrap().sub.as_deref(),
+            Some("svc")
+        );
+
+        available.store(false, Ordering::SeqCst);
+        assert!(verifier.verify(&token).await.is_ok());
+        assert!(jwks.cache.read().await.failed_at.is_some());
+
+        header.kid = Some("k2".to_string());
+        let token = sign(
+            header,
+            serde_json::json!({"exp": now() + 600}),
+            "jwks-signing-key",
+        );
+        assert_eq!(
+            verifier.verify(&token).await.unwrap_err().code(),
+            "jwt_invalid"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_jwks_unavailable() {
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = closed.local_addr().unwrap();
+        drop(closed);
+
+        let verifier = JwtVerifier::new(
+            &JwtConfig {
+                jwks_url: Some(format!("http://{}/jwks.json", addr)),
+                ..JwtConfig::default()
+            },
+            reqwest::Client::new(),
+        );
+        let header = Header {
+            kid: Some("k1".to_string()),
+            ..Header::default()
+        };
+        let token = sign(header, serde_json::json!({"exp": now() + 600}), "secret");
+        let err = verifier.verify(&token).await.unwrap_err();
+        assert_eq!(err.code(), "jwt_key_unavailable");
+        assert_eq!(err.status(), axum::http::StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,862 @@
+//! 远程 HTTP 代理：把 `/proxy` 等接口构造成 axum 路由，可以独立运行，也可以挂载到其他 axum 服务中
+//!
+//! ```no_run
+//! use remote_http_agent::{config::Config, proxy_router};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let config = Config::default();
+//! let client = remote_http_agent::build_client(&config)?;
+//! let app = axum::Router::new().nest("/agent", proxy_router(config, client)?);
+//! # let _: axum::Router = app;
+//! # Ok(())
+//! # }
+//! ```
+
+mod access_log;
+mod aliases;
+pub mod audit;
+pub mod auth;
+mod background;
+mod batch;
+mod cache;
+mod capture;
+mod client_filter;
+mod compression;
+pub mod config;
+pub mod config_file;
+mod cookies;
+mod dns;
+mod headers;
+mod history;
+mod hosts;
+#[cfg(feature = "http3")]
+mod http3;
+mod ip;
+mod jwt;
+mod log_file;
+mod metrics;
+mod notify;
+mod outbound;
+mod proxy;
+mod request_body;
+mod rewrite;
+pub mod server;
+mod signed_url;
+mod stream;
+pub mod telemetry;
+mod tunnel;
+mod ui;
+mod unix;
+mod upstream_groups;
+mod url_rewrite;
+
+use anyhow::Result;
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::Response,
+    routing::{any, get, post},
+    Router,
+};
+use config::Config;
+use headers::check_header_limits;
+use proxy::{add_cache_control_headers, add_cors_headers, AppState};
+use reqwest::Client;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::Instrument;
+
+pub use proxy::{build_client, verify_upstream_proxy};
+
+pub(crate) struct AppConfig {
+    pub state: Arc<AppState>,
+    pub token: String,
+}
+
+impl AppConfig {
+    /// 按 `trust_proxy_header` 规则确定的客户端地址，没有对端地址（监听 Unix socket、集成方未提供
+    /// `ConnectInfo`）时为 None
+    pub(crate) fn client_ip(&self, request: &Request) -> Option<std::net::IpAddr> {
+        let ConnectInfo(peer) = request.extensions().get::<ConnectInfo<SocketAddr>>()?;
+        Some(
+            self.state
+                .client_filter
+                .client_ip(peer.ip(), request.headers()),
+        )
+    }
+}
+
+async fn app_middleware(
+    State(config): State<Arc<AppConfig>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let request_headers = request.headers().clone();
+    let method = request.method().clone();
+
+    let mut cors_headers = HeaderMap::new();
+    let cors_allowed = add_cors_headers(
+        &mut cors_headers,
+        &request_headers,
+        &config.state.config.cors,
+        &config.state.config.tun_rename_response_headers,
+    );
+
+    // 请求头过多或过大时在认证与转发之前拒绝
+    let server = &config.state.config.server;
+    if let Err(message) = check_header_limits(
+        &request_headers,
+        server.max_header_count as usize,
+        server.max_header_bytes as usize,
+    ) {
+        return json_error_response(
+            &config,
+            StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            &message,
+            None,
+            &cors_headers,
+        );
+    }
+
+    // OPTIONS 直接返回，不做认证（与 Go 版本一致）；来源不被允许时返回不带 CORS 头部的 200，由浏览器拦截
+    if method == Method::OPTIONS {
+        let mut resp = Response::new(Body::empty());
+        *resp.status_mut() = if cors_allowed {
+            StatusCode::NO_CONTENT
+        } else {
+            StatusCode::OK
+        };
+        *resp.headers_mut() = cors_headers;
+        config
+            .state
+            .response_header_overrides
+            .apply_to_response(resp.headers_mut());
+        return resp;
+    }
+
+    // 关闭覆盖或单次请求要求保留时，上游自身的缓存头部原样返回
+    let settings = &config.state.config;
+    if settings.override_cache_headers && !is_preserve_cache(&request_headers) {
+        add_cache_control_headers(&mut cors_headers, &settings.cache_control_value);
+    }
+
+    let auth_header = request_headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+
+    let mut claims = None;
+    let mut signed_url = None;
+    let mut anonymous = false;
+    if !auth::valid_bearer(auth_header, &config.token)
+        && !config.state.token_hashes.verify_bearer(auth_header).await
+    {
+        let verified = match &config.state.jwt {
+            Some(verifier) => verifier.verify_bearer(auth_header).await,
+            None => None,
+        };
+        // 没有可用的 Authorization 时再检查 `/proxy` 的签名地址
+        let rejected = match verified {
+            Some(Ok(verified)) => {
+                claims = Some(verified);
+                None
+            }
+            Some(Err(e)) => {
+                let credential = auth::bearer_token(auth_header).unwrap_or(auth_header);
+                audit::auth_failure(&config, &request, "jwt", credential, &e.to_string());
+                Some((e.status(), format!("未认证: {}", e), Some(e.code())))
+            }
+            None => match signed_url::verify_request(&config, &request) {
+                Some(Ok(signed)) => {
+                    signed_url = Some(signed);
+                    None
+                }
+                Some(Err(e)) => {
+                    let sig = signed_url::request_signature(&request).unwrap_or_default();
+                    audit::auth_failure(&config, &request, "signature", &sig, &e.to_string());
+                    Some((
+                        StatusCode::UNAUTHORIZED,
+                        format!("未认证: {}", e),
+                        Some(e.code()),
+                    ))
+                }
+                // 目标在 public_hosts 中时匿名放行
+                None if config.state.is_public_request(request.uri()) => {
+                    anonymous = true;
+                    None
+                }
+                None => {
+                    let credential = auth::bearer_token(auth_header).unwrap_or(auth_header);
+                    audit::auth_failure(&config, &request, "bearer", credential, "bearer 认证失败");
+                    Some((
+                        StatusCode::UNAUTHORIZED,
+                        "未认证，请更新App: bearer 认证失败".to_string(),
+                        None,
+                    ))
+                }
+            },
+        };
+        if let Some((status, message, code)) = rejected {
+            return json_error_response(&config, status, &message, code, &cors_headers);
+        }
+    }
+    let mut request = request;
+    if let Some(signed_url) = signed_url {
+        request.extensions_mut().insert(signed_url);
+    }
+
+    // Content-Length 超出上限时不读取请求体直接拒绝；没有 Content-Length（分块上传）时在接收过程中检查
+    let request = match settings.max_body_bytes {
+        Some(limit) => {
+            let content_length = request_headers
+                .get("content-length")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.trim().parse::<u64>().ok());
+            if content_length.is_some_and(|length| length > limit) {
+                return json_error_response(
+                    &config,
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    &format!("请求体超过 max_body_bytes（{} 字节）", limit),
+                    None,
+                    &cors_headers,
+                );
+            }
+            let limit = usize::try_from(limit).unwrap_or(usize::MAX);
+            request.map(|body| Body::new(http_body_util::Limited::new(body, limit)))
+        }
+        None => request,
+    };
+
+    let mut resp = match claims {
+        // JWT 的 sub 写入该请求的日志，allowed_hosts 在确定目标地址时检查
+        Some(claims) => {
+            let span = tracing::info_span!("jwt", sub = claims.sub.as_deref().unwrap_or(""));
+            jwt::scope(claims, next.run(request)).instrument(span).await
+        }
+        // 匿名请求在日志中标记 auth=anonymous，最终的目标地址在确定时再次检查
+        None if anonymous => {
+            let span = tracing::info_span!("request", auth = "anonymous");
+            auth::anonymous(next.run(request)).instrument(span).await
+        }
+        None => next.run(request).await,
+    };
+    for (k, v) in cors_headers.iter() {
+        resp.headers_mut().insert(k, v.clone());
+    }
+    // 配置的覆盖规则最后应用，优先于上游、CORS 与缓存头部
+    config
+        .state
+        .response_header_overrides
+        .apply_to_response(resp.headers_mut());
+    resp
+}
+
+/// 按客户端 IP 过滤（`client_allow_cidrs`、`client_deny_cidrs`），在 CONNECT、认证与读取请求体之前
+/// 拒绝；服务未提供 `ConnectInfo`（如集成方未使用 `into_make_service_with_connect_info`）时同样拒绝
+async fn client_filter_middleware(
+    State(config): State<Arc<AppConfig>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let filter = &config.state.client_filter;
+    let allowed = match request.extensions().get::<ConnectInfo<SocketAddr>>() {
+        Some(ConnectInfo(peer)) => {
+            let ip = filter.client_ip(peer.ip(), request.headers());
+            let allowed = filter.allows(ip);
+            if !allowed {
+                tracing::debug!("拒绝客户端 {}（对端 {}）", ip, peer);
+            }
+            allowed
+        }
+        None => {
+            tracing::debug!("拒绝没有对端地址的请求");
+            false
+        }
+    };
+    if !allowed {
+        return json_error_response(
+            &config,
+            StatusCode::FORBIDDEN,
+            "客户端地址不允许访问",
+            Some("client_ip_denied"),
+            &HeaderMap::new(),
+        );
+    }
+    next.run(request).await
+}
+
+/// 中间件直接拒绝请求时的 JSON 错误响应，带上 CORS 头部并应用响应头覆盖规则；
+/// `code` 为便于客户端区分的错误代码
+fn json_error_response(
+    config: &AppConfig,
+    status: StatusCode,
+    message: &str,
+    code: Option<&str>,
+    cors_headers: &HeaderMap,
+) -> Response {
+    let body = match code {
+        Some(code) => serde_json::json!({ "error": message, "code": code }),
+        None => serde_json::json!({ "error": message }),
+    }
+    .to_string();
+    let mut resp = Response::new(Body::from(body));
+    *resp.status_mut() = status;
+    resp.headers_mut().insert(
+        "content-type",
+        HeaderValue::from_static("application/json; charset=utf-8"),
+    );
+    for (k, v) in cors_headers.iter() {
+        resp.headers_mut().insert(k, v.clone());
+    }
+    config
+        .state
+        .response_header_overrides
+        .apply_to_response(resp.headers_mut());
+    resp
+}
+
+fn is_preserve_cache(headers: &HeaderMap) -> bool {
+    headers
+        .get("tun-preserve-cache")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+}
+
+/// `GET /status`：上游组各后端的健康状态
+async fn status_handler(State(config): State<Arc<AppConfig>>) -> impl axum::response::IntoResponse {
+    axum::Json(serde_json::json!({
+        "code": 0,
+        "msg": "success",
+        "upstream_groups": config.state.upstream_groups.status(),
+    }))
+}
+
+async fn kill_handler(
+    State(config): State<Arc<AppConfig>>,
+    request: Request,
+) -> impl axum::response::IntoResponse {
+    audit::admin_action(
+        &config.state.config.audit,
+        "kill",
+        config.client_ip(&request),
+        "",
+    );
+    tokio::spawn(async move {
+        tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+        cleanup_before_exit(&config.state.config);
+        std::process::exit(0);
+    });
+    axum::Json(serde_json::json!({"code": 0, "msg": "程序即将退出"}))
+}
+
+/// 退出前删除监听的 Unix socket 文件，并导出剩余的 span
+pub fn cleanup_before_exit(config: &Config) {
+    if let Some(path) = config.listening_unix_path() {
+        let _ = std::fs::remove_file(path);
+    }
+    telemetry::shutdown();
+}
+
+/// 与独立运行时相同的完整路由：代理接口、`/lanip`、`/kill`、`/sign`、`/status`、`/admin/requests`、反向代理、
+/// 控制台页面，并按配置加上路径前缀与 CONNECT 隧道；上游客户端按配置创建
+pub fn build_router(config: Config) -> Result<Router> {
+    let client = build_client(&config)?;
+    build_router_with_client(config, client)
+}
+
+/// 同 [`build_router`]，使用调用方提供的上游客户端
+pub fn build_router_with_client(config: Config, client: Client) -> Result<Router> {
+    let app_config = app_config(&config, client)?;
+
+    let mut routes = api_routes()
+        .route("/lanip", get(ip::get_lan_ip_handler))
+        .route("/kill", get(kill_handler))
+        .route("/sign", post(signed_url::sign_handler))
+        .route("/status", get(status_handler))
+        .route(
+            "/admin/requests",
+            get(history::list_requests_handler).delete(history::clear_requests_handler),
+        );
+    // 反向代理前缀在配置校验时已保证互不重叠，也不与内置接口冲突
+    for rule in &config.reverse_proxies {
+        routes = routes
+            .route(&rule.path, any(proxy::reverse_proxy_handler))
+            .route(
+                &format!("{}*rest", rule.path),
+                any(proxy::reverse_proxy_handler),
+            );
+    }
+    let mut app = with_middleware(routes, app_config.clone());
+
+    if config.ui.enabled {
+        app = app.merge(ui::router());
+    }
+
+    let base_path = config.normalized_base_path();
+    if !base_path.is_empty() {
+        app = Router::new().nest(&base_path, app);
+    }
+
+    // CONNECT 的目标写在请求行中，不经过路由与路径前缀，在最外层处理
+    if config.allow_connect {
+        app = app.layer(axum::middleware::from_fn_with_state(
+            app_config.clone(),
+            tunnel::connect_middleware,
+        ));
+    }
+
+    Ok(with_outer_layers(app, app_config))
+}
+
+/// 只包含代理接口（`/proxy`、`/proxy/batch`、`/proxy/<编码后的目标地址>`）的路由，
+/// 带认证、CORS 与响应头规则，供集成方挂载到自己的服务中
+///
+/// 不包含 `/kill` 等管理接口，也不处理 `base_path` 与 CONNECT 隧道，挂载位置由调用方决定；配置了
+/// `client_allow_cidrs` 或 `client_deny_cidrs` 时需以 `into_make_service_with_connect_info::<SocketAddr>()`
+/// 提供服务，否则所有请求都会被拒绝
+pub fn proxy_router(config: Config, client: Client) -> Result<Router> {
+    let app_config = app_config(&config, client)?;
+    Ok(with_outer_layers(
+        with_middleware(api_routes(), app_config.clone()),
+        app_config,
+    ))
+}
+
+fn app_config(config: &Config, client: Client) -> Result<Arc<AppConfig>> {
+    let outbound_pool = outbound::OutboundPool::new(config)?;
+    let access_log = config
+        .log
+        .access_log_file_path()
+        .map(|path| {
+            access_log::AccessLog::open(path, config.log.rotate.clone()).map_err(|e| {
+                anyhow::anyhow!(
+                    "log.access_log_file: cannot open {:?} for writing: {}",
+                    path,
+                    e
+                )
+            })
+        })
+        .transpose()?;
+    Ok(Arc::new(AppConfig {
+        state: Arc::new(
+            AppState::new(client, config)
+                .with_outbound_pool(outbound_pool)
+                .with_access_log(access_log),
+        ),
+        token: config.token.clone(),
+    }))
+}
+
+fn api_routes() -> Router<Arc<AppConfig>> {
+    Router::new()
+        .route("/proxy", any(proxy::proxy_request_handler))
+        .route("/proxy/batch", post(batch::batch_handler))
+        .route("/proxy/*target", any(proxy::proxy_path_handler))
+}
+
+/// 最外层依次为指标、访问日志与客户端 IP 规则，被拒绝的请求也会统计与记录
+fn with_outer_layers(app: Router, app_config: Arc<AppConfig>) -> Router {
+    let app = with_client_filter(app, app_config.clone());
+    let app = with_access_log(app, app_config.clone());
+    with_metrics(app, app_config)
+}
+
+/// 配置了客户端 IP 规则时检查
+fn with_client_filter(app: Router, app_config: Arc<AppConfig>) -> Router {
+    if !app_config.state.client_filter.is_enabled() {
+        return app;
+    }
+    app.layer(axum::middleware::from_fn_with_state(
+        app_config,
+        client_filter_middleware,
+    ))
+}
+
+/// 配置了访问日志时记录每个请求
+fn with_access_log(app: Router, app_config: Arc<AppConfig>) -> Router {
+    if app_config.state.access_log.is_none() {
+        return app;
+    }
+    app.layer(axum::middleware::from_fn_with_state(
+        app_config,
+        access_log::access_log_middleware,
+    ))
+}
+
+/// 配置了 `metrics.statsd` 时统计每个请求
+fn with_metrics(app: Router, app_config: Arc<AppConfig>) -> Router {
+    if app_config.state.statsd.is_none() {
+        return app;
+    }
+    app.layer(axum::middleware::from_fn_with_state(
+        app_config,
+        metrics::metrics_middleware,
+    ))
+}
+
+fn with_middleware(routes: Router<Arc<AppConfig>>, app_config: Arc<AppConfig>) -> Router {
+    routes
+        .layer(axum::middleware::from_fn_with_state(
+            app_config.clone(),
+            app_middleware,
+        ))
+        .with_state(app_config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 带认证/CORS 中间件的测试服务，`/cached` 返回自带缓存头部的响应，`/proxy` 为实际的代理接口，
+    /// `/sized` 与 `/chunked` 分别返回带 `Content-Length` 与分块传输的 1000 字节响应体
+    async fn spawn_app(config: Config) -> String {
+        let app_config = Arc::new(AppConfig {
+            state: Arc::new(AppState::new(reqwest::Client::new(), &config)),
+            token: "test-token".to_string(),
+        });
+        let app = Router::new()
+            .route(
+                "/cached",
+                get(|| async { ([("cache-control", "public, max-age=3600")], "asset") }),
+            )
+            .route("/sized", get(|| async { "x".repeat(1000) }))
+            .route(
+                "/chunked",
+                get(|| async {
+                    let chunks = (0..10).map(|_| Ok::<_, std::io::Error>("x".repeat(100)));
+                    axum::body::Body::from_stream(futures_util::stream::iter(chunks))
+                }),
+            )
+            .route("/proxy", any(proxy::proxy_request_handler))
+            .layer(axum::middleware::from_fn_with_state(
+                app_config.clone(),
+                app_middleware,
+            ))
+            .with_state(app_config);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        format!("http://{}/cached", addr)
+    }
+
+    #[tokio::test]
+    async fn test_public_router_api() {
+        use tower::ServiceExt;
+
+        let upstream = Router::new().route("/hello", get(|| async { "hello" }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, upstream).await.unwrap() });
+
+        let config = Config {
+            token: "embed-token".to_string(),
+            base_path: "/agent".to_string(),
+            ..Config::default()
+        };
+        let request = |uri: String, token: &str| {
+            Request::builder()
+                .uri(uri)
+                .header("authorization", format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let target = format!("http://{}/hello", addr);
+
+        // 完整路由沿用 base_path 与认证
+        let app = build_router(config.clone()).unwrap();
+        let response = app
+            .clone()
+            .oneshot(request(
+                format!("/agent/proxy?url={}", target),
+                "embed-token",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "hello");
+        let response = app
+            .oneshot(request(format!("/agent/proxy?url={}", target), "wrong"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // 只含代理接口的路由由调用方决定挂载位置，不包含管理接口
+        let app = Router::new()
+            .nest(
+                "/embedded",
+                proxy_router(config, reqwest::Client::new()).unwrap(),
+            )
+            .route("/health", get(|| async { "ok" }));
+        let response = app
+            .clone()
+            .oneshot(request(
+                format!("/embedded/proxy?url={}", target),
+                "embed-token",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app
+            .oneshot(request("/embedded/kill".to_string(), "embed-token"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    async fn cached_response(config: Config, preserve: bool) -> reqwest::Response {
+        let url = spawn_app(config).await;
+        let mut request = reqwest::Client::new().get(url).bearer_auth("test-token");
+        if preserve {
+            request = request.header("tun-preserve-cache", "true");
+        }
+        request.send().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_preflight_uses_cors_policy() {
+        let config = Config {
+            cors: config::CorsConfig {
+                allowed_origins: vec!["https://app.example.com".to_string()],
+                ..Default::default()
+            },
+            ..Config::default()
+        };
+        let url = spawn_app(config).await;
+        let client = reqwest::Client::new();
+
+        let response = client
+            .request(reqwest::Method::OPTIONS, &url)
+            .header("origin", "https://app.example.com")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 204);
+        assert_eq!(
+            response.headers()["access-control-allow-origin"],
+            "https://app.example.com"
+        );
+
+        let response = client
+            .request(reqwest::Method::OPTIONS, &url)
+            .header("origin", "https://evil.com")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(response.headers().get("access-control-allow-origin").is_none());
+        assert!(response.headers().get("access-control-allow-methods").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cache_headers_overridden_by_default() {
+        let response = cached_response(Config::default(), false).await;
+        assert_eq!(
+            response.headers()["cache-control"],
+            "no-store, no-cache, must-revalidate"
+        );
+        assert_eq!(response.headers()["expires"], "0");
+    }
+
+    #[tokio::test]
+    async fn test_cache_headers_custom_value() {
+        let config = Config {
+            cache_control_value: "no-cache".to_string(),
+            ..Config::default()
+        };
+        let response = cached_response(config, false).await;
+        assert_eq!(response.headers()["cache-control"], "no-cache");
+    }
+
+    #[tokio::test]
+    async fn test_cache_headers_preserved() {
+        let config = Config {
+            override_cache_headers: false,
+            ..Config::default()
+        };
+        let response = cached_response(config, false).await;
+        assert_eq!(response.headers()["cache-control"], "public, max-age=3600");
+        assert!(response.headers().get("expires").is_none());
+
+        let response = cached_response(Config::default(), true).await;
+        assert_eq!(response.headers()["cache-control"], "public, max-age=3600");
+    }
+
+    #[tokio::test]
+    async fn test_response_header_overrides() {
+        let config = Config {
+            response_header_overrides: std::collections::HashMap::from([
+                ("Cache-Control".to_string(), "private".to_string()),
+                ("+X-Served-By".to_string(), "agent".to_string()),
+                ("+Expires".to_string(), "1".to_string()),
+            ]),
+            ..Config::default()
+        };
+        let url = spawn_app(config).await;
+        let client = reqwest::Client::new();
+
+        let response = client.get(&url).bearer_auth("test-token").send().await.unwrap();
+        assert_eq!(response.headers()["cache-control"], "private");
+        assert_eq!(response.headers()["x-served-by"], "agent");
+        assert_eq!(response.headers()["expires"], "0");
+
+        // 未认证的响应同样应用
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), 401);
+        assert_eq!(response.headers()["x-served-by"], "agent");
+    }
+
+    #[tokio::test]
+    async fn test_header_limits() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let upstream = Router::new().route(
+            "/",
+            get(move || async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                "upstream"
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, upstream).await });
+
+        let config = Config {
+            server: config::ServerConfig {
+                max_header_count: 20,
+                ..Default::default()
+            },
+            ..Config::default()
+        };
+        let url = spawn_app(config).await.replace("/cached", "/proxy");
+        let request = |count: usize| {
+            let mut request = reqwest::Client::new()
+                .get(&url)
+                .query(&[("url", &target)])
+                .bearer_auth("test-token");
+            for i in 0..count {
+                request = request.header(format!("x-extra-{}", i), "1");
+            }
+            request
+        };
+
+        let response = request(5).send().await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "upstream");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // 超出上限的请求在转发前被拒绝
+        let response = request(30).send().await.unwrap();
+        assert_eq!(response.status(), 431);
+        assert!(response.text().await.unwrap().contains("请求头数量"));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_missing_url_returns_bad_request() {
+        let url = spawn_app(Config::default()).await.replace("/cached", "/proxy");
+        let response = reqwest::Client::new()
+            .get(url)
+            .bearer_auth("test-token")
+            .header("origin", "https://app.example.com")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 400);
+        assert_eq!(
+            response.headers()["access-control-allow-origin"],
+            "https://app.example.com"
+        );
+        assert_eq!(response.text().await.unwrap(), "缺少 url 参数");
+    }
+
+    #[tokio::test]
+    async fn test_content_length_passthrough() {
+        let cached = spawn_app(Config::default()).await;
+        let proxy = |path: &str| {
+            format!(
+                "{}?url={}",
+                cached.replace("/cached", "/proxy"),
+                urlencoding::encode(&cached.replace("/cached", path))
+            )
+        };
+        // 上游路由同样要求认证，令牌经 tun- 头部转发
+        let send = |method: reqwest::Method, path: &str| {
+            reqwest::Client::new()
+                .request(method, proxy(path))
+                .bearer_auth("test-token")
+                .header("tun-authorization", "Bearer test-token")
+                .send()
+        };
+
+        // 上游给出长度时原样转发，客户端可以显示下载进度
+        let response = send(reqwest::Method::GET, "/sized").await.unwrap();
+        assert_eq!(response.headers()["content-length"], "1000");
+        assert!(response.headers().get("transfer-encoding").is_none());
+        assert_eq!(response.bytes().await.unwrap().len(), 1000);
+
+        // HEAD 不带响应体，但保留上游的 Content-Length
+        let response = send(reqwest::Method::HEAD, "/sized").await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-length"], "1000");
+        assert!(response.bytes().await.unwrap().is_empty());
+
+        let response = send(reqwest::Method::GET, "/chunked").await.unwrap();
+        assert_eq!(response.headers()["transfer-encoding"], "chunked");
+        assert!(response.headers().get("content-length").is_none());
+        assert_eq!(response.bytes().await.unwrap().len(), 1000);
+    }
+
+    #[tokio::test]
+    async fn test_total_time_trailer() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let cached = spawn_app(Config::default()).await;
+        let addr = cached
+            .trim_start_matches("http://")
+            .trim_end_matches("/cached")
+            .to_string();
+        // reqwest 不提供 trailer，直接读取原始 HTTP/1.1 响应
+        let raw_response = |te: &'static str| {
+            let addr = addr.clone();
+            async move {
+                let mut stream = tokio::net::TcpStream::connect(&addr).await.unwrap();
+                let request = format!(
+                    "GET /proxy?url={} HTTP/1.1\r\nHost: {}\r\nAuthorization: Bearer test-token\r\n\
+                     tun-authorization: Bearer test-token\r\n{}Connection: close\r\n\r\n",
+                    urlencoding::encode(&format!("http://{}/sized", addr)),
+                    addr,
+                    te
+                );
+                stream.write_all(request.as_bytes()).await.unwrap();
+                let mut response = Vec::new();
+                stream.read_to_end(&mut response).await.unwrap();
+                String::from_utf8_lossy(&response).to_lowercase()
+            }
+        };
+
+        let response = raw_response("TE: trailers\r\n").await;
+        assert!(response.contains("transfer-encoding: chunked"), "{}", response);
+        assert!(response.contains("trailer: tun-total-time-ms"), "{}", response);
+        let total = response
+            .split("\r\ntun-total-time-ms: ")
+            .nth(1)
+            .and_then(|rest| rest.split("\r\n").next())
+            .unwrap();
+        assert!(total.parse::<u64>().is_ok(), "{}", total);
+
+        // 未声明 TE: trailers 时保留 Content-Length，不发送 trailer
+        let response = raw_response("").await;
+        assert!(response.contains("content-length: 1000"), "{}", response);
+        assert!(!response.contains("tun-total-time-ms"), "{}", response);
+    }
+}
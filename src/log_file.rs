@@ -0,0 +1,254 @@
+use crate::config::LogRotateConfig;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 每次请求重新打开时递增，各日志文件在下一次写入前发现变化后重新打开
+static REOPEN_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// 让所有日志文件在下一次写入前按路径重新打开（收到 SIGHUP 时调用），配合 logrotate 等外部工具
+/// 移走文件后使用
+pub fn request_reopen() {
+    REOPEN_GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+/// 按大小或日期轮转的日志文件，旧文件依次命名为 `<文件名>.1`（最新）、`<文件名>.2` ……
+///
+/// 只由日志后台线程写入，每次 `write` 写入完整的一行，轮转只发生在两行之间
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    day: u64,
+    rotate: LogRotateConfig,
+    /// 打开文件时的 `REOPEN_GENERATION`
+    generation: u64,
+}
+
+impl RotatingFile {
+    /// 打开（必要时创建）日志文件及其所在目录，无法写入时返回错误
+    pub fn open(path: impl Into<PathBuf>, rotate: LogRotateConfig) -> io::Result<Self> {
+        let path = path.into();
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let generation = REOPEN_GENERATION.load(Ordering::Relaxed);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let metadata = file.metadata()?;
+        // 按文件的修改日期计算，重启后不会把前一天的文件当作当天的继续写入
+        let day = metadata.modified().map(day_of).unwrap_or_else(|_| today());
+        Ok(Self {
+            path,
+            file,
+            size: metadata.len(),
+            day,
+            rotate,
+            generation,
+        })
+    }
+
+    /// 按路径重新打开，文件已被移走时创建新文件
+    fn reopen(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = self.file.metadata()?.len();
+        Ok(())
+    }
+
+    fn should_rotate(&self, incoming: usize) -> bool {
+        let oversized = self.rotate.max_bytes > 0
+            && self.size > 0
+            && self.size + incoming as u64 > self.rotate.max_bytes;
+        oversized || (self.rotate.daily && today() != self.day)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let max_files = self.rotate.max_files;
+        if max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            match fs::remove_file(self.rotated_path(max_files)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            for n in (1..max_files).rev() {
+                let from = self.rotated_path(n);
+                if from.exists() {
+                    fs::rename(from, self.rotated_path(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        self.day = today();
+        Ok(())
+    }
+
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut path = OsString::from(self.path.as_os_str());
+        path.push(format!(".{}", n));
+        path.into()
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let generation = REOPEN_GENERATION.load(Ordering::Relaxed);
+        if generation != self.generation {
+            self.generation = generation;
+            if let Err(e) = self.reopen() {
+                eprintln!("重新打开日志文件失败: {}: {}", self.path.display(), e);
+            }
+        }
+        if self.should_rotate(buf.len()) {
+            if let Err(e) = self.rotate() {
+                // 继续写入当前文件，到下一次达到条件时再尝试
+                eprintln!("日志文件轮转失败: {}: {}", self.path.display(), e);
+                self.size = 0;
+                self.day = today();
+            }
+        }
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// 自 UNIX 纪元起的天数（UTC）
+fn day_of(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / 86_400)
+        .unwrap_or_default()
+}
+
+fn today() -> u64 {
+    day_of(SystemTime::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rha-log-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn read_lines(path: PathBuf) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_rotate_by_size_keeps_all_lines() {
+        let dir = temp_dir("size");
+        let path = dir.join("logs").join("agent.log");
+        let mut file = RotatingFile::open(
+            &path,
+            LogRotateConfig {
+                max_bytes: 100,
+                daily: false,
+                max_files: 3,
+            },
+        )
+        .unwrap();
+        // 每行 10 字节，每个文件恰好 10 行
+        for i in 0..40 {
+            file.write_all(format!("line-{:04}\n", i).as_bytes())
+                .unwrap();
+        }
+        file.flush().unwrap();
+
+        let mut lines = Vec::new();
+        for name in ["agent.log.3", "agent.log.2", "agent.log.1", "agent.log"] {
+            let file_lines = read_lines(dir.join("logs").join(name));
+            assert_eq!(file_lines.len(), 10, "{}", name);
+            lines.extend(file_lines);
+        }
+        let expected: Vec<String> = (0..40).map(|i| format!("line-{:04}", i)).collect();
+        assert_eq!(lines, expected);
+
+        // 超出 max_files 的旧文件被删除
+        file.write_all(b"line-0040\n").unwrap();
+        assert!(!dir.join("logs").join("agent.log.4").exists());
+        assert_eq!(
+            read_lines(dir.join("logs").join("agent.log.3"))[0],
+            "line-0010"
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_rotate_daily_and_unwritable() {
+        let dir = temp_dir("daily");
+        let path = dir.join("agent.log");
+        let mut file = RotatingFile::open(
+            &path,
+            LogRotateConfig {
+                max_bytes: 0,
+                daily: true,
+                max_files: 1,
+            },
+        )
+        .unwrap();
+        file.write_all(b"yesterday\n").unwrap();
+        file.day -= 1;
+        file.write_all(b"today\n").unwrap();
+        assert_eq!(read_lines(dir.join("agent.log.1")), ["yesterday"]);
+        assert_eq!(read_lines(path), ["today"]);
+
+        // 目录无法创建（上级是普通文件）
+        let blocked = dir.join("agent.log.1").join("sub").join("agent.log");
+        assert!(RotatingFile::open(blocked, LogRotateConfig::default()).is_err());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_reopen_after_move() {
+        let dir = temp_dir("reopen");
+        let path = dir.join("access.log");
+        let mut file = RotatingFile::open(
+            &path,
+            LogRotateConfig {
+                max_bytes: 0,
+                daily: false,
+                max_files: 1,
+            },
+        )
+        .unwrap();
+        file.write_all(b"before\n").unwrap();
+        // 外部工具移走文件后发送 SIGHUP
+        fs::rename(&path, dir.join("access.log.moved")).unwrap();
+        file.write_all(b"still-old\n").unwrap();
+        request_reopen();
+        file.write_all(b"after\n").unwrap();
+        assert_eq!(
+            read_lines(dir.join("access.log.moved")),
+            ["before", "still-old"]
+        );
+        assert_eq!(read_lines(path), ["after"]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,100 +1,17 @@
 #![cfg_attr(all(windows, feature = "gui"), windows_subsystem = "windows")]
 
-mod auth;
-mod config;
-mod headers;
-mod ip;
-mod proxy;
-
 use anyhow::Result;
-use axum::{
-    body::Body,
-    extract::{Request, State},
-    http::{HeaderMap, HeaderValue, Method, StatusCode},
-    middleware::Next,
-    response::Response,
-    routing::{any, get},
-    Router,
+use remote_http_agent::config::Config;
+use remote_http_agent::{
+    build_client, build_router_with_client, cleanup_before_exit, server, telemetry,
+    verify_upstream_proxy,
 };
-use config::Config;
-use proxy::{add_cache_control_headers, add_cors_headers, AppState};
-use reqwest::Client;
-use std::sync::Arc;
-
-pub struct AppConfig {
-    pub state: Arc<AppState>,
-    pub token: String,
-}
-
-
-async fn app_middleware(
-    State(config): State<Arc<AppConfig>>,
-    request: Request,
-    next: Next,
-) -> Response {
-    let request_headers = request.headers().clone();
-    let method = request.method().clone();
-
-    let mut cors_headers = HeaderMap::new();
-    add_cors_headers(&mut cors_headers, &request_headers);
-
-    // OPTIONS 直接返回 204，不做认证（与 Go 版本一致）
-    if method == Method::OPTIONS {
-        let mut resp = Response::new(Body::empty());
-        *resp.status_mut() = StatusCode::NO_CONTENT;
-        *resp.headers_mut() = cors_headers;
-        return resp;
-    }
-
-    add_cache_control_headers(&mut cors_headers);
-
-    let auth_header = request_headers
-        .get("authorization")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("");
-
-    if !auth::valid_bearer(auth_header, &config.token) {
-        let body =
-            serde_json::json!({"error": "未认证，请更新App: bearer 认证失败"}).to_string();
-        let mut resp = Response::new(Body::from(body));
-        *resp.status_mut() = StatusCode::UNAUTHORIZED;
-        resp.headers_mut().insert(
-            "content-type",
-            HeaderValue::from_static("application/json; charset=utf-8"),
-        );
-        for (k, v) in cors_headers.iter() {
-            resp.headers_mut().insert(k, v.clone());
-        }
-        return resp;
-    }
-
-    let mut resp = next.run(request).await;
-    for (k, v) in cors_headers.iter() {
-        resp.headers_mut().insert(k, v.clone());
-    }
-    resp
-}
-
-async fn kill_handler() -> impl axum::response::IntoResponse {
-    tokio::spawn(async {
-        tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
-        std::process::exit(0);
-    });
-    axum::Json(serde_json::json!({"code": 0, "msg": "程序即将退出"}))
-}
+use std::path::Path;
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "info".into()),
-        )
-        .init();
-
-    let app_dir = std::env::current_dir()?;
-    println!("Current working directory: {:?}", app_dir);
+mod cli;
 
+/// 生成 kill 脚本，便于在没有终端的环境下停止服务
+fn write_kill_script(app_dir: &Path, quiet: bool) {
     let pid = std::process::id();
     #[cfg(windows)]
     {
@@ -104,9 +21,9 @@ async fn main() -> Result<()> {
         );
         let kill_bat_path = app_dir.join("kill.bat");
         if let Err(e) = std::fs::write(&kill_bat_path, kill_bat_content) {
-            eprintln!("生成 kill.bat 失败: {}", e);
-        } else {
-            println!("已生成 kill.bat");
+            tracing::warn!("生成 kill.bat 失败: {}", e);
+        } else if !quiet {
+            tracing::info!("已生成 kill.bat");
         }
     }
     #[cfg(unix)]
@@ -115,53 +32,163 @@ async fn main() -> Result<()> {
         let kill_sh_content = format!("#!/bin/sh\nkill {} 2>/dev/null\necho 'Service stopped'\n", pid);
         let kill_sh_path = app_dir.join("kill.sh");
         if let Err(e) = std::fs::write(&kill_sh_path, &kill_sh_content) {
-            eprintln!("生成 kill.sh 失败: {}", e);
+            tracing::warn!("生成 kill.sh 失败: {}", e);
         } else {
             let _ = std::fs::set_permissions(&kill_sh_path, std::fs::Permissions::from_mode(0o755));
-            println!("已生成 kill.sh");
+            if !quiet {
+                tracing::info!("已生成 kill.sh");
+            }
         }
     }
+}
 
-    let config = Config::load_or_create("config.json5")?;
-
-    let mut client_builder = Client::builder()
-        .timeout(std::time::Duration::from_secs(300))
-        .redirect(reqwest::redirect::Policy::none())
-        .danger_accept_invalid_certs(config.skip_tls);
+/// 已启用的编译特性，写入启动时的生效配置日志
+fn enabled_features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    if cfg!(feature = "otel") {
+        features.push("otel");
+    }
+    if cfg!(feature = "http3") {
+        features.push("http3");
+    }
+    if cfg!(feature = "h2c") {
+        features.push("h2c");
+    }
+    if cfg!(feature = "unix-upstream") {
+        features.push("unix-upstream");
+    }
+    if cfg!(feature = "gui") {
+        features.push("gui");
+    }
+    features
+}
 
-    if !config.http_proxy.trim().is_empty() {
-        match reqwest::Proxy::all(&config.http_proxy) {
-            Ok(proxy) => {
-                client_builder = client_builder.proxy(proxy);
-            }
-            Err(e) => {
-                println!("http_proxy 格式错误，将使用默认代理: {:?}", e);
+#[tokio::main]
+async fn main() -> Result<()> {
+    let command = match cli::parse(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    let args = match command {
+        cli::Command::Serve(args) => args,
+        command => {
+            if let Err(e) = cli::run(command) {
+                eprintln!("错误: {:#}", e);
+                std::process::exit(1);
             }
+            return Ok(());
+        }
+    };
+    let app_dir = std::env::current_dir()?;
+    let config_path = match &args.config {
+        // 显式指定的配置文件必须存在，避免路径写错时静默使用默认配置
+        Some(path) if !path.exists() => {
+            anyhow::bail!("配置文件不存在: {}", path.display())
+        }
+        Some(path) => app_dir.join(path),
+        None => Config::find_config_file(&app_dir),
+    };
+    let config_exists = config_path.exists();
+    let config = Config::load_or_create(&config_path)?;
+    config.validate()?;
+    telemetry::init(&config)?;
+
+    if !args.quiet {
+        tracing::info!("当前工作目录: {}", app_dir.display());
+        if config_exists {
+            tracing::info!("已加载配置文件: {}", config_path.display());
+        } else {
+            tracing::info!("配置文件不存在，使用默认配置");
         }
     }
+    write_kill_script(&app_dir, args.quiet);
 
-    let client = client_builder.build()?;
-
-    let app_config = Arc::new(AppConfig {
-        state: Arc::new(AppState { client }),
-        token: config.token.clone(),
-    });
+    #[cfg(not(feature = "http3"))]
+    if config.upstream_http3 {
+        tracing::warn!("未启用 http3 编译特性，忽略 upstream_http3");
+    }
+    #[cfg(not(feature = "unix-upstream"))]
+    if config.unix_sockets || !config.allowed_unix_sockets.is_empty() {
+        tracing::warn!("未启用 unix-upstream 编译特性，忽略 unix_sockets 与 allowed_unix_sockets");
+    }
+    for warning in config.server.warnings() {
+        tracing::warn!("{}", warning);
+    }
+    for warning in config.credential_warnings() {
+        tracing::warn!("{}", warning);
+    }
+    if config.skip_tls {
+        tracing::warn!(
+            "skip_tls 已开启：不验证任何上游的 TLS 证书，流量可能被中间人窃听或篡改；\
+             生产环境请关闭，只把自签名证书的主机加入 insecure_hosts"
+        );
+    }
 
-    let app = Router::new()
-        .route("/proxy", any(proxy::proxy_request_handler))
-        .route("/lanip", get(ip::get_lan_ip_handler))
-        .route("/kill", get(kill_handler))
-        .layer(axum::middleware::from_fn_with_state(
-            app_config.clone(),
-            app_middleware,
-        ))
-        .with_state(app_config);
+    let client = build_client(&config)?;
+    if config.verify_proxy_on_startup {
+        if let Err(e) = verify_upstream_proxy(&client, &config).await {
+            anyhow::bail!("{}，请检查 http_proxy 配置", e);
+        }
+    }
 
-    let addr = &config.listening;
-    println!("运行在 http://{}", addr);
+    let app = build_router_with_client(config.clone(), client)?;
+    let base_path = config.normalized_base_path();
+
+    let listener =
+        server::Listener::bind(&config.listening, config.unix_socket_permissions()).await?;
+    // `listening` 的端口为 0 时记录系统实际分配的端口
+    let listen = match (config.listening_unix_path(), listener.local_addr()) {
+        (Some(path), _) => format!("unix:{}", path),
+        (None, addr) => addr.map_or(config.listening.clone(), |addr| addr.to_string()),
+    };
+    tracing::info!(
+        config_path = %config_path.display(),
+        config_loaded = config_exists,
+        listen = %listen,
+        upstream_proxy = !config.http_proxy.trim().is_empty(),
+        skip_tls = config.skip_tls,
+        connect = config.allow_connect,
+        h2c = config.server.h2c_enabled(),
+        features = ?enabled_features(),
+        config = %config.redacted(),
+        "生效配置"
+    );
+    if !args.quiet {
+        for rule in &config.reverse_proxies {
+            tracing::info!("反向代理: {} -> {}", rule.path, rule.upstream);
+        }
+        if config.allow_connect {
+            tracing::info!("已启用 CONNECT 隧道");
+        }
+        if config.listening_unix_path().is_some() {
+            tracing::info!("运行在 {}", listen);
+        } else {
+            tracing::info!("运行在 http://{}{}", listen, base_path);
+            if config.ui.enabled {
+                tracing::info!("控制台页面: http://{}{}/ui/", listen, base_path);
+            }
+        }
+        if config.server.h2c_enabled() {
+            tracing::info!("已启用 h2c（明文 HTTP/2）");
+        }
+    }
+    if let Some(path) = &args.print_port {
+        let addr = listener
+            .local_addr()
+            .ok_or_else(|| anyhow::anyhow!("--print-port 只适用于 TCP 监听地址"))?;
+        cli::write_atomic(path, &format!("{}\n", addr.port()))
+            .map_err(|e| anyhow::anyhow!("--print-port: 无法写入 {}: {}", path.display(), e))?;
+    }
 
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    tokio::spawn(server::reopen_logs_on_hangup());
+    tokio::select! {
+        result = server::serve(listener, app, &config.server) => result?,
+        _ = server::shutdown_signal() => tracing::info!("正在退出"),
+    }
+    cleanup_before_exit(&config);
 
     Ok(())
 }
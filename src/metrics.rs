@@ -0,0 +1,352 @@
+//! 指标导出（`metrics.statsd`）：计数器、计时器与仪表以 StatsD/DogStatsD 文本协议经 UDP 发送，
+//! 供只能接收推送、无法抓取指标的 Datadog Agent 等环境使用
+//!
+//! 指标经有界 mpsc 通道交给后台任务合并成数据包发送；通道已满时丢弃指标，StatsD 服务不可达时
+//! 只记录日志
+
+use crate::background::DeferredTask;
+use crate::config::StatsdConfig;
+use crate::stream::CompletionBody;
+use crate::AppConfig;
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+/// 收到的请求数（计数器）
+pub const REQUESTS: &str = "requests";
+/// 上游请求失败（连接失败、超时等）次数（计数器）
+pub const ERRORS: &str = "errors";
+/// 认证失败次数（计数器）
+pub const AUTH_FAILURES: &str = "auth_failures";
+/// 发出上游请求到收到响应头的耗时（计时器）
+pub const UPSTREAM_TTFB: &str = "upstream_ttfb";
+/// 收到请求到响应体发送完（或客户端断开）的耗时（计时器）
+pub const TOTAL_DURATION: &str = "total_duration";
+/// 正在处理的请求数（仪表），每 10 秒发送一次
+pub const IN_FLIGHT: &str = "in_flight";
+
+/// 等待发送的指标数上限
+const QUEUE_SIZE: usize = 4096;
+
+/// 单个 UDP 数据包的最大字节数，不超过常见 MTU 以免分片
+const MAX_PACKET_BYTES: usize = 1432;
+
+/// 发送 `in_flight` 的间隔
+const GAUGE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// 解析 StatsD 地址失败后重试的间隔，期间的指标丢弃
+const RESOLVE_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Metric {
+    Counter(&'static str),
+    Timer(&'static str, Duration),
+    Gauge(&'static str, i64),
+}
+
+/// 指标名前缀与 DogStatsD 标签
+#[derive(Debug, Clone)]
+struct Format {
+    prefix: String,
+    /// `|#a:b,c` 形式，没有标签时为空
+    tags: String,
+}
+
+impl Format {
+    fn new(config: &StatsdConfig) -> Self {
+        let prefix = config.prefix.trim();
+        Self {
+            prefix: if prefix.is_empty() {
+                String::new()
+            } else {
+                format!("{}.", prefix)
+            },
+            tags: if config.tags.is_empty() {
+                String::new()
+            } else {
+                format!("|#{}", config.tags.join(","))
+            },
+        }
+    }
+
+    /// 一行 `<前缀>.<名称>:<值>|<类型>[|#<标签>]`，计时器的值为毫秒
+    fn line(&self, metric: Metric) -> String {
+        let (name, value, kind) = match metric {
+            Metric::Counter(name) => (name, 1, "c"),
+            Metric::Timer(name, duration) => (name, duration.as_millis() as i64, "ms"),
+            Metric::Gauge(name, value) => (name, value, "g"),
+        };
+        format!("{}{}:{}|{}{}", self.prefix, name, value, kind, self.tags)
+    }
+}
+
+/// StatsD 客户端，所有方法都不会等待
+pub struct Statsd {
+    sender: mpsc::Sender<Metric>,
+    /// 发送任务，第一次发送指标时启动
+    export: DeferredTask,
+    in_flight: Arc<AtomicI64>,
+}
+
+impl Statsd {
+    pub fn new(config: &StatsdConfig) -> Self {
+        let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+        let in_flight = Arc::new(AtomicI64::new(0));
+        Self {
+            sender,
+            export: DeferredTask::new(export(
+                receiver,
+                config.address.trim().to_string(),
+                Format::new(config),
+                in_flight.clone(),
+            )),
+            in_flight,
+        }
+    }
+
+    /// 计数器加 1
+    pub fn increment(&self, name: &'static str) {
+        self.send(Metric::Counter(name));
+    }
+
+    /// 记录一次耗时
+    pub fn timing(&self, name: &'static str, duration: Duration) {
+        self.send(Metric::Timer(name, duration));
+    }
+
+    fn send(&self, metric: Metric) {
+        self.export.start();
+        if self.sender.try_send(metric).is_err() {
+            debug!("指标队列已满，丢弃 {:?}", metric);
+        }
+    }
+
+    /// 开始处理一个请求：计入 `requests` 与 `in_flight`，返回的守卫丢弃时请求结束
+    fn request_started(self: &Arc<Self>) -> InFlight {
+        self.increment(REQUESTS);
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight {
+            statsd: self.clone(),
+            started: Instant::now(),
+        }
+    }
+}
+
+/// 正在处理的请求，丢弃时减少 `in_flight` 并记录 `total_duration`
+struct InFlight {
+    statsd: Arc<Statsd>,
+    started: Instant,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.statsd.in_flight.fetch_sub(1, Ordering::Relaxed);
+        self.statsd.timing(TOTAL_DURATION, self.started.elapsed());
+    }
+}
+
+/// 配置了 StatsD 时在最外层统计每个请求；处理中途客户端断开同样结束计时
+pub(crate) async fn metrics_middleware(
+    State(config): State<Arc<AppConfig>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(statsd) = &config.state.statsd else {
+        return next.run(request).await;
+    };
+    let in_flight = statsd.request_started();
+    let response = next.run(request).await;
+    response.map(|inner| Body::new(CompletionBody::new(inner, move |_| drop(in_flight))))
+}
+
+/// 把通道中已有的指标合并成不超过 `MAX_PACKET_BYTES` 的数据包发送，并定期发送 `in_flight`；
+/// 所有发送端都被丢弃后结束
+async fn export(
+    mut receiver: mpsc::Receiver<Metric>,
+    address: String,
+    format: Format,
+    in_flight: Arc<AtomicI64>,
+) {
+    let mut sink = Sink::new(address);
+    let mut gauge = tokio::time::interval(GAUGE_INTERVAL);
+    loop {
+        let metric = tokio::select! {
+            metric = receiver.recv() => match metric {
+                Some(metric) => metric,
+                None => return,
+            },
+            _ = gauge.tick() => Metric::Gauge(IN_FLIGHT, in_flight.load(Ordering::Relaxed)),
+        };
+        let mut packet = format.line(metric);
+        while let Ok(metric) = receiver.try_recv() {
+            let line = format.line(metric);
+            if packet.len() + 1 + line.len() > MAX_PACKET_BYTES {
+                sink.send(&packet).await;
+                packet = line;
+            } else {
+                packet.push('\n');
+                packet.push_str(&line);
+            }
+        }
+        sink.send(&packet).await;
+    }
+}
+
+/// StatsD 服务地址与发送用的 UDP socket，第一次发送时解析地址
+struct Sink {
+    address: String,
+    target: Option<(UdpSocket, SocketAddr)>,
+    next_resolve: Instant,
+}
+
+impl Sink {
+    fn new(address: String) -> Self {
+        Self {
+            address,
+            target: None,
+            next_resolve: Instant::now(),
+        }
+    }
+
+    async fn send(&mut self, packet: &str) {
+        if self.target.is_none() && Instant::now() >= self.next_resolve {
+            self.target = self.connect().await;
+            if self.target.is_none() {
+                self.next_resolve = Instant::now() + RESOLVE_RETRY_INTERVAL;
+            }
+        }
+        let Some((socket, target)) = &self.target else {
+            return;
+        };
+        // UDP 不等待对方确认，StatsD 服务不可达时最多返回一次 ICMP 错误
+        if let Err(e) = socket.send_to(packet.as_bytes(), target).await {
+            debug!("发送指标到 {} 失败: {}", target, e);
+        }
+    }
+
+    async fn connect(&self) -> Option<(UdpSocket, SocketAddr)> {
+        let target = match tokio::net::lookup_host(&self.address).await {
+            Ok(mut addrs) => addrs.next(),
+            Err(e) => {
+                warn!("解析 StatsD 地址 {} 失败: {}", self.address, e);
+                return None;
+            }
+        }?;
+        let bind: SocketAddr = if target.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        match UdpSocket::bind(bind).await {
+            Ok(socket) => Some((socket, target)),
+            Err(e) => {
+                warn!("创建发送指标的 UDP socket 失败: {}", e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(address: &str) -> StatsdConfig {
+        StatsdConfig {
+            address: address.to_string(),
+            prefix: "rha".to_string(),
+            tags: vec!["env:test".to_string(), "canary".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_line_format() {
+        let format = Format::new(&config("127.0.0.1:8125"));
+        assert_eq!(
+            format.line(Metric::Counter(REQUESTS)),
+            "rha.requests:1|c|#env:test,canary"
+        );
+        assert_eq!(
+            format.line(Metric::Timer(UPSTREAM_TTFB, Duration::from_micros(42_900))),
+            "rha.upstream_ttfb:42|ms|#env:test,canary"
+        );
+        assert_eq!(
+            format.line(Metric::Gauge(IN_FLIGHT, 3)),
+            "rha.in_flight:3|g|#env:test,canary"
+        );
+
+        // 不带前缀与标签时为普通 StatsD 格式
+        let format = Format::new(&StatsdConfig {
+            prefix: String::new(),
+            tags: Vec::new(),
+            ..config("127.0.0.1:8125")
+        });
+        assert_eq!(format.line(Metric::Counter(ERRORS)), "errors:1|c");
+    }
+
+    #[tokio::test]
+    async fn test_send_over_udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let statsd = Statsd::new(&config(&server.local_addr().unwrap().to_string()));
+        statsd.increment(AUTH_FAILURES);
+        statsd.timing(TOTAL_DURATION, Duration::from_millis(250));
+
+        // 同时发送的指标可能合并在一个数据包中，也可能带上 in_flight
+        let mut lines = Vec::new();
+        let mut buf = [0; MAX_PACKET_BYTES];
+        while lines.len() < 3 {
+            let len = tokio::time::timeout(Duration::from_secs(5), server.recv(&mut buf))
+                .await
+                .expect("没有收到指标")
+                .unwrap();
+            let packet = std::str::from_utf8(&buf[..len]).unwrap();
+            lines.extend(packet.lines().map(str::to_string));
+        }
+        assert!(
+            lines.contains(&"rha.auth_failures:1|c|#env:test,canary".to_string()),
+            "{:?}",
+            lines
+        );
+        assert!(
+            lines.contains(&"rha.total_duration:250|ms|#env:test,canary".to_string()),
+            "{:?}",
+            lines
+        );
+        assert!(
+            lines.contains(&"rha.in_flight:0|g|#env:test,canary".to_string()),
+            "{:?}",
+            lines
+        );
+    }
+
+    #[test]
+    fn test_full_queue_does_not_block() {
+        // 运行时之外不启动发送任务，队列满后直接丢弃
+        let statsd = Statsd::new(&config("127.0.0.1:9"));
+        for _ in 0..QUEUE_SIZE * 2 {
+            statsd.increment(REQUESTS);
+        }
+        assert_eq!(statsd.sender.capacity(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_in_flight() {
+        let statsd = Arc::new(Statsd::new(&config("127.0.0.1:9")));
+        let first = statsd.request_started();
+        let second = statsd.request_started();
+        assert_eq!(statsd.in_flight.load(Ordering::Relaxed), 2);
+        drop(first);
+        assert_eq!(statsd.in_flight.load(Ordering::Relaxed), 1);
+        drop(second);
+        assert_eq!(statsd.in_flight.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,421 @@
+//! 异常通知（`notifications`）：上游请求失败激增、上游组后端被摘除或恢复时，事件经 mpsc 通道交给
+//! 后台任务，按 `min_severity` 过滤、按事件类型限流后以 JSON POST 到 webhook
+//!
+//! 通道已满时丢弃事件，webhook 发送失败只记录日志，都不影响请求处理
+
+use crate::background::DeferredTask;
+use crate::config::{NotificationsConfig, Severity};
+use crate::upstream_groups::HealthChange;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+use url::Url;
+
+/// 等待发送的事件数上限
+const QUEUE_SIZE: usize = 64;
+
+/// 通知中附带的错误示例数
+const MAX_SAMPLES: usize = 3;
+
+/// 单次 webhook 请求的超时
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 一次通知事件
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    /// 事件类型，按类型限流：`upstream_errors`、`backend_ejected`、`backend_recovered`
+    pub kind: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    /// 触发事件的次数，如窗口内的失败次数
+    pub count: u64,
+    /// 错误示例，最多 3 条
+    pub samples: Vec<String>,
+}
+
+/// POST 到 webhook 的 JSON
+#[derive(Debug, Serialize)]
+struct Payload<'a> {
+    /// 一行摘要，供 Slack 等只显示 `text` 的接收方使用
+    text: String,
+    event: &'a str,
+    severity: Severity,
+    message: &'a str,
+    count: u64,
+    /// 上次通知之后因限流未发送的同类事件数
+    suppressed: u64,
+    sample_errors: &'a [String],
+    service: &'static str,
+    /// Unix 时间戳（秒）
+    timestamp: u64,
+}
+
+impl<'a> Payload<'a> {
+    fn new(event: &'a Event, suppressed: u64) -> Self {
+        let mut text = format!(
+            "[{}] {}: {}",
+            env!("CARGO_PKG_NAME"),
+            event.severity.as_str(),
+            event.message
+        );
+        if suppressed > 0 {
+            text.push_str(&format!("（期间另有 {} 次同类事件未通知）", suppressed));
+        }
+        Self {
+            text,
+            event: event.kind,
+            severity: event.severity,
+            message: &event.message,
+            count: event.count,
+            suppressed,
+            sample_errors: &event.samples,
+            service: env!("CARGO_PKG_NAME"),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// 上游请求失败的滑动窗口
+#[derive(Debug, Default)]
+struct ErrorWindow {
+    failures: VecDeque<Instant>,
+    samples: VecDeque<String>,
+}
+
+/// 按事件类型限流：同一类事件在 `interval` 内只通知一次，其余只计数
+#[derive(Debug)]
+struct RateLimiter {
+    interval: Duration,
+    last_sent: HashMap<&'static str, Instant>,
+    suppressed: HashMap<&'static str, u64>,
+}
+
+impl RateLimiter {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_sent: HashMap::new(),
+            suppressed: HashMap::new(),
+        }
+    }
+
+    /// 允许发送时返回此前被限流的同类事件数，否则返回 None 并计数
+    fn admit(&mut self, kind: &'static str, now: Instant) -> Option<u64> {
+        if self
+            .last_sent
+            .get(kind)
+            .is_some_and(|last| now.duration_since(*last) < self.interval)
+        {
+            *self.suppressed.entry(kind).or_default() += 1;
+            return None;
+        }
+        self.last_sent.insert(kind, now);
+        Some(self.suppressed.remove(kind).unwrap_or_default())
+    }
+}
+
+/// 收集事件并交给后台任务发送
+pub struct Notifier {
+    config: NotificationsConfig,
+    sender: mpsc::Sender<Event>,
+    /// 发送任务，第一次发送事件时启动
+    deliver: DeferredTask,
+    errors: Mutex<ErrorWindow>,
+}
+
+impl Notifier {
+    pub fn new(config: &NotificationsConfig) -> Self {
+        let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+        Self {
+            config: config.clone(),
+            sender,
+            deliver: DeferredTask::new(deliver(receiver, config.clone())),
+            errors: Mutex::new(ErrorWindow::default()),
+        }
+    }
+
+    /// 记录一次上游请求失败，`error_window_secs` 内的失败次数达到 `error_threshold` 时发送
+    /// `upstream_errors` 并重新计数
+    pub fn upstream_error(&self, sample: String) {
+        if let Some(event) = self.upstream_error_at(sample, Instant::now()) {
+            self.send(event);
+        }
+    }
+
+    fn upstream_error_at(&self, sample: String, now: Instant) -> Option<Event> {
+        let window = Duration::from_secs(self.config.error_window_secs);
+        let mut errors = self.errors.lock().unwrap();
+        while errors
+            .failures
+            .front()
+            .is_some_and(|time| now.duration_since(*time) > window)
+        {
+            errors.failures.pop_front();
+        }
+        errors.failures.push_back(now);
+        if errors.samples.len() >= MAX_SAMPLES {
+            errors.samples.pop_front();
+        }
+        errors.samples.push_back(sample);
+
+        let count = errors.failures.len();
+        if count < self.config.error_threshold as usize {
+            return None;
+        }
+        let samples = errors.samples.drain(..).collect();
+        errors.failures.clear();
+        Some(Event {
+            kind: "upstream_errors",
+            severity: Severity::Critical,
+            message: format!(
+                "{} 秒内上游请求失败 {} 次",
+                self.config.error_window_secs, count
+            ),
+            count: count as u64,
+            samples,
+        })
+    }
+
+    /// 上游组后端被摘除或恢复
+    pub fn health_change(&self, change: HealthChange) {
+        let event = match change {
+            HealthChange::Ejected { backend, failures } => Event {
+                kind: "backend_ejected",
+                severity: Severity::Warning,
+                message: format!("上游后端 {} 连续失败 {} 次，已摘除", backend, failures),
+                count: u64::from(failures),
+                samples: Vec::new(),
+            },
+            HealthChange::Recovered { backend } => Event {
+                kind: "backend_recovered",
+                severity: Severity::Info,
+                message: format!("上游后端 {} 已恢复", backend),
+                count: 1,
+                samples: Vec::new(),
+            },
+        };
+        self.send(event);
+    }
+
+    fn send(&self, event: Event) {
+        if event.severity < self.config.min_severity {
+            return;
+        }
+        self.deliver.start();
+        if self.sender.try_send(event).is_err() {
+            debug!("通知队列已满，丢弃事件");
+        }
+    }
+}
+
+/// 逐个发送事件，所有发送端都被丢弃后结束
+async fn deliver(mut receiver: mpsc::Receiver<Event>, config: NotificationsConfig) {
+    let client = match reqwest::Client::builder().timeout(WEBHOOK_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("创建通知客户端失败: {}", e);
+            return;
+        }
+    };
+    let mut limiter = RateLimiter::new(Duration::from_secs(config.rate_limit_secs));
+    while let Some(event) = receiver.recv().await {
+        let Some(suppressed) = limiter.admit(event.kind, Instant::now()) else {
+            debug!("通知限流，跳过 {}", event.kind);
+            continue;
+        };
+        let body = match serde_json::to_vec(&Payload::new(&event, suppressed)) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("序列化通知失败: {}", e);
+                continue;
+            }
+        };
+        // webhook 地址本身是凭据，日志中不出现
+        let result = client
+            .post(config.webhook_url.trim())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await;
+        match result {
+            Ok(response) if response.status().is_success() => {
+                debug!("已发送通知 {}", event.kind)
+            }
+            Ok(response) => warn!(
+                "发送通知 {} 失败: webhook 返回 {}",
+                event.kind,
+                response.status()
+            ),
+            Err(e) => warn!("发送通知 {} 失败: {}", event.kind, e.without_url()),
+        }
+    }
+}
+
+/// 通知中的错误示例：目标只保留协议与主机，错误信息中的完整地址（查询参数中可能带有凭据）同样替换
+pub(crate) fn error_sample(url: &str, error: &(dyn std::error::Error + 'static)) -> String {
+    let target = match Url::parse(url) {
+        Ok(parsed) => match parsed.host_str() {
+            Some(host) => match parsed.port() {
+                Some(port) => format!("{}://{}:{}", parsed.scheme(), host, port),
+                None => format!("{}://{}", parsed.scheme(), host),
+            },
+            None => parsed.scheme().to_string(),
+        },
+        Err(_) => "-".to_string(),
+    };
+    let mut text = error.to_string();
+    let mut source = error.source();
+    while let Some(e) = source {
+        text.push_str(": ");
+        text.push_str(&e.to_string());
+        source = e.source();
+    }
+    if let Some(full) = error.downcast_ref::<reqwest::Error>().and_then(|e| e.url()) {
+        text = text.replace(full.as_str(), &target);
+    }
+    format!("{} {}", target, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::post, Json, Router};
+    use std::sync::Arc;
+
+    fn config(webhook_url: String) -> NotificationsConfig {
+        NotificationsConfig {
+            webhook_url,
+            min_severity: Severity::Warning,
+            rate_limit_secs: 300,
+            error_threshold: 3,
+            error_window_secs: 60,
+        }
+    }
+
+    #[test]
+    fn test_error_window() {
+        let notifier = Notifier::new(&config("http://127.0.0.1/".to_string()));
+        let now = Instant::now();
+        assert!(notifier.upstream_error_at("e1".into(), now).is_none());
+        assert!(notifier.upstream_error_at("e2".into(), now).is_none());
+        // 超出窗口的失败不再计数
+        let later = now + Duration::from_secs(61);
+        assert!(notifier.upstream_error_at("e3".into(), later).is_none());
+        assert!(notifier.upstream_error_at("e4".into(), later).is_none());
+        let event = notifier.upstream_error_at("e5".into(), later).unwrap();
+        assert_eq!(event.kind, "upstream_errors");
+        assert_eq!(event.severity, Severity::Critical);
+        assert_eq!(event.count, 3);
+        assert_eq!(event.samples, ["e3", "e4", "e5"]);
+        // 发送后重新计数
+        assert!(notifier.upstream_error_at("e6".into(), later).is_none());
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::new(Duration::from_secs(300));
+        let now = Instant::now();
+        assert_eq!(limiter.admit("upstream_errors", now), Some(0));
+        assert_eq!(limiter.admit("backend_ejected", now), Some(0));
+        assert_eq!(limiter.admit("upstream_errors", now), None);
+        assert_eq!(
+            limiter.admit("upstream_errors", now + Duration::from_secs(299)),
+            None
+        );
+        assert_eq!(
+            limiter.admit("upstream_errors", now + Duration::from_secs(300)),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn test_error_sample() {
+        let error = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "拒绝连接");
+        assert_eq!(
+            error_sample("https://api.example.com:8443/x?key=secret", &error),
+            "https://api.example.com:8443 拒绝连接"
+        );
+        assert_eq!(
+            error_sample("unix:/run/app.sock/x", &error),
+            "unix 拒绝连接"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_webhook_delivery() {
+        let received = Arc::new(Mutex::new(Vec::<serde_json::Value>::new()));
+        let app = Router::new().route(
+            "/hook",
+            post({
+                let received = received.clone();
+                move |Json(payload): Json<serde_json::Value>| async move {
+                    received.lock().unwrap().push(payload);
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let notifier = Notifier::new(&config(format!("http://{}/hook", addr)));
+        for i in 0..3 {
+            notifier.upstream_error(format!("https://api.example.com 错误 {}", i));
+        }
+        // 同类事件被限流，低于 min_severity 的事件不发送
+        for i in 0..3 {
+            notifier.upstream_error(format!("https://api.example.com 错误 {}", i));
+        }
+        notifier.health_change(HealthChange::Recovered {
+            backend: "http://a.internal".to_string(),
+        });
+        notifier.health_change(HealthChange::Ejected {
+            backend: "http://a.internal".to_string(),
+            failures: 3,
+        });
+
+        for _ in 0..100 {
+            if received.lock().unwrap().len() >= 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2, "{:?}", received);
+        assert_eq!(received[0]["event"], "upstream_errors");
+        assert_eq!(received[0]["severity"], "critical");
+        assert_eq!(received[0]["count"], 3);
+        assert_eq!(received[0]["suppressed"], 0);
+        assert_eq!(received[0]["sample_errors"].as_array().unwrap().len(), 3);
+        assert_eq!(
+            received[0]["text"],
+            "[remote_http_agent] critical: 60 秒内上游请求失败 3 次"
+        );
+        assert_eq!(received[1]["event"], "backend_ejected");
+        assert_eq!(received[1]["severity"], "warning");
+    }
+
+    #[tokio::test]
+    async fn test_webhook_failure_does_not_block() {
+        // 绑定后立即释放的端口，连接会被拒绝
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let notifier = Notifier::new(&NotificationsConfig {
+            error_threshold: 1,
+            ..config(format!("http://{}/hook", closed))
+        });
+        // 队列满后直接丢弃，不等待发送
+        for i in 0..QUEUE_SIZE * 2 {
+            notifier.upstream_error(format!("错误 {}", i));
+        }
+    }
+}
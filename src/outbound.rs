@@ -0,0 +1,128 @@
+use crate::config::{Config, OutboundRotation};
+use crate::proxy::build_client;
+use reqwest::Client;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use url::Url;
+use uuid::Uuid;
+
+/// `per_host_sticky` 下主机与源地址的对应关系在最后一次使用后保留的时间
+const STICKY_TTL: Duration = Duration::from_secs(600);
+
+/// 对应关系超过此数量时清理过期条目
+const STICKY_PRUNE_THRESHOLD: usize = 1024;
+
+/// `outbound_local_addresses` 中每个源地址各自的客户端，按 `outbound_rotation` 为请求选择
+pub struct OutboundPool {
+    clients: Vec<(IpAddr, Client)>,
+    rotation: OutboundRotation,
+    next: AtomicUsize,
+    /// 主机名到源地址下标及最后使用时间
+    sticky: Mutex<HashMap<String, (usize, Instant)>>,
+}
+
+impl OutboundPool {
+    /// 未配置 `outbound_local_addresses` 时返回 None
+    pub fn new(config: &Config) -> anyhow::Result<Option<Self>> {
+        let addresses = config
+            .outbound_local_ips()
+            .map_err(|e| anyhow::anyhow!("outbound_local_addresses: {}", e))?;
+        if addresses.is_empty() {
+            return Ok(None);
+        }
+        let clients = addresses
+            .into_iter()
+            .map(|ip| {
+                let derived = Config {
+                    outbound_local_address: Some(ip.to_string()),
+                    ..config.clone()
+                };
+                Ok((ip, build_client(&derived)?))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Some(Self {
+            clients,
+            rotation: config.outbound_rotation,
+            next: AtomicUsize::new(0),
+            sticky: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    /// 为访问 `url` 的请求选择源地址及其客户端
+    pub fn pick(&self, url: &str) -> (IpAddr, &Client) {
+        let (ip, client) = &self.clients[self.pick_index(url)];
+        (*ip, client)
+    }
+
+    fn pick_index(&self, url: &str) -> usize {
+        let len = self.clients.len();
+        match self.rotation {
+            OutboundRotation::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed) % len,
+            OutboundRotation::Random => (Uuid::new_v4().as_u128() % len as u128) as usize,
+            OutboundRotation::PerHostSticky => {
+                let host = Url::parse(url)
+                    .ok()
+                    .and_then(|url| url.host_str().map(str::to_ascii_lowercase))
+                    .unwrap_or_default();
+                let now = Instant::now();
+                let mut sticky = self.sticky.lock().unwrap();
+                if sticky.len() >= STICKY_PRUNE_THRESHOLD {
+                    sticky.retain(|_, (_, used)| now.duration_since(*used) < STICKY_TTL);
+                }
+                let entry = sticky
+                    .entry(host)
+                    .or_insert_with(|| (self.next.fetch_add(1, Ordering::Relaxed) % len, now));
+                if now.duration_since(entry.1) >= STICKY_TTL {
+                    entry.0 = self.next.fetch_add(1, Ordering::Relaxed) % len;
+                }
+                entry.1 = now;
+                entry.0
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(rotation: OutboundRotation) -> OutboundPool {
+        OutboundPool::new(&Config {
+            outbound_local_addresses: vec!["127.0.0.1".to_string(), "::1".to_string()],
+            outbound_rotation: rotation,
+            ..Config::default()
+        })
+        .unwrap()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_rotation() {
+        assert!(OutboundPool::new(&Config::default()).unwrap().is_none());
+
+        let pool = self::pool(OutboundRotation::RoundRobin);
+        let picked: Vec<IpAddr> = (0..4)
+            .map(|_| pool.pick("https://example.com/").0)
+            .collect();
+        let v4: IpAddr = "127.0.0.1".parse().unwrap();
+        let v6: IpAddr = "::1".parse().unwrap();
+        assert_eq!(picked, [v4, v6, v4, v6]);
+
+        let pool = self::pool(OutboundRotation::PerHostSticky);
+        let first = pool.pick("https://a.example.com/x").0;
+        let second = pool.pick("https://b.example.com/").0;
+        assert_ne!(first, second);
+        for _ in 0..3 {
+            assert_eq!(pool.pick("https://A.example.com/y?z=1").0, first);
+            assert_eq!(pool.pick("https://b.example.com/").0, second);
+        }
+
+        let pool = self::pool(OutboundRotation::Random);
+        for _ in 0..8 {
+            assert!([v4, v6].contains(&pool.pick("https://example.com/").0));
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use crate::proxy::{AppError, BoxError};
+use axum::body::{Body, BodyDataStream};
+use bytes::{Bytes, BytesMut};
+use futures_util::{Stream, StreamExt, TryStreamExt};
+use http_body_util::LengthLimitError;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+
+/// 未配置 `buffer_request_below_bytes` 时完整读入的请求体上限，与 axum 的 `Bytes` 提取器默认值一致
+pub(crate) const BUFFERED_BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+/// 入站请求体：完整读入内存，或在达到缓冲阈值后边接收边转发
+#[derive(Debug)]
+pub(crate) enum RequestBody {
+    Buffered(Bytes),
+    Streamed(StreamedBody),
+}
+
+impl From<Bytes> for RequestBody {
+    fn from(body: Bytes) -> Self {
+        RequestBody::Buffered(body)
+    }
+}
+
+impl RequestBody {
+    /// `buffer_below` 为 None 时完整读入（上限 [`BUFFERED_BODY_LIMIT`]）；
+    /// 否则读到 `buffer_below` 字节仍未结束时改为流式转发，已读取的部分放在开头
+    pub(crate) async fn read(body: Body, buffer_below: Option<u64>) -> Result<Self, AppError> {
+        let Some(threshold) = buffer_below else {
+            return read_all(body).await.map(RequestBody::Buffered);
+        };
+        let mut stream = body.into_data_stream();
+        let mut head = BytesMut::new();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| {
+                if is_body_too_large(&e) {
+                    AppError::PayloadTooLarge(format!("读取请求体失败: {}", e))
+                } else {
+                    AppError::BadRequest(format!("读取请求体失败: {}", e))
+                }
+            })?;
+            head.extend_from_slice(&chunk);
+            if head.len() as u64 >= threshold {
+                return Ok(RequestBody::Streamed(StreamedBody::new(
+                    head.freeze(),
+                    stream,
+                )));
+            }
+        }
+        Ok(RequestBody::Buffered(head.freeze()))
+    }
+
+    /// 缓冲的请求体，流式请求体返回空
+    pub(crate) fn buffered(&self) -> Bytes {
+        match self {
+            RequestBody::Buffered(body) => body.clone(),
+            RequestBody::Streamed(_) => Bytes::new(),
+        }
+    }
+}
+
+/// 完整读入请求体，超过上限时返回 413
+pub(crate) async fn read_all(body: Body) -> Result<Bytes, AppError> {
+    axum::body::to_bytes(body, BUFFERED_BODY_LIMIT)
+        .await
+        .map_err(|e| AppError::PayloadTooLarge(format!("读取请求体失败: {}", e)))
+}
+
+/// 请求体超过 `max_body_bytes`：错误链中含有 [`LengthLimitError`]（流式转发时由上游客户端包装）
+pub(crate) fn is_body_too_large(error: &(dyn std::error::Error + 'static)) -> bool {
+    let mut current = Some(error);
+    while let Some(e) = current {
+        if e.is::<LengthLimitError>() {
+            return true;
+        }
+        current = e.source();
+    }
+    false
+}
+
+/// 边接收边转发的请求体，只能发送一次
+pub(crate) struct StreamedBody(Mutex<Option<(Bytes, BodyDataStream)>>);
+
+impl std::fmt::Debug for StreamedBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("StreamedBody")
+    }
+}
+
+impl StreamedBody {
+    fn new(head: Bytes, rest: BodyDataStream) -> Self {
+        StreamedBody(Mutex::new(Some((head, rest))))
+    }
+
+    /// 取出请求体交给上游客户端，已取出过（如跟随重定向后重新发送）时返回 None
+    pub(crate) fn take(&self) -> Option<reqwest::Body> {
+        let (head, rest) = self.0.lock().unwrap().take()?;
+        let stream =
+            futures_util::stream::once(async move { Ok(head) }).chain(rest.map_err(BoxError::from));
+        Some(reqwest::Body::wrap_stream(SyncStream(Mutex::new(
+            Box::pin(stream) as Pin<Box<dyn Stream<Item = Result<Bytes, BoxError>> + Send>>,
+        ))))
+    }
+
+    /// 读完剩余部分，用于只接受完整请求体的上游（Unix socket）
+    pub(crate) async fn collect(&self) -> Result<Bytes, AppError> {
+        let Some((head, rest)) = self.0.lock().unwrap().take() else {
+            return Ok(Bytes::new());
+        };
+        let rest = read_all(Body::from_stream(rest)).await?;
+        let mut body = BytesMut::from(&head[..]);
+        body.extend_from_slice(&rest);
+        Ok(body.freeze())
+    }
+}
+
+/// reqwest 要求请求体的流同时实现 `Sync`，入站请求体只实现了 `Send`
+struct SyncStream<S>(Mutex<S>);
+
+impl<S: Stream + Unpin> Stream for SyncStream<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().0.get_mut().unwrap().poll_next_unpin(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunked(chunks: &[&'static str]) -> Body {
+        let chunks: Vec<Result<Bytes, std::io::Error>> = chunks
+            .iter()
+            .map(|c| Ok(Bytes::from_static(c.as_bytes())))
+            .collect();
+        Body::from_stream(futures_util::stream::iter(chunks))
+    }
+
+    #[tokio::test]
+    async fn test_read() {
+        let body = RequestBody::read(chunked(&["ab", "cd"]), None)
+            .await
+            .unwrap();
+        assert!(matches!(body, RequestBody::Buffered(ref b) if b == "abcd"));
+
+        let body = RequestBody::read(chunked(&["ab", "cd"]), Some(5))
+            .await
+            .unwrap();
+        assert!(matches!(body, RequestBody::Buffered(ref b) if b == "abcd"));
+
+        // 达到阈值后改为流式，已读取的部分不会丢失
+        let body = RequestBody::read(chunked(&["ab", "cd", "ef"]), Some(4))
+            .await
+            .unwrap();
+        let RequestBody::Streamed(streamed) = body else {
+            panic!("expected streamed body");
+        };
+        assert_eq!(streamed.collect().await.unwrap(), "abcdef");
+        assert!(streamed.take().is_none());
+
+        let body = RequestBody::read(Body::empty(), Some(0)).await.unwrap();
+        assert!(matches!(body, RequestBody::Buffered(ref b) if b.is_empty()));
+
+        let large = Body::from(vec![0u8; BUFFERED_BODY_LIMIT + 1]);
+        assert!(matches!(
+            RequestBody::read(large, None).await,
+            Err(AppError::PayloadTooLarge(_))
+        ));
+    }
+}
@@ -0,0 +1,886 @@
+use crate::headers::is_grpc_content_type;
+use crate::proxy::{build_proxy_url, BoxError, ProxyUrlStyle, UpstreamResponse, PROXY_PATH};
+use axum::http::{HeaderMap, HeaderValue};
+use bytes::Bytes;
+use futures_util::StreamExt;
+use std::io::Read;
+use url::Url;
+
+/// 请求改写页面链接的控制头部
+pub const REWRITE_HEADER: &str = "tun-rewrite-html";
+
+/// 不经过代理的链接
+const SKIPPED_SCHEMES: &[&str] = &["javascript:", "data:", "mailto:", "tel:", "about:", "blob:"];
+
+/// 可改写链接的响应类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentKind {
+    Html,
+    Css,
+    Json,
+}
+
+impl ContentKind {
+    pub fn from_headers(headers: &reqwest::header::HeaderMap) -> Option<Self> {
+        let content_type = headers.get("content-type")?.to_str().ok()?;
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        match mime.as_str() {
+            "text/html" | "application/xhtml+xml" => Some(ContentKind::Html),
+            "text/css" => Some(ContentKind::Css),
+            "application/json" => Some(ContentKind::Json),
+            _ if is_grpc_content_type(&mime) => None,
+            _ if mime.ends_with("+json") => Some(ContentKind::Json),
+            _ => None,
+        }
+    }
+}
+
+/// 请求头 `tun-rewrite-html` 优先，未携带时使用配置的默认值
+pub fn is_rewrite_requested(headers: &HeaderMap, default: bool) -> bool {
+    match headers.get(REWRITE_HEADER).and_then(|v| v.to_str().ok()) {
+        Some(value) => value.trim().eq_ignore_ascii_case("true"),
+        None => default,
+    }
+}
+
+/// 只向上游声明可以解压的编码，避免收到无法改写的 br/zstd 响应
+pub fn restrict_accept_encoding(headers: &mut reqwest::header::HeaderMap) {
+    let Some(value) = headers.get("accept-encoding").and_then(|v| v.to_str().ok()) else {
+        return;
+    };
+    let kept: Vec<&str> = value
+        .split(',')
+        .map(str::trim)
+        .filter(|token| {
+            let coding = token.split(';').next().unwrap_or_default().trim();
+            ["gzip", "x-gzip", "deflate", "identity"]
+                .iter()
+                .any(|c| c.eq_ignore_ascii_case(coding))
+        })
+        .collect();
+
+    if kept.is_empty() {
+        headers.remove("accept-encoding");
+    } else if let Ok(value) = reqwest::header::HeaderValue::from_str(&kept.join(", ")) {
+        headers.insert("accept-encoding", value);
+    }
+}
+
+/// 把页面中的链接改写为经由代理的地址
+pub struct LinkRewriter<'a> {
+    base: Url,
+    base_path: &'a str,
+    style: ProxyUrlStyle,
+    /// 是否改写 HTML/CSS
+    markup: bool,
+    /// JSON 响应中需要改写的字符串值（`rewrite_json_urls`）
+    json_pointers: &'a [String],
+}
+
+impl<'a> LinkRewriter<'a> {
+    /// `base` 为页面的最终地址，相对链接以此为基准解析
+    pub(crate) fn new(base: Url, base_path: &'a str, style: ProxyUrlStyle) -> Self {
+        Self {
+            base,
+            base_path,
+            style,
+            markup: true,
+            json_pointers: &[],
+        }
+    }
+
+    /// 是否改写 HTML/CSS，默认改写
+    pub(crate) fn with_markup(mut self, markup: bool) -> Self {
+        self.markup = markup;
+        self
+    }
+
+    /// 改写 JSON 响应中这些 JSON Pointer 位置的地址，`*` 匹配任意数组下标或对象键
+    pub(crate) fn with_json_pointers(mut self, pointers: &'a [String]) -> Self {
+        self.json_pointers = pointers;
+        self
+    }
+
+    fn handles(&self, kind: ContentKind) -> bool {
+        match kind {
+            ContentKind::Html | ContentKind::Css => self.markup,
+            ContentKind::Json => !self.json_pointers.is_empty(),
+        }
+    }
+
+    /// 解析并改写单个链接，无需改写（锚点、`data:`、已是代理地址等）时返回 None
+    fn rewrite_url(&self, value: &str) -> Option<String> {
+        let value = value.trim();
+        if value.is_empty() || value.starts_with('#') {
+            return None;
+        }
+
+        let lowered = value.to_ascii_lowercase();
+        if SKIPPED_SCHEMES.iter().any(|s| lowered.starts_with(s)) {
+            return None;
+        }
+
+        let proxy_prefix = format!("{}{}", self.base_path, PROXY_PATH);
+        if let Some(rest) = value.strip_prefix(&proxy_prefix) {
+            if rest.starts_with('?') || rest.starts_with('/') {
+                return None;
+            }
+        }
+
+        let url = self.base.join(value).ok()?;
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return None;
+        }
+        Some(build_proxy_url(self.base_path, url.as_str(), self.style))
+    }
+
+    /// 改写 `srcset` 中每个候选地址，保留其后的尺寸描述
+    fn rewrite_srcset(&self, value: &str) -> Option<String> {
+        let mut changed = false;
+        let candidates: Vec<String> = value
+            .split(',')
+            .map(|candidate| {
+                let trimmed = candidate.trim();
+                let (url, descriptor) = match trimmed.find(char::is_whitespace) {
+                    Some(index) => trimmed.split_at(index),
+                    None => (trimmed, ""),
+                };
+                match self.rewrite_url(url) {
+                    Some(url) => {
+                        changed = true;
+                        format!("{}{}", url, descriptor)
+                    }
+                    None => trimmed.to_string(),
+                }
+            })
+            .collect();
+        changed.then(|| candidates.join(", "))
+    }
+
+    /// 改写属性值，`&amp;` 先还原为 `&` 再解析
+    fn rewrite_attribute(&mut self, tag: &str, name: &str, value: &[u8]) -> Option<Vec<u8>> {
+        let value = std::str::from_utf8(value).ok()?;
+        match name {
+            "href" | "src" | "action" | "poster" => {
+                let decoded = value.replace("&amp;", "&");
+                // <base href> 改变之后相对链接的解析基准
+                if tag == "base" && name == "href" {
+                    if let Ok(base) = self.base.join(decoded.trim()) {
+                        self.base = base;
+                    }
+                }
+                self.rewrite_url(&decoded).map(String::into_bytes)
+            }
+            "srcset" => self
+                .rewrite_srcset(&value.replace("&amp;", "&"))
+                .map(String::into_bytes),
+            "style" => {
+                let rewritten = self.rewrite_css(value.as_bytes());
+                (rewritten != value.as_bytes()).then_some(rewritten)
+            }
+            _ => None,
+        }
+    }
+
+    /// 改写 HTML 中的 `href`/`src`/`srcset`/`action` 属性、`style` 属性以及 `<style>` 内的 CSS
+    ///
+    /// 注释与 `<script>` 内容原样保留
+    pub fn rewrite_html(&mut self, html: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(html.len() + html.len() / 8);
+        let mut i = 0;
+
+        while let Some(offset) = find(&html[i..], b"<") {
+            let start = i + offset;
+            out.extend_from_slice(&html[i..start]);
+            i = start;
+
+            if html[i..].starts_with(b"<!--") {
+                let end = find(&html[i..], b"-->").map_or(html.len(), |e| i + e + 3);
+                out.extend_from_slice(&html[i..end]);
+                i = end;
+                continue;
+            }
+
+            let is_tag = html.get(i + 1).is_some_and(|c| c.is_ascii_alphabetic());
+            if !is_tag {
+                // 结束标签、DOCTYPE 等原样输出
+                let end = match html.get(i + 1) {
+                    Some(b'/' | b'!' | b'?') => {
+                        find(&html[i..], b">").map_or(html.len(), |e| i + e + 1)
+                    }
+                    _ => i + 1,
+                };
+                out.extend_from_slice(&html[i..end]);
+                i = end;
+                continue;
+            }
+
+            let (tag, end) = self.rewrite_tag(html, i, &mut out);
+            i = end;
+
+            // <script>/<style> 的内容不是 HTML，直到对应的结束标签为止
+            if tag == "script" || tag == "style" {
+                let close = format!("</{}", tag);
+                let content_end =
+                    find_ignore_case(&html[i..], close.as_bytes()).map_or(html.len(), |e| i + e);
+                if tag == "style" {
+                    out.extend_from_slice(&self.rewrite_css(&html[i..content_end]));
+                } else {
+                    out.extend_from_slice(&html[i..content_end]);
+                }
+                i = content_end;
+            }
+        }
+
+        out.extend_from_slice(&html[i..]);
+        out
+    }
+
+    /// 处理从 `start`（指向 `<`）开始的开始标签，返回小写标签名与标签结束后的位置
+    fn rewrite_tag(&mut self, html: &[u8], start: usize, out: &mut Vec<u8>) -> (String, usize) {
+        let mut i = start + 1;
+        while i < html.len() && (html[i].is_ascii_alphanumeric() || html[i] == b'-') {
+            i += 1;
+        }
+        let tag = String::from_utf8_lossy(&html[start + 1..i]).to_ascii_lowercase();
+        out.extend_from_slice(&html[start..i]);
+
+        loop {
+            // 属性之间的空白与多余的 `/`
+            while i < html.len() && (html[i].is_ascii_whitespace() || html[i] == b'/') {
+                if html[i] == b'/' && html.get(i + 1) == Some(&b'>') {
+                    break;
+                }
+                out.push(html[i]);
+                i += 1;
+            }
+            if i >= html.len() {
+                return (tag, i);
+            }
+            if html[i] == b'>' {
+                out.push(b'>');
+                return (tag, i + 1);
+            }
+            if html[i..].starts_with(b"/>") {
+                out.extend_from_slice(b"/>");
+                return (tag, i + 2);
+            }
+
+            let name_start = i;
+            while i < html.len()
+                && !html[i].is_ascii_whitespace()
+                && !matches!(html[i], b'=' | b'>' | b'/')
+            {
+                i += 1;
+            }
+            if i == name_start {
+                // 无法识别的字符（如孤立的 `=`）原样输出
+                out.push(html[i]);
+                i += 1;
+                continue;
+            }
+            let name = String::from_utf8_lossy(&html[name_start..i]).to_ascii_lowercase();
+            out.extend_from_slice(&html[name_start..i]);
+
+            let mut j = i;
+            while j < html.len() && html[j].is_ascii_whitespace() {
+                j += 1;
+            }
+            if html.get(j) != Some(&b'=') {
+                continue;
+            }
+            j += 1;
+            while j < html.len() && html[j].is_ascii_whitespace() {
+                j += 1;
+            }
+            out.extend_from_slice(&html[i..j]);
+            i = j;
+
+            let (quote, value_start, value_end, next) = match html.get(i) {
+                Some(&q @ (b'"' | b'\'')) => {
+                    let end = find(&html[i + 1..], &[q]).map_or(html.len(), |e| i + 1 + e);
+                    (Some(q), i + 1, end, (end + 1).min(html.len()))
+                }
+                _ => {
+                    let mut end = i;
+                    while end < html.len() && !html[end].is_ascii_whitespace() && html[end] != b'>'
+                    {
+                        end += 1;
+                    }
+                    (None, i, end, end)
+                }
+            };
+
+            let value = &html[value_start..value_end];
+            let rewritten = self.rewrite_attribute(&tag, &name, value);
+            if let Some(q) = quote {
+                out.push(q);
+            }
+            out.extend_from_slice(rewritten.as_deref().unwrap_or(value));
+            if let Some(q) = quote {
+                if value_end < html.len() {
+                    out.push(q);
+                }
+            }
+            i = next;
+        }
+    }
+
+    /// 改写 JSON 中指定位置的绝对 http(s) 地址，其他字符串与不存在的位置保持不变；
+    /// 不是合法 JSON 时返回 None，没有需要改写的值时返回原内容
+    pub fn rewrite_json(&self, json: &[u8]) -> Option<Vec<u8>> {
+        let mut value: serde_json::Value = serde_json::from_slice(json).ok()?;
+        let mut changed = false;
+        for pointer in self.json_pointers {
+            let segments: Vec<String> = pointer
+                .split('/')
+                .skip(1)
+                .map(|segment| segment.replace("~1", "/").replace("~0", "~"))
+                .collect();
+            changed |= self.rewrite_json_value(&mut value, &segments);
+        }
+        if !changed {
+            return Some(json.to_vec());
+        }
+        serde_json::to_vec(&value).ok()
+    }
+
+    fn rewrite_json_value(&self, value: &mut serde_json::Value, segments: &[String]) -> bool {
+        use serde_json::Value;
+
+        let Some((segment, rest)) = segments.split_first() else {
+            let Value::String(url) = value else {
+                return false;
+            };
+            let absolute = Url::parse(url.trim())
+                .is_ok_and(|url| url.scheme() == "http" || url.scheme() == "https");
+            return match self.rewrite_url(url).filter(|_| absolute) {
+                Some(rewritten) => {
+                    *url = rewritten;
+                    true
+                }
+                None => false,
+            };
+        };
+        match (value, segment.as_str()) {
+            (Value::Array(items), "*") => items.iter_mut().fold(false, |changed, item| {
+                self.rewrite_json_value(item, rest) | changed
+            }),
+            (Value::Object(map), "*") => map.values_mut().fold(false, |changed, item| {
+                self.rewrite_json_value(item, rest) | changed
+            }),
+            (Value::Array(items), index) => index
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| items.get_mut(index))
+                .is_some_and(|item| self.rewrite_json_value(item, rest)),
+            (Value::Object(map), key) => map
+                .get_mut(key)
+                .is_some_and(|item| self.rewrite_json_value(item, rest)),
+            _ => false,
+        }
+    }
+
+    /// 改写 CSS 中的 `url(...)` 与 `@import "..."`
+    pub fn rewrite_css(&self, css: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(css.len() + css.len() / 8);
+        let mut i = 0;
+
+        while i < css.len() {
+            let rest = &css[i..];
+            let (prefix_len, is_import) = if starts_with_ignore_case(rest, b"url(") {
+                (4, false)
+            } else if starts_with_ignore_case(rest, b"@import") {
+                (7, true)
+            } else {
+                out.push(css[i]);
+                i += 1;
+                continue;
+            };
+
+            let mut j = i + prefix_len;
+            while j < css.len() && css[j].is_ascii_whitespace() {
+                j += 1;
+            }
+
+            let (quote, value_start, value_end) = match css.get(j) {
+                Some(&q @ (b'"' | b'\'')) => {
+                    let end = find(&css[j + 1..], &[q]).map_or(css.len(), |e| j + 1 + e);
+                    (Some(q), j + 1, end)
+                }
+                // `@import url(...)` 交给下一轮的 `url(` 处理
+                _ if is_import => {
+                    out.extend_from_slice(&css[i..j]);
+                    i = j;
+                    continue;
+                }
+                _ => {
+                    let end = find(&css[j..], b")").map_or(css.len(), |e| j + e);
+                    let mut value_end = end;
+                    while value_end > j && css[value_end - 1].is_ascii_whitespace() {
+                        value_end -= 1;
+                    }
+                    (None, j, value_end)
+                }
+            };
+
+            out.extend_from_slice(&css[i..value_start]);
+            let value = &css[value_start..value_end];
+            let rewritten = std::str::from_utf8(value)
+                .ok()
+                .and_then(|v| self.rewrite_url(v));
+            out.extend_from_slice(rewritten.as_ref().map_or(value, |v| v.as_bytes()));
+            i = value_end;
+            if quote.is_some() && i < css.len() {
+                out.push(css[i]);
+                i += 1;
+            }
+        }
+
+        out
+    }
+}
+
+/// 按 `Content-Encoding` 解压，解压后超过 `max_bytes` 或编码不支持时返回 None
+fn decode_body(encoding: Option<&str>, body: &[u8], max_bytes: usize) -> Option<Vec<u8>> {
+    let limit = max_bytes as u64 + 1;
+    let mut decoded = Vec::new();
+    let result = match encoding {
+        None | Some("identity") => return (body.len() <= max_bytes).then(|| body.to_vec()),
+        Some("gzip" | "x-gzip") => flate2::read::GzDecoder::new(body)
+            .take(limit)
+            .read_to_end(&mut decoded),
+        // 多数服务端的 deflate 带 zlib 头，少数直接发送原始 deflate 数据
+        Some("deflate") => flate2::read::ZlibDecoder::new(body)
+            .take(limit)
+            .read_to_end(&mut decoded)
+            .or_else(|_| {
+                decoded.clear();
+                flate2::read::DeflateDecoder::new(body)
+                    .take(limit)
+                    .read_to_end(&mut decoded)
+            }),
+        Some(_) => return None,
+    };
+    (result.is_ok() && decoded.len() <= max_bytes).then_some(decoded)
+}
+
+/// 缓冲 HTML/CSS（以及配置了 `rewrite_json_urls` 时的 JSON）响应体并改写其中的链接
+///
+/// 响应体超过 `max_bytes`、编码无法解压、JSON 无法解析或不是需要改写的类型时原样转发；
+/// 改写后去掉 `Content-Encoding` 并更新 `Content-Length`
+pub(crate) async fn rewrite_response(
+    response: &mut UpstreamResponse,
+    response_headers: &mut HeaderMap,
+    rewriter: &mut LinkRewriter<'_>,
+    max_bytes: usize,
+) -> Result<(), BoxError> {
+    let Some(kind) =
+        ContentKind::from_headers(&response.headers).filter(|kind| rewriter.handles(*kind))
+    else {
+        return Ok(());
+    };
+    let encoding = match response.headers.get("content-encoding") {
+        Some(value) => match value.to_str() {
+            Ok(value) => Some(value.trim().to_ascii_lowercase()),
+            Err(_) => return Ok(()),
+        },
+        None => None,
+    };
+    if !matches!(
+        encoding.as_deref(),
+        None | Some("identity" | "gzip" | "x-gzip" | "deflate")
+    ) {
+        return Ok(());
+    }
+
+    let mut body = std::mem::replace(&mut response.body, Box::pin(futures_util::stream::empty()));
+    let mut buffered: Vec<Bytes> = Vec::new();
+    let mut size = 0;
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+        size += chunk.len();
+        buffered.push(chunk);
+        if size > max_bytes {
+            // 超过上限：已读取的部分与剩余响应体拼接后原样转发
+            let prefix = futures_util::stream::iter(buffered.into_iter().map(Ok));
+            response.body = Box::pin(prefix.chain(body));
+            return Ok(());
+        }
+    }
+    let raw = buffered.concat();
+
+    let Some(decoded) = decode_body(encoding.as_deref(), &raw, max_bytes) else {
+        response.body = Box::pin(futures_util::stream::once(
+            async move { Ok(Bytes::from(raw)) },
+        ));
+        return Ok(());
+    };
+
+    let rewritten = Bytes::from(match kind {
+        ContentKind::Html => rewriter.rewrite_html(&decoded),
+        ContentKind::Css => rewriter.rewrite_css(&decoded),
+        ContentKind::Json => match rewriter.rewrite_json(&decoded) {
+            Some(rewritten) => rewritten,
+            None => {
+                response.body =
+                    Box::pin(futures_util::stream::once(
+                        async move { Ok(Bytes::from(raw)) },
+                    ));
+                return Ok(());
+            }
+        },
+    });
+
+    response_headers.remove("content-encoding");
+    response_headers.insert("content-length", HeaderValue::from(rewritten.len()));
+    response.body = Box::pin(futures_util::stream::once(async move { Ok(rewritten) }));
+    Ok(())
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn find_ignore_case(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|w| w.eq_ignore_ascii_case(needle))
+}
+
+fn starts_with_ignore_case(haystack: &[u8], prefix: &[u8]) -> bool {
+    haystack.len() >= prefix.len() && haystack[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn rewriter(base: &str) -> LinkRewriter<'static> {
+        LinkRewriter::new(Url::parse(base).unwrap(), "", ProxyUrlStyle::Query)
+    }
+
+    fn proxied(url: &str) -> String {
+        format!("/proxy?url={}", urlencoding::encode(url))
+    }
+
+    fn html(base: &str, input: &str) -> String {
+        String::from_utf8(rewriter(base).rewrite_html(input.as_bytes())).unwrap()
+    }
+
+    #[test]
+    fn test_rewrite_url_forms() {
+        let r = rewriter("https://example.com/docs/page.html");
+        // 相对路径基于页面所在目录
+        assert_eq!(
+            r.rewrite_url("img/a.png").unwrap(),
+            proxied("https://example.com/docs/img/a.png")
+        );
+        assert_eq!(
+            r.rewrite_url("../style.css").unwrap(),
+            proxied("https://example.com/style.css")
+        );
+        assert_eq!(
+            r.rewrite_url("/login?next=/docs").unwrap(),
+            proxied("https://example.com/login?next=/docs")
+        );
+        assert_eq!(
+            r.rewrite_url("http://cdn.example.net/lib.js").unwrap(),
+            proxied("http://cdn.example.net/lib.js")
+        );
+        // 协议相对地址沿用页面的协议
+        assert_eq!(
+            r.rewrite_url("//cdn.example.net/lib.js").unwrap(),
+            proxied("https://cdn.example.net/lib.js")
+        );
+
+        // 已经是代理地址、锚点与非 HTTP 链接不改写
+        assert!(r
+            .rewrite_url("/proxy?url=https%3A%2F%2Fexample.com%2F")
+            .is_none());
+        assert!(r
+            .rewrite_url("/proxy/https%3A%2F%2Fexample.com%2F")
+            .is_none());
+        assert!(r.rewrite_url("#top").is_none());
+        assert!(r.rewrite_url("javascript:void(0)").is_none());
+        assert!(r.rewrite_url("data:image/png;base64,AAAA").is_none());
+        assert!(r.rewrite_url("mailto:a@example.com").is_none());
+    }
+
+    #[test]
+    fn test_rewrite_html() {
+        let base = "https://example.com/docs/";
+        let output = html(
+            base,
+            r##"<!DOCTYPE html><a href="a.html?x=1&amp;y=2" class=link>A</a>
+<img src='/logo.png' srcset="s.png 1x, //cdn.example.net/l.png 2x">
+<form action=submit method="post"></form><a href="#top">top</a>
+<!-- <a href="comment.html"> --><script>var s = "<a href='js.html'>";</script>
+<div style="background: url(bg.png)"></div><style>body { background: url("/bg.png") }</style>"##,
+        );
+
+        assert!(output.starts_with("<!DOCTYPE html>"));
+        assert!(output.contains(&format!(
+            r#"<a href="{}" class=link>A</a>"#,
+            proxied("https://example.com/docs/a.html?x=1&y=2")
+        )));
+        assert!(output.contains(&format!(
+            "<img src='{}'",
+            proxied("https://example.com/logo.png")
+        )));
+        assert!(output.contains(&format!(
+            r#"srcset="{} 1x, {} 2x""#,
+            proxied("https://example.com/docs/s.png"),
+            proxied("https://cdn.example.net/l.png")
+        )));
+        assert!(output.contains(&format!(
+            r#"<form action={} method="post">"#,
+            proxied("https://example.com/docs/submit")
+        )));
+        assert!(output.contains(r##"<a href="#top">"##));
+        assert!(output.contains(r#"<!-- <a href="comment.html"> -->"#));
+        assert!(output.contains(r#"var s = "<a href='js.html'>";"#));
+        assert!(output.contains(&format!(
+            r#"style="background: url({})""#,
+            proxied("https://example.com/docs/bg.png")
+        )));
+        assert!(output.contains(&format!(
+            r#"background: url("{}")"#,
+            proxied("https://example.com/bg.png")
+        )));
+    }
+
+    #[test]
+    fn test_rewrite_html_base_tag() {
+        let output = html(
+            "https://example.com/a/page.html",
+            r#"<head><base href="https://static.example.com/v2/"></head><img src="x.png"/>"#,
+        );
+        assert!(output.contains(&format!(
+            r#"<img src="{}"/>"#,
+            proxied("https://static.example.com/v2/x.png")
+        )));
+    }
+
+    #[test]
+    fn test_rewrite_css() {
+        let r = rewriter("https://example.com/css/site.css");
+        let css = r#"@import "reset.css"; @import url(theme.css);
+.a { background: URL( '../img/a.png' ) } .b { background: url(data:image/png;base64,AA) }"#;
+        let output = String::from_utf8(r.rewrite_css(css.as_bytes())).unwrap();
+
+        assert!(output.contains(&format!(
+            r#"@import "{}";"#,
+            proxied("https://example.com/css/reset.css")
+        )));
+        assert!(output.contains(&format!(
+            "@import url({});",
+            proxied("https://example.com/css/theme.css")
+        )));
+        assert!(output.contains(&format!(
+            "URL( '{}' )",
+            proxied("https://example.com/img/a.png")
+        )));
+        assert!(output.contains("url(data:image/png;base64,AA)"));
+    }
+
+    fn upstream(content_type: &str, encoding: Option<&str>, body: Vec<u8>) -> UpstreamResponse {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("content-type", content_type.parse().unwrap());
+        if let Some(encoding) = encoding {
+            headers.insert("content-encoding", encoding.parse().unwrap());
+        }
+        let chunks: Vec<Result<Bytes, BoxError>> = body
+            .chunks(8)
+            .map(|c| Ok(Bytes::copy_from_slice(c)))
+            .collect();
+        UpstreamResponse {
+            status: 200,
+            headers,
+            url: "https://example.com/".to_string(),
+            version: reqwest::Version::HTTP_11,
+            trailers: Default::default(),
+            local_address: None,
+            body: Box::pin(futures_util::stream::iter(chunks)),
+        }
+    }
+
+    #[test]
+    fn test_rewrite_json() {
+        let pointers = [
+            "/next".to_string(),
+            "/links/*/href".to_string(),
+            "/items/1/url".to_string(),
+            "/a~1b".to_string(),
+        ];
+        let r = rewriter("https://api.example.com/v1/items").with_json_pointers(&pointers);
+        let json = |input: &str| {
+            r.rewrite_json(input.as_bytes())
+                .map(|out| String::from_utf8(out).unwrap())
+        };
+
+        assert_eq!(
+            json(r#"{"z":1,"next":"https://api.example.com/v1/items?page=2","a":null}"#).unwrap(),
+            format!(
+                r#"{{"z":1,"next":"{}","a":null}}"#,
+                proxied("https://api.example.com/v1/items?page=2")
+            )
+        );
+        assert_eq!(
+            json(r#"{"links":[{"href":"http://a.example/"},{"href":"http://b.example/"}]}"#)
+                .unwrap(),
+            format!(
+                r#"{{"links":[{{"href":"{}"}},{{"href":"{}"}}]}}"#,
+                proxied("http://a.example/"),
+                proxied("http://b.example/")
+            )
+        );
+        assert_eq!(
+            json(r#"{"links":{"self":{"href":"http://a.example/"}},"items":[{},{"url":"http://b.example/"}],"a/b":"http://c.example/"}"#).unwrap(),
+            format!(
+                r#"{{"links":{{"self":{{"href":"{}"}}}},"items":[{{}},{{"url":"{}"}}],"a/b":"{}"}}"#,
+                proxied("http://a.example/"),
+                proxied("http://b.example/"),
+                proxied("http://c.example/")
+            )
+        );
+
+        // 相对地址、非 http(s) 地址、非字符串与不存在的位置不改写，原内容（包括空白）保持不变
+        for input in [
+            r#"{ "next": "/v1/items?page=2" }"#,
+            r#"{ "next": "ftp://example.com/" }"#,
+            r#"{ "next": 2, "links": "x" }"#,
+            r#"[1, 2]"#,
+        ] {
+            assert_eq!(json(input).unwrap(), input);
+        }
+        // 不是合法 JSON
+        assert_eq!(json(r#"{"next": "https://example.com/""#), None);
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_response_json() {
+        let pointers = ["/next".to_string()];
+        let mut r = rewriter("https://example.com/")
+            .with_markup(false)
+            .with_json_pointers(&pointers);
+        let mut headers = HeaderMap::new();
+
+        let body = r#"{"next":"https://example.com/?page=2"}"#;
+        let mut response = upstream("application/vnd.api+json", None, body.into());
+        rewrite_response(&mut response, &mut headers, &mut r, 1024)
+            .await
+            .unwrap();
+        assert_eq!(
+            collect(response).await,
+            format!(r#"{{"next":"{}"}}"#, proxied("https://example.com/?page=2"))
+        );
+
+        // 无法解析的 JSON 与未开启改写的 HTML 原样转发
+        let mut headers = HeaderMap::new();
+        let malformed = r#"{"next":"https://example.com/"#;
+        let mut response = upstream("application/json", None, malformed.into());
+        rewrite_response(&mut response, &mut headers, &mut r, 1024)
+            .await
+            .unwrap();
+        assert_eq!(collect(response).await, malformed);
+        assert!(headers.get("content-length").is_none());
+
+        let page = r#"<a href="/next">next</a>"#;
+        let mut response = upstream("text/html", None, page.into());
+        rewrite_response(&mut response, &mut headers, &mut r, 1024)
+            .await
+            .unwrap();
+        assert_eq!(collect(response).await, page);
+    }
+
+    async fn collect(response: UpstreamResponse) -> String {
+        let chunks: Vec<Bytes> = response.body.map(|c| c.unwrap()).collect().await;
+        String::from_utf8(chunks.concat()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_response_decompresses() {
+        let page = r#"<a href="/next">next</a>"#;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(page.as_bytes()).unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        let mut response = upstream("text/html; charset=utf-8", Some("gzip"), gzipped);
+        let mut headers = HeaderMap::new();
+        headers.insert("content-encoding", HeaderValue::from_static("gzip"));
+        headers.insert("content-length", HeaderValue::from_static("999"));
+        let mut r = rewriter(&response.url.clone());
+        rewrite_response(&mut response, &mut headers, &mut r, 1024)
+            .await
+            .unwrap();
+
+        let body = collect(response).await;
+        assert_eq!(
+            body,
+            format!(
+                r#"<a href="{}">next</a>"#,
+                proxied("https://example.com/next")
+            )
+        );
+        assert!(headers.get("content-encoding").is_none());
+        assert_eq!(
+            headers.get("content-length").unwrap(),
+            &body.len().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_response_passthrough() {
+        let page = r#"<a href="/next">next</a>"#;
+        let mut r = rewriter("https://example.com/");
+
+        // 超过大小上限
+        let mut response = upstream("text/html", None, page.as_bytes().to_vec());
+        let mut headers = HeaderMap::new();
+        rewrite_response(&mut response, &mut headers, &mut r, 10)
+            .await
+            .unwrap();
+        assert_eq!(collect(response).await, page);
+
+        // 非 HTML/CSS
+        let mut response = upstream("application/json", None, page.as_bytes().to_vec());
+        rewrite_response(&mut response, &mut headers, &mut r, 1024)
+            .await
+            .unwrap();
+        assert_eq!(collect(response).await, page);
+
+        // gRPC-Web 的 JSON 消息帧不按 JSON 改写
+        let response = upstream("application/grpc-web+json", None, Vec::new());
+        assert_eq!(ContentKind::from_headers(&response.headers), None);
+
+        // 无法解压的编码
+        let mut response = upstream("text/html", Some("br"), page.as_bytes().to_vec());
+        rewrite_response(&mut response, &mut headers, &mut r, 1024)
+            .await
+            .unwrap();
+        assert_eq!(collect(response).await, page);
+        assert!(headers.get("content-length").is_none());
+    }
+
+    #[test]
+    fn test_restrict_accept_encoding() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            "accept-encoding",
+            "gzip, deflate, br, zstd".parse().unwrap(),
+        );
+        restrict_accept_encoding(&mut headers);
+        assert_eq!(headers.get("accept-encoding").unwrap(), "gzip, deflate");
+
+        headers.insert("accept-encoding", "br".parse().unwrap());
+        restrict_accept_encoding(&mut headers);
+        assert!(headers.get("accept-encoding").is_none());
+    }
+}
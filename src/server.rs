@@ -0,0 +1,413 @@
+use crate::config::ServerConfig;
+use crate::proxy::BoxError;
+use axum::extract::{ConnectInfo, Request};
+use axum::response::Response;
+use axum::{Extension, Router};
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+#[cfg(feature = "h2c")]
+use hyper_util::rt::TokioExecutor;
+use hyper_util::rt::{TokioIo, TokioTimer};
+use hyper_util::service::TowerToHyperService;
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::net::{TcpListener, TcpStream};
+use tower::Layer;
+use tracing::{debug, error};
+
+/// HTTP/1.1 读缓冲在 `max_header_bytes` 之外为请求行与分隔符预留的字节数
+const REQUEST_LINE_SLACK: usize = 8192;
+
+/// 监听端：TCP 端口，或 `listening` 为 `unix:/path` 时的 Unix socket
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// 按 `listening` 绑定监听端，Unix socket 会按 `mode` 设置文件权限
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    pub async fn bind(listening: &str, mode: Option<u32>) -> io::Result<Self> {
+        match listening.strip_prefix("unix:") {
+            #[cfg(unix)]
+            Some(path) => bind_unix(std::path::Path::new(path), mode).map(Self::Unix),
+            #[cfg(not(unix))]
+            Some(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "当前平台不支持 unix socket",
+            )),
+            None => TcpListener::bind(listening).await.map(Self::Tcp),
+        }
+    }
+
+    /// TCP 监听端实际绑定的地址，`listening` 的端口为 0 时由系统分配
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Self::Tcp(listener) => listener.local_addr().ok(),
+            #[cfg(unix)]
+            Self::Unix(_) => None,
+        }
+    }
+}
+
+/// 绑定 Unix socket：上次未清理的 socket 文件先删除，仍有进程在监听时报错
+#[cfg(unix)]
+fn bind_unix(path: &std::path::Path, mode: Option<u32>) -> io::Result<UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} 已存在且不是 socket 文件", path.display()),
+            ));
+        }
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} 已有其他进程在监听", path.display()),
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    if let Some(mode) = mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    Ok(listener)
+}
+
+/// 接受连接并按 `server` 配置提供服务，与 `axum::serve` 相同，但可以调整 hyper 的连接参数
+pub async fn serve(listener: Listener, app: Router, config: &ServerConfig) -> io::Result<()> {
+    let builder = Arc::new(ConnectionBuilder::new(config));
+    loop {
+        let accepted = match &listener {
+            Listener::Tcp(listener) => listener.accept().await.map(|(stream, peer)| {
+                debug!("新连接: {}", peer);
+                configure_tcp(&stream, config);
+                // 与 `into_make_service_with_connect_info` 相同，处理函数可以取得对端地址
+                let service = Extension(ConnectInfo(peer)).layer(app.clone());
+                spawn_connection(&builder, service, stream);
+            }),
+            // Unix socket 没有对端 IP，记录对端进程的 uid/pid
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener.accept().await.map(|(stream, _)| {
+                match stream.peer_cred() {
+                    Ok(cred) => debug!("新连接: unix uid={} pid={:?}", cred.uid(), cred.pid()),
+                    Err(_) => debug!("新连接: unix"),
+                }
+                spawn_connection(&builder, app.clone(), stream);
+            }),
+        };
+        match accepted {
+            Ok(()) => {}
+            Err(e) if is_connection_error(&e) => {}
+            Err(e) => {
+                // 多为文件描述符耗尽，稍后重试
+                error!("接受连接失败: {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+/// 连接上提供的服务：路由本身，或附加了对端地址的路由
+trait AppService:
+    tower::Service<Request<Incoming>, Response = Response, Error = Infallible> + Clone + Send + 'static
+{
+}
+
+impl<S> AppService for S where
+    S: tower::Service<Request<Incoming>, Response = Response, Error = Infallible>
+        + Clone
+        + Send
+        + 'static
+{
+}
+
+/// 按配置设置已接受的 TCP 连接
+fn configure_tcp(stream: &TcpStream, config: &ServerConfig) {
+    if let Err(e) = stream.set_nodelay(config.tcp_nodelay) {
+        debug!("设置 TCP_NODELAY 失败: {}", e);
+    }
+}
+
+fn spawn_connection<I, S>(builder: &Arc<ConnectionBuilder>, app: S, stream: I)
+where
+    I: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    S: AppService,
+    S::Future: Send + 'static,
+{
+    let builder = builder.clone();
+    let service = TowerToHyperService::new(app);
+    tokio::spawn(async move {
+        // 客户端未发送请求就断开时会返回错误，忽略
+        let _ = builder.serve(TokioIo::new(stream), service).await;
+    });
+}
+
+/// 等待 Ctrl-C 或 SIGTERM，用于退出前清理
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+/// 收到 SIGHUP 时让日志文件（`log.file`、`log.access_log_file`、`audit.file`）在下一次写入前按路径
+/// 重新打开，配合 logrotate 等外部工具移走文件后使用；非 Unix 平台不做处理
+pub async fn reopen_logs_on_hangup() {
+    #[cfg(unix)]
+    if let Ok(mut signal) = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        while signal.recv().await.is_some() {
+            crate::log_file::request_reopen();
+            tracing::info!("收到 SIGHUP，重新打开日志文件");
+        }
+    }
+}
+
+/// 按配置设置连接参数；未开启 h2c 时只使用 HTTP/1.1，避免自动识别 HTTP/2 连接前言
+enum ConnectionBuilder {
+    Http1(http1::Builder),
+    #[cfg(feature = "h2c")]
+    Auto(hyper_util::server::conn::auto::Builder<TokioExecutor>),
+}
+
+/// 在 `http1::Builder` 或 auto 构造器的 `http1()` 上应用 HTTP/1.1 设置
+macro_rules! configure_http1 {
+    ($http1:expr, $config:expr) => {{
+        let http1 = $http1;
+        http1.keep_alive($config.http1_keep_alive);
+        if let Some(secs) = $config.header_read_timeout_secs {
+            http1
+                .timer(TokioTimer::new())
+                .header_read_timeout(Duration::from_secs(secs as u64));
+        }
+        // 读缓冲还要容纳请求行与分隔符，精确的大小与数量检查在 `app_middleware` 中进行
+        http1
+            .max_buf_size($config.max_header_bytes as usize + REQUEST_LINE_SLACK)
+            .max_headers($config.max_header_count as usize);
+    }};
+}
+
+impl ConnectionBuilder {
+    fn new(config: &ServerConfig) -> Self {
+        #[cfg(feature = "h2c")]
+        if config.h2c_enabled() {
+            let mut builder = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new());
+            configure_http1!(&mut builder.http1(), config);
+            let mut http2 = builder.http2();
+            http2.timer(TokioTimer::new());
+            if let Some(max) = config.http2_max_concurrent_streams {
+                http2.max_concurrent_streams(max as u32);
+            }
+            if let Some(secs) = config.http2_keep_alive_interval_secs {
+                http2.keep_alive_interval(Duration::from_secs(secs as u64));
+                if let Some(secs) = config.http2_keep_alive_timeout_secs {
+                    http2.keep_alive_timeout(Duration::from_secs(secs as u64));
+                }
+            }
+            // HTTP/2 的头部列表大小按每个头部的名称与值长度再加 32 字节计算
+            http2.max_header_list_size(
+                (config.max_header_bytes + 32 * config.max_header_count) as u32,
+            );
+            return Self::Auto(builder);
+        }
+
+        let mut builder = http1::Builder::new();
+        configure_http1!(&mut builder, config);
+        Self::Http1(builder)
+    }
+
+    /// 处理一个连接，支持 CONNECT 等协议升级
+    async fn serve<I, S>(
+        &self,
+        io: TokioIo<I>,
+        service: TowerToHyperService<S>,
+    ) -> Result<(), BoxError>
+    where
+        I: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+        S: AppService,
+        S::Future: Send + 'static,
+    {
+        match self {
+            Self::Http1(builder) => Ok(builder
+                .serve_connection(io, service)
+                .with_upgrades()
+                .await?),
+            #[cfg(feature = "h2c")]
+            Self::Auto(builder) => builder.serve_connection_with_upgrades(io, service).await,
+        }
+    }
+}
+
+fn is_connection_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+
+    async fn spawn_server(config: ServerConfig) -> String {
+        let app = Router::new().route("/", get(|| async { "ok" }));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { serve(Listener::Tcp(listener), app, &config).await });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_bind_ephemeral_port() {
+        let listener = Listener::bind("127.0.0.1:0", None).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        assert!(addr.ip().is_loopback());
+        assert_ne!(addr.port(), 0);
+
+        let app = Router::new().route("/", get(|| async { "ok" }));
+        tokio::spawn(async move { serve(listener, app, &ServerConfig::default()).await });
+        let response = reqwest::get(format!("http://{}/", addr)).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "ok");
+    }
+
+    #[tokio::test]
+    async fn test_connect_info() {
+        let app = Router::new().route(
+            "/",
+            get(|ConnectInfo(peer): ConnectInfo<SocketAddr>| async move { peer.ip().to_string() }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            async move { serve(Listener::Tcp(listener), app, &ServerConfig::default()).await },
+        );
+        let response = reqwest::get(format!("http://{}/", addr)).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "127.0.0.1");
+    }
+
+    #[tokio::test]
+    async fn test_max_header_bytes() {
+        let url = spawn_server(ServerConfig {
+            max_header_bytes: 8192,
+            ..ServerConfig::default()
+        })
+        .await;
+        let client = reqwest::Client::new();
+
+        let response = client
+            .get(&url)
+            .header("x-small", "a")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().await.unwrap(), "ok");
+
+        let response = client
+            .get(&url)
+            .header("x-large", "a".repeat(64 * 1024))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 431);
+
+        let url = spawn_server(ServerConfig::default()).await;
+        let response = client
+            .get(&url)
+            .header("x-large", "a".repeat(32 * 1024))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_http1_keep_alive_disabled() {
+        let url = spawn_server(ServerConfig {
+            http1_keep_alive: false,
+            ..ServerConfig::default()
+        })
+        .await;
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(response.headers()["connection"], "close");
+    }
+
+    #[tokio::test]
+    async fn test_tcp_nodelay() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        for tcp_nodelay in [true, false] {
+            let _client = TcpStream::connect(addr).await.unwrap();
+            let (stream, _) = listener.accept().await.unwrap();
+            configure_tcp(
+                &stream,
+                &ServerConfig {
+                    tcp_nodelay,
+                    ..ServerConfig::default()
+                },
+            );
+            assert_eq!(stream.nodelay().unwrap(), tcp_nodelay);
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_listener() {
+        use std::os::unix::fs::PermissionsExt;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let path = std::env::temp_dir().join(format!("rha-listen-{}.sock", std::process::id()));
+        let listening = format!("unix:{}", path.display());
+
+        // 残留的 socket 文件在绑定前删除
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        let listener = Listener::bind(&listening, Some(0o600)).await.unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // 仍在监听时不能重复绑定
+        assert!(Listener::bind(&listening, None).await.is_err());
+
+        let app = Router::new().route("/", get(|| async { "ok" }));
+        tokio::spawn(async move { serve(listener, app, &ServerConfig::default()).await });
+        let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: agent\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("ok"), "{}", response);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,310 @@
+//! 签名的代理地址（`url_signing_key`）：不便携带 Bearer Token 的客户端（如 `<video>` 标签）
+//! 使用 `POST /sign` 生成的 `/proxy?url=...&exp=...&sig=...` 直接访问
+//!
+//! 签名为 `HMAC-SHA256(url_signing_key, canonical)` 的 base64url（无填充）编码，其中
+//!
+//! ```text
+//! canonical = METHOD + "\n" + exp + "\n" + url
+//! ```
+//!
+//! - `METHOD`：签名时指定的方法（大写，如 `GET`），未指定时为空字符串；指定后查询串带上 `method`
+//! - `exp`：过期时间，Unix 时间戳（秒）的十进制表示，与查询串中的 `exp` 完全一致
+//! - `url`：目标地址，即查询串中 `url` 参数百分号解码后的原始字符串，不做任何规范化
+
+use crate::proxy::{build_proxy_url, AppError, ProxyQuery, ProxyUrlStyle};
+use crate::AppConfig;
+use axum::extract::{Query, Request, State};
+use axum::http::Method;
+use axum::Json;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// `POST /sign` 未指定有效期时签名地址的有效期（秒）
+const DEFAULT_TTL_SECS: u64 = 3600;
+
+/// 签名校验通过的请求，由认证中间件放入请求扩展，`/proxy` 据此只允许访问签名时的目标地址
+#[derive(Debug, Clone)]
+pub(crate) struct SignedUrl {
+    pub url: String,
+}
+
+/// 签名地址校验失败的原因，`code` 写入 401 响应的 `code` 字段
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignedUrlError {
+    Expired,
+    /// 签名缺失、格式错误或与地址不符（地址被改动）
+    InvalidSignature,
+    /// 请求方法与签名时指定的方法不符，或 `signed_url_read_only` 时不是 GET/HEAD
+    MethodNotAllowed,
+}
+
+impl SignedUrlError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            SignedUrlError::Expired => "signed_url_expired",
+            SignedUrlError::InvalidSignature => "signed_url_invalid",
+            SignedUrlError::MethodNotAllowed => "signed_url_method_not_allowed",
+        }
+    }
+}
+
+impl std::fmt::Display for SignedUrlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignedUrlError::Expired => f.write_str("签名地址已过期"),
+            SignedUrlError::InvalidSignature => f.write_str("签名无效"),
+            SignedUrlError::MethodNotAllowed => f.write_str("签名地址不允许该请求方法"),
+        }
+    }
+}
+
+/// 签名所用的规范化字符串，见模块文档
+pub fn canonical(url: &str, exp: u64, method: Option<&str>) -> String {
+    format!(
+        "{}\n{}\n{}",
+        method.unwrap_or_default().to_ascii_uppercase(),
+        exp,
+        url
+    )
+}
+
+fn mac(key: &str, url: &str, exp: u64, method: Option<&str>) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key.as_bytes()).expect("HMAC 接受任意长度的密钥");
+    mac.update(canonical(url, exp, method).as_bytes());
+    mac
+}
+
+pub fn sign(key: &str, url: &str, exp: u64, method: Option<&str>) -> String {
+    URL_SAFE_NO_PAD.encode(mac(key, url, exp, method).finalize().into_bytes())
+}
+
+/// 依次校验签名（常量时间比较）、过期时间与请求方法；`now` 为当前 Unix 时间戳（秒）
+#[allow(clippy::too_many_arguments)]
+pub fn verify(
+    key: &str,
+    url: &str,
+    exp: u64,
+    method: Option<&str>,
+    sig: &str,
+    request_method: &Method,
+    read_only: bool,
+    now: u64,
+) -> Result<(), SignedUrlError> {
+    let sig = URL_SAFE_NO_PAD
+        .decode(sig.trim())
+        .map_err(|_| SignedUrlError::InvalidSignature)?;
+    mac(key, url, exp, method)
+        .verify_slice(&sig)
+        .map_err(|_| SignedUrlError::InvalidSignature)?;
+    if now > exp {
+        return Err(SignedUrlError::Expired);
+    }
+    // 签名为 GET 的地址同样可以 HEAD
+    let allowed = match method {
+        Some(method) => {
+            request_method.as_str().eq_ignore_ascii_case(method)
+                || (method.eq_ignore_ascii_case("GET") && request_method == Method::HEAD)
+        }
+        None => true,
+    };
+    if !allowed || (read_only && !is_read_only(request_method)) {
+        return Err(SignedUrlError::MethodNotAllowed);
+    }
+    Ok(())
+}
+
+fn is_read_only(method: &Method) -> bool {
+    method == Method::GET || method == Method::HEAD
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// 认证中间件在 Authorization 认证失败后调用：未配置 `url_signing_key`、不是 `/proxy` 或查询串中
+/// 没有 `sig` 时返回 None
+pub(crate) fn verify_request(
+    config: &AppConfig,
+    request: &Request,
+) -> Option<Result<SignedUrl, SignedUrlError>> {
+    let settings = &config.state.config;
+    let key = settings.url_signing_key.as_deref()?;
+    if request.uri().path() != "/proxy" {
+        return None;
+    }
+    let Query(query) = Query::<ProxyQuery>::try_from_uri(request.uri()).ok()?;
+    let sig = query.sig.as_deref()?;
+    let (Some(url), Some(exp)) = (query.url.as_deref(), query.exp) else {
+        return Some(Err(SignedUrlError::InvalidSignature));
+    };
+    Some(
+        verify(
+            key,
+            url,
+            exp,
+            query.method.as_deref(),
+            sig,
+            request.method(),
+            settings.signed_url_read_only,
+            unix_now(),
+        )
+        .map(|()| SignedUrl {
+            url: url.to_string(),
+        }),
+    )
+}
+
+/// 查询串中的 `sig`，认证失败时写入审计日志
+pub(crate) fn request_signature(request: &Request) -> Option<String> {
+    let Query(query) = Query::<ProxyQuery>::try_from_uri(request.uri()).ok()?;
+    query.sig
+}
+
+/// `POST /sign` 的请求体
+#[derive(Debug, Deserialize)]
+pub(crate) struct SignRequest {
+    url: String,
+    /// 有效期（秒），默认 1 小时
+    ttl_secs: Option<u64>,
+    /// 限定请求方法，如 `GET`
+    method: Option<String>,
+}
+
+/// `POST /sign`：为目标地址生成签名的代理地址（含 `base_path`）
+pub(crate) async fn sign_handler(
+    State(config): State<Arc<AppConfig>>,
+    Json(request): Json<SignRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let settings = &config.state.config;
+    let key = settings
+        .url_signing_key
+        .as_deref()
+        .ok_or_else(|| AppError::BadRequest("未配置 url_signing_key".to_string()))?;
+    if request.url.trim().is_empty() {
+        return Err(AppError::BadRequest("url 不能为空".to_string()));
+    }
+    // 签名的地址不经 JWT 认证，签名时就要按当前 token 的 allowed_hosts 检查；签名使用原始地址
+    let mut target = request.url.clone();
+    config.state.prepare_target(&mut target)?;
+    let ttl = request.ttl_secs.unwrap_or(DEFAULT_TTL_SECS);
+    if ttl == 0 {
+        return Err(AppError::BadRequest("ttl_secs 必须大于 0".to_string()));
+    }
+    let method = match request.method.as_deref().map(str::trim) {
+        Some(method) => {
+            let method =
+                Method::from_bytes(method.to_ascii_uppercase().as_bytes()).map_err(|_| {
+                    AppError::BadRequest(format!("method: 无效的请求方法 {:?}", method))
+                })?;
+            if settings.signed_url_read_only && !is_read_only(&method) {
+                return Err(AppError::BadRequest(format!(
+                    "method: 已开启 signed_url_read_only，只能签名 GET/HEAD，收到 {}",
+                    method
+                )));
+            }
+            Some(method.to_string())
+        }
+        None => None,
+    };
+
+    let exp = unix_now().saturating_add(ttl);
+    let sig = sign(key, &request.url, exp, method.as_deref());
+    let mut url = format!(
+        "{}&exp={}&sig={}",
+        build_proxy_url(&config.state.base_path, &request.url, ProxyUrlStyle::Query),
+        exp,
+        sig
+    );
+    if let Some(method) = &method {
+        url.push_str(&format!("&method={}", method));
+    }
+    Ok(Json(serde_json::json!({ "url": url, "exp": exp })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "signing-key";
+    const URL: &str = "https://cdn.example.com/video.mp4?quality=hd&t=10";
+
+    #[test]
+    fn test_canonical() {
+        assert_eq!(
+            canonical(URL, 1735689600, None),
+            "\n1735689600\nhttps://cdn.example.com/video.mp4?quality=hd&t=10"
+        );
+        assert_eq!(canonical(URL, 1, Some("get")), format!("GET\n1\n{}", URL));
+        // 与其他 HMAC-SHA256 实现交叉验证
+        assert_eq!(
+            sign(KEY, "https://example.com/", 1735689600, None),
+            "-1OHSPEYAy84R_oTBcoNlLtiEpzoK8FbifYRSj62qng"
+        );
+    }
+
+    #[test]
+    fn test_verify() {
+        let exp = 2_000_000_000;
+        let now = exp - 60;
+        let sig = sign(KEY, URL, exp, None);
+        assert_eq!(sig.len(), 43);
+        let check = |url: &str, exp: u64, method: Option<&str>, sig: &str, request: Method| {
+            verify(KEY, url, exp, method, sig, &request, true, now)
+        };
+
+        assert_eq!(check(URL, exp, None, &sig, Method::GET), Ok(()));
+        assert_eq!(check(URL, exp, None, &sig, Method::HEAD), Ok(()));
+        // 改动地址、过期时间或签名
+        let invalid = Err(SignedUrlError::InvalidSignature);
+        assert_eq!(
+            check(
+                "https://cdn.example.com/other.mp4",
+                exp,
+                None,
+                &sig,
+                Method::GET
+            ),
+            invalid
+        );
+        assert_eq!(check(URL, exp + 3600, None, &sig, Method::GET), invalid);
+        assert_eq!(check(URL, exp, Some("GET"), &sig, Method::GET), invalid);
+        assert_eq!(check(URL, exp, None, &sig[1..], Method::GET), invalid);
+        assert_eq!(check(URL, exp, None, "not base64!", Method::GET), invalid);
+        assert_eq!(
+            verify(KEY, URL, exp, None, &sig, &Method::GET, true, exp + 1),
+            Err(SignedUrlError::Expired)
+        );
+        assert_eq!(
+            verify("other-key", URL, exp, None, &sig, &Method::GET, true, now),
+            invalid
+        );
+
+        // 只读限制与签名时指定的方法
+        assert_eq!(
+            check(URL, exp, None, &sig, Method::POST),
+            Err(SignedUrlError::MethodNotAllowed)
+        );
+        assert_eq!(
+            verify(KEY, URL, exp, None, &sig, &Method::POST, false, now),
+            Ok(())
+        );
+        let sig = sign(KEY, URL, exp, Some("PUT"));
+        assert_eq!(
+            verify(KEY, URL, exp, Some("PUT"), &sig, &Method::PUT, false, now),
+            Ok(())
+        );
+        assert_eq!(
+            verify(KEY, URL, exp, Some("PUT"), &sig, &Method::GET, false, now),
+            Err(SignedUrlError::MethodNotAllowed)
+        );
+    }
+}
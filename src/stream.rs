@@ -0,0 +1,590 @@
+use crate::proxy::{BoxError, UpstreamTimeout};
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use bytes::Bytes;
+use futures_util::Stream;
+use hyper::body::{Frame, SizeHint};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::{Instant, Sleep};
+use tracing::{info, Span};
+
+/// 为响应体设置整体截止时间，超时后返回 [`UpstreamTimeout`] 并结束
+pub struct DeadlineStream<S> {
+    inner: S,
+    sleep: Pin<Box<Sleep>>,
+    expired: bool,
+}
+
+impl<S> DeadlineStream<S> {
+    pub fn new(inner: S, deadline: Instant) -> Self {
+        Self {
+            inner,
+            sleep: Box::pin(tokio::time::sleep_until(deadline)),
+            expired: false,
+        }
+    }
+}
+
+impl<S> Stream for DeadlineStream<S>
+where
+    S: Stream<Item = Result<Bytes, BoxError>> + Unpin,
+{
+    type Item = Result<Bytes, BoxError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.expired {
+            return Poll::Ready(None);
+        }
+
+        if let Poll::Ready(item) = Pin::new(&mut self.inner).poll_next(cx) {
+            return Poll::Ready(item);
+        }
+
+        if self.sleep.as_mut().poll(cx).is_ready() {
+            self.expired = true;
+            return Poll::Ready(Some(Err(Box::new(UpstreamTimeout))));
+        }
+
+        Poll::Pending
+    }
+}
+
+/// 流式响应的空闲超时：连续 `idle` 时间内没有收到数据则返回 [`UpstreamTimeout`] 并结束
+pub struct IdleTimeoutStream<S> {
+    inner: S,
+    idle: Duration,
+    sleep: Pin<Box<Sleep>>,
+    expired: bool,
+}
+
+impl<S> IdleTimeoutStream<S> {
+    pub fn new(inner: S, idle: Duration) -> Self {
+        Self {
+            inner,
+            idle,
+            sleep: Box::pin(tokio::time::sleep(idle)),
+            expired: false,
+        }
+    }
+}
+
+impl<S> Stream for IdleTimeoutStream<S>
+where
+    S: Stream<Item = Result<Bytes, BoxError>> + Unpin,
+{
+    type Item = Result<Bytes, BoxError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.expired {
+            return Poll::Ready(None);
+        }
+
+        if let Poll::Ready(item) = Pin::new(&mut self.inner).poll_next(cx) {
+            let next = Instant::now() + self.idle;
+            self.sleep.as_mut().reset(next);
+            return Poll::Ready(item);
+        }
+
+        if self.sleep.as_mut().poll(cx).is_ready() {
+            self.expired = true;
+            return Poll::Ready(Some(Err(Box::new(UpstreamTimeout))));
+        }
+
+        Poll::Pending
+    }
+}
+
+/// 在请求完成前被丢弃（客户端断开）时记录日志并计数
+///
+/// 处理函数的 future 或响应体被丢弃时，上游请求随之被丢弃并中止
+pub struct AbortGuard {
+    url: String,
+    counter: Arc<AtomicU64>,
+    armed: bool,
+    started: std::time::Instant,
+    bytes: u64,
+    span: Span,
+}
+
+impl AbortGuard {
+    pub fn new(url: &str, counter: Arc<AtomicU64>) -> Self {
+        Self {
+            url: url.to_string(),
+            counter,
+            armed: true,
+            started: std::time::Instant::now(),
+            bytes: 0,
+            span: Span::none(),
+        }
+    }
+
+    /// 响应体结束或被丢弃时在 `span` 上记录 `http.response_body_bytes`
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = span;
+        self
+    }
+
+    /// 请求已正常结束（或以上游错误结束），不再视为客户端中止
+    pub fn disarm(&mut self) {
+        self.armed = false;
+    }
+
+    /// 响应体传输结束，记录总字节数与总耗时
+    fn finish(&mut self) {
+        if self.armed {
+            self.disarm();
+            info!(
+                "代理响应完成: {} {} 字节，耗时 {}ms",
+                self.url,
+                self.bytes,
+                self.started.elapsed().as_millis()
+            );
+        }
+    }
+}
+
+impl Drop for AbortGuard {
+    fn drop(&mut self) {
+        self.span.record("http.response_body_bytes", self.bytes);
+        if self.armed {
+            self.counter.fetch_add(1, Ordering::Relaxed);
+            info!(
+                "客户端已断开，中止上游请求: {}（已传输 {} 字节，耗时 {}ms）",
+                self.url,
+                self.bytes,
+                self.started.elapsed().as_millis()
+            );
+        }
+    }
+}
+
+/// 响应体读完或出错时解除 [`AbortGuard`]，提前被丢弃则视为客户端中止
+pub struct AbortOnDropStream<S> {
+    inner: S,
+    guard: AbortGuard,
+}
+
+impl<S> AbortOnDropStream<S> {
+    pub fn new(inner: S, guard: AbortGuard) -> Self {
+        Self { inner, guard }
+    }
+}
+
+impl<S, E> Stream for AbortOnDropStream<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    type Item = Result<Bytes, E>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.inner).poll_next(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) => self.guard.bytes += chunk.len() as u64,
+            Poll::Ready(Some(Err(_))) => self.guard.disarm(),
+            Poll::Ready(None) => self.guard.finish(),
+            Poll::Pending => {}
+        }
+        poll
+    }
+}
+
+/// 令牌桶限速：按 `rate` 字节/秒放行响应体，大的分块会被拆开发送
+pub struct ThrottleStream<S> {
+    inner: S,
+    rate: f64,
+    tokens: f64,
+    refilled_at: Instant,
+    pending: Option<Bytes>,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> ThrottleStream<S> {
+    pub fn new(inner: S, bytes_per_sec: u64) -> Self {
+        Self {
+            inner,
+            rate: bytes_per_sec.max(1) as f64,
+            tokens: 0.0,
+            refilled_at: Instant::now(),
+            pending: None,
+            sleep: None,
+        }
+    }
+
+    /// 单次放行的最大字节数，约为 1/10 秒的配额
+    fn max_piece(&self) -> usize {
+        ((self.rate / 10.0) as usize).max(1)
+    }
+}
+
+impl<S, E> Stream for ThrottleStream<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    type Item = Result<Bytes, E>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(sleep) = self.sleep.as_mut() {
+                if sleep.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                self.sleep = None;
+            }
+
+            let mut chunk = match self.pending.take() {
+                Some(chunk) => chunk,
+                None => match Pin::new(&mut self.inner).poll_next(cx) {
+                    Poll::Ready(Some(Ok(chunk))) if !chunk.is_empty() => chunk,
+                    other => return other,
+                },
+            };
+
+            // 桶容量为一秒的配额，避免空闲后突发过多数据
+            let now = Instant::now();
+            let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+            self.refilled_at = now;
+
+            let size = chunk.len().min(self.max_piece());
+            if self.tokens >= size as f64 {
+                self.tokens -= size as f64;
+                let piece = chunk.split_to(size);
+                if !chunk.is_empty() {
+                    self.pending = Some(chunk);
+                }
+                return Poll::Ready(Some(Ok(piece)));
+            }
+
+            let wait = (size as f64 - self.tokens) / self.rate;
+            self.sleep = Some(Box::pin(tokio::time::sleep(Duration::from_secs_f64(wait))));
+            self.pending = Some(chunk);
+        }
+    }
+}
+
+/// 响应体结束后以 HTTP trailer 发送的总耗时（毫秒）
+pub const TOTAL_TIME_TRAILER: &str = "tun-total-time-ms";
+
+/// 记录已发送请求体字节数的 trailer 名称
+pub const UPLOAD_PROGRESS_TRAILER: &str = "tun-upload-progress";
+
+/// 上游 trailer 转发给客户端时添加的前缀
+pub const UPSTREAM_TRAILER_PREFIX: &str = "tun-trailer-";
+
+/// 上游响应的 trailer，由上游连接在响应体读完后填入
+pub type UpstreamTrailers = Arc<Mutex<Option<HeaderMap>>>;
+
+/// 在响应体末尾追加 `tun-total-time-ms` trailer，耗时从 `started` 计算到响应体读完；
+/// 上游带有 trailer 时以 `tun-trailer-<名称>` 一并发送
+pub struct TotalTimeBody<S> {
+    inner: S,
+    started: std::time::Instant,
+    upstream_trailers: UpstreamTrailers,
+    upload_sent: Option<Arc<AtomicU64>>,
+    done: bool,
+}
+
+impl<S> TotalTimeBody<S> {
+    pub fn new(inner: S, started: std::time::Instant, upstream_trailers: UpstreamTrailers) -> Self {
+        Self {
+            inner,
+            started,
+            upstream_trailers,
+            upload_sent: None,
+            done: false,
+        }
+    }
+
+    /// 同时以 `tun-upload-progress` 发送上游已读取的请求体字节数
+    pub fn with_upload_progress(mut self, sent: Arc<AtomicU64>) -> Self {
+        self.upload_sent = Some(sent);
+        self
+    }
+}
+
+impl<S, E> hyper::body::Body for TotalTimeBody<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        match Pin::new(&mut self.inner).poll_next(cx) {
+            Poll::Ready(Some(Ok(chunk))) => Poll::Ready(Some(Ok(Frame::data(chunk)))),
+            Poll::Ready(Some(Err(e))) => {
+                self.done = true;
+                Poll::Ready(Some(Err(e.into())))
+            }
+            Poll::Ready(None) => {
+                self.done = true;
+                let mut trailers = HeaderMap::new();
+                if let Some(upstream) = self.upstream_trailers.lock().unwrap().take() {
+                    for (name, value) in upstream.iter() {
+                        let name = format!("{}{}", UPSTREAM_TRAILER_PREFIX, name.as_str());
+                        if let Ok(name) = HeaderName::from_bytes(name.as_bytes()) {
+                            trailers.append(name, value.clone());
+                        }
+                    }
+                }
+                if let Some(sent) = &self.upload_sent {
+                    trailers.insert(
+                        UPLOAD_PROGRESS_TRAILER,
+                        HeaderValue::from(sent.load(Ordering::Relaxed)),
+                    );
+                }
+                trailers.insert(
+                    TOTAL_TIME_TRAILER,
+                    HeaderValue::from(self.started.elapsed().as_millis() as u64),
+                );
+                Poll::Ready(Some(Ok(Frame::trailers(trailers))))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// 统计发送给客户端的响应体字节数，丢弃时（发送完或客户端断开）以字节数调用 `on_complete`
+pub struct CompletionBody<F: FnOnce(u64)> {
+    inner: axum::body::Body,
+    bytes: u64,
+    on_complete: Option<F>,
+}
+
+impl<F: FnOnce(u64)> CompletionBody<F> {
+    pub fn new(inner: axum::body::Body, on_complete: F) -> Self {
+        Self {
+            inner,
+            bytes: 0,
+            on_complete: Some(on_complete),
+        }
+    }
+}
+
+impl<F: FnOnce(u64) + Unpin> hyper::body::Body for CompletionBody<F> {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &poll {
+            if let Some(data) = frame.data_ref() {
+                self.bytes += data.len() as u64;
+            }
+        }
+        poll.map_err(Into::into)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl<F: FnOnce(u64)> Drop for CompletionBody<F> {
+    fn drop(&mut self) {
+        if let Some(on_complete) = self.on_complete.take() {
+            on_complete(self.bytes);
+        }
+    }
+}
+
+/// 请求体分块交给上游客户端时每块的大小
+const UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// 两条上传进度日志之间的最短间隔
+const UPLOAD_PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 把请求体分块交给上游客户端，累计上游已读取的字节数并定期写入进度日志
+///
+/// 上游停止读取时不再产生进度日志；未发送完就被丢弃（如超时）时记录已发送的字节数
+pub struct UploadProgressStream {
+    url: String,
+    body: Bytes,
+    total: u64,
+    sent: Arc<AtomicU64>,
+    started: std::time::Instant,
+    last_log: std::time::Instant,
+}
+
+impl UploadProgressStream {
+    /// 每次发送（包括跟随重定向后重新发送）从 0 开始计数
+    pub fn new(url: &str, body: Bytes, sent: Arc<AtomicU64>) -> Self {
+        sent.store(0, Ordering::Relaxed);
+        let now = std::time::Instant::now();
+        Self {
+            url: url.to_string(),
+            total: body.len() as u64,
+            body,
+            sent,
+            started: now,
+            last_log: now,
+        }
+    }
+}
+
+impl Stream for UploadProgressStream {
+    type Item = Result<Bytes, BoxError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.body.is_empty() {
+            return Poll::Ready(None);
+        }
+        let len = self.body.len().min(UPLOAD_CHUNK_SIZE);
+        let chunk = self.body.split_to(len);
+        let sent = self.sent.fetch_add(len as u64, Ordering::Relaxed) + len as u64;
+        if sent == self.total {
+            info!(
+                "上传完成: {} {} 字节，耗时 {}ms",
+                self.url,
+                sent,
+                self.started.elapsed().as_millis()
+            );
+        } else if self.last_log.elapsed() >= UPLOAD_PROGRESS_INTERVAL {
+            self.last_log = std::time::Instant::now();
+            info!("上传进度: {} {} / {} 字节", self.url, sent, self.total);
+        }
+        Poll::Ready(Some(Ok(chunk)))
+    }
+}
+
+impl Drop for UploadProgressStream {
+    fn drop(&mut self) {
+        let sent = self.sent.load(Ordering::Relaxed);
+        if sent < self.total {
+            info!(
+                "上传未完成: {} 已发送 {} / {} 字节，耗时 {}ms",
+                self.url,
+                sent,
+                self.total,
+                self.started.elapsed().as_millis()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+
+    #[tokio::test]
+    async fn test_deadline_stream_expires() {
+        let inner = futures_util::stream::pending::<Result<Bytes, BoxError>>();
+        let deadline = Instant::now() + Duration::from_millis(20);
+        let mut stream = DeadlineStream::new(inner, deadline);
+
+        let item = stream.next().await.unwrap();
+        assert!(item.unwrap_err().is::<UpstreamTimeout>());
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_throttle_stream_limits_rate() {
+        let inner = futures_util::stream::iter(vec![
+            Ok::<_, BoxError>(Bytes::from(vec![b'a'; 1500])),
+            Ok(Bytes::from(vec![b'b'; 500])),
+        ]);
+        let started = Instant::now();
+        let chunks: Vec<Bytes> = ThrottleStream::new(inner, 4000)
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+
+        // 2000 字节以 4000 字节/秒发送，至少需要 500ms
+        assert!(started.elapsed() >= Duration::from_millis(500));
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 400));
+        assert_eq!(chunks.concat().len(), 2000);
+    }
+
+    #[tokio::test]
+    async fn test_deadline_stream_passes_through() {
+        let inner = futures_util::stream::iter(vec![
+            Ok::<_, BoxError>(Bytes::from_static(b"a")),
+            Ok(Bytes::from_static(b"b")),
+        ]);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let chunks: Vec<Bytes> = DeadlineStream::new(inner, deadline)
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+        assert_eq!(chunks.concat(), b"ab");
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout_resets_on_data() {
+        // 每 30ms 一个分块，总时长超过空闲时间但每次间隔都小于空闲时间
+        let inner = futures_util::stream::unfold(0u32, |i| async move {
+            if i == 5 {
+                return None;
+            }
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            Some((Ok::<_, BoxError>(Bytes::from_static(b"x")), i + 1))
+        });
+        let chunks: Vec<_> = IdleTimeoutStream::new(Box::pin(inner), Duration::from_millis(80))
+            .collect()
+            .await;
+        assert_eq!(chunks.len(), 5);
+        assert!(chunks.iter().all(|chunk| chunk.is_ok()));
+
+        let inner = futures_util::stream::pending::<Result<Bytes, BoxError>>();
+        let mut stream = IdleTimeoutStream::new(inner, Duration::from_millis(20));
+        assert!(stream
+            .next()
+            .await
+            .unwrap()
+            .unwrap_err()
+            .is::<UpstreamTimeout>());
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_upload_progress_stream() {
+        let sent = Arc::new(AtomicU64::new(7));
+        let body = Bytes::from(vec![1u8; UPLOAD_CHUNK_SIZE * 2 + 10]);
+        let stream = UploadProgressStream::new("http://a", body.clone(), sent.clone());
+        assert_eq!(sent.load(Ordering::Relaxed), 0);
+
+        let chunks: Vec<Bytes> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(
+            chunks.iter().map(Bytes::len).collect::<Vec<_>>(),
+            [UPLOAD_CHUNK_SIZE, UPLOAD_CHUNK_SIZE, 10]
+        );
+        assert_eq!(chunks.concat(), body);
+        assert_eq!(sent.load(Ordering::Relaxed), body.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn test_abort_on_drop_stream_counts_only_early_drop() {
+        let counter = Arc::new(AtomicU64::new(0));
+
+        let inner = futures_util::stream::iter(vec![Ok::<_, BoxError>(Bytes::from_static(b"a"))]);
+        let stream = AbortOnDropStream::new(inner, AbortGuard::new("http://a", counter.clone()));
+        let _: Vec<_> = stream.collect().await;
+        assert_eq!(counter.load(Ordering::Relaxed), 0);
+
+        let inner = futures_util::stream::pending::<Result<Bytes, BoxError>>();
+        drop(AbortOnDropStream::new(
+            inner,
+            AbortGuard::new("http://b", counter.clone()),
+        ));
+        assert_eq!(counter.load(Ordering::Relaxed), 1);
+    }
+}
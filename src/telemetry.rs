@@ -0,0 +1,320 @@
+use crate::audit;
+use crate::config::{Config, LogFormat};
+use crate::log_file::RotatingFile;
+use anyhow::Result;
+use axum::http::HeaderMap;
+use std::sync::Mutex;
+use tracing::{Span, Subscriber};
+use tracing_appender::non_blocking::{NonBlockingBuilder, WorkerGuard};
+use tracing_subscriber::{
+    filter::{LevelFilter, Targets},
+    fmt::MakeWriter,
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+    EnvFilter, Layer, Registry,
+};
+use url::Url;
+
+#[cfg(feature = "otel")]
+use {
+    opentelemetry::propagation::{Extractor, Injector},
+    tracing_opentelemetry::OpenTelemetrySpanExt,
+};
+
+/// 日志文件后台写入线程的句柄，丢弃时写完缓冲中的日志
+static FILE_LOG_GUARD: Mutex<Option<WorkerGuard>> = Mutex::new(None);
+
+/// 审计日志文件后台写入线程的句柄
+static AUDIT_LOG_GUARD: Mutex<Option<WorkerGuard>> = Mutex::new(None);
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// 初始化日志，配置了 `log.file` 时同时写入日志文件，配置了 `audit.file` 时审计事件另外写入该文件，
+/// 配置了 `otel`（或 `otlp_endpoint`）且启用 `otel` 特性时同时导出 span 到 OTLP 收集器
+///
+/// 标准输出的日志级别优先取 `RUST_LOG`，其次为配置的 `log.level`、`log_level`，默认 `info`
+pub fn init(config: &Config) -> Result<()> {
+    let stdout_filter = || {
+        EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| EnvFilter::new(config.effective_log_level()))
+    };
+    let mut layers: Vec<BoxedLayer> =
+        vec![fmt_layer(config.effective_log_format(), std::io::stdout)
+            .with_filter(stdout_filter())
+            .boxed()];
+
+    if let Some(path) = config.log.file_path() {
+        let file = RotatingFile::open(path, config.log.rotate.clone())
+            .map_err(|e| anyhow::anyhow!("log.file: cannot open {:?} for writing: {}", path, e))?;
+        // 缓冲满时等待而不是丢弃日志
+        let (writer, guard) = NonBlockingBuilder::default()
+            .lossy(false)
+            .thread_name("log-file")
+            .finish(file);
+        let filter = match &config.log.file_level {
+            Some(level) => EnvFilter::new(level),
+            None => stdout_filter(),
+        };
+        layers.push(
+            fmt_layer(LogFormat::Json, writer)
+                .with_filter(filter)
+                .boxed(),
+        );
+        *FILE_LOG_GUARD.lock().unwrap() = Some(guard);
+    }
+    let log_file = config.log.file_path();
+    if let Some(layer) = audit_layer(config)? {
+        layers.push(layer);
+    }
+
+    let otel = config.otel_settings();
+
+    #[cfg(feature = "otel")]
+    if let Some(otel) = otel {
+        use opentelemetry_otlp::WithExportConfig;
+        use opentelemetry_sdk::trace::Sampler;
+
+        let endpoint = otel.endpoint.trim();
+        let service_name = otel
+            .service_name
+            .as_deref()
+            .map(str::trim)
+            .unwrap_or(env!("CARGO_PKG_NAME"))
+            .to_string();
+
+        opentelemetry::global::set_text_map_propagator(
+            opentelemetry_sdk::propagation::TraceContextPropagator::new(),
+        );
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(endpoint),
+            )
+            .with_trace_config(
+                opentelemetry_sdk::trace::config()
+                    // 入站请求带有 `traceparent` 时沿用调用方的采样决定
+                    .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+                        otel.sample_ratio,
+                    ))))
+                    .with_resource(opentelemetry_sdk::Resource::new(vec![
+                        opentelemetry::KeyValue::new("service.name", service_name),
+                    ])),
+            )
+            .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+
+        layers.push(
+            tracing_opentelemetry::layer()
+                .with_tracer(tracer)
+                .with_filter(stdout_filter())
+                .boxed(),
+        );
+        tracing_subscriber::registry().with(layers).init();
+        if let Some(path) = log_file {
+            tracing::info!("日志同时写入文件: {}", path);
+        }
+        tracing::info!(
+            "链路追踪导出到: {}（采样比例 {}）",
+            endpoint,
+            otel.sample_ratio
+        );
+        return Ok(());
+    }
+
+    tracing_subscriber::registry().with(layers).init();
+    if let Some(path) = log_file {
+        tracing::info!("日志同时写入文件: {}", path);
+    }
+    if let Some(path) = config.audit.file_path() {
+        tracing::info!("审计日志写入文件: {}", path);
+    }
+
+    #[cfg(not(feature = "otel"))]
+    if otel.is_some() {
+        tracing::warn!("未启用 otel 编译特性，忽略链路追踪导出设置");
+    }
+
+    Ok(())
+}
+
+/// 只把审计事件写入 `audit.file`，供不启动服务的子命令（如 `token rotate`）记录管理操作
+pub fn init_audit(config: &Config) -> Result<()> {
+    if let Some(layer) = audit_layer(config)? {
+        tracing_subscriber::registry().with(layer).init();
+    }
+    Ok(())
+}
+
+/// 启用审计且配置了 `audit.file` 时，只接收 `audit` 目标事件的 JSON 行日志文件，按 `log.rotate` 轮转；
+/// 缓冲满时丢弃事件而不是阻塞请求处理
+fn audit_layer(config: &Config) -> Result<Option<BoxedLayer>> {
+    let Some(path) = config.audit.file_path().filter(|_| config.audit.enabled) else {
+        return Ok(None);
+    };
+    let file = RotatingFile::open(path, config.log.rotate.clone())
+        .map_err(|e| anyhow::anyhow!("audit.file: cannot open {:?} for writing: {}", path, e))?;
+    let (writer, guard) = NonBlockingBuilder::default()
+        .lossy(true)
+        .thread_name("audit-file")
+        .finish(file);
+    *AUDIT_LOG_GUARD.lock().unwrap() = Some(guard);
+    Ok(Some(
+        fmt_layer(LogFormat::Json, writer)
+            .with_filter(Targets::new().with_target(audit::TARGET, LevelFilter::TRACE))
+            .boxed(),
+    ))
+}
+
+/// 按配置的格式输出日志到 `writer`
+fn fmt_layer<S, W>(format: LogFormat, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer);
+    match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer.json().boxed(),
+        LogFormat::Compact => layer.compact().boxed(),
+    }
+}
+
+/// 程序退出前写完日志文件与审计日志文件的缓冲并导出尚未发送的 span
+pub fn shutdown() {
+    drop(FILE_LOG_GUARD.lock().unwrap().take());
+    drop(AUDIT_LOG_GUARD.lock().unwrap().take());
+    #[cfg(feature = "otel")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// 为一次代理请求创建 span，入站请求带有 `traceparent` 时作为其子 span
+///
+/// 响应体字节数在响应体传输结束时记录，span 随之结束
+pub fn proxy_span(method: &str, url: &str, request_bytes: usize, inbound: &HeaderMap) -> Span {
+    let host = Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_string))
+        .unwrap_or_default();
+
+    let span = tracing::info_span!(
+        "proxy",
+        otel.kind = "server",
+        http.method = %method,
+        http.host = %host,
+        http.status_code = tracing::field::Empty,
+        http.request_body_bytes = request_bytes,
+        http.response_body_bytes = tracing::field::Empty,
+        upstream.duration_ms = tracing::field::Empty,
+    );
+
+    #[cfg(feature = "otel")]
+    {
+        let parent = opentelemetry::global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(inbound))
+        });
+        span.set_parent(parent);
+    }
+    #[cfg(not(feature = "otel"))]
+    let _ = inbound;
+
+    span
+}
+
+/// `proxy` 的子 span，覆盖向上游发送请求到收到响应头的过程
+pub fn upstream_span(parent: &Span) -> Span {
+    tracing::info_span!(
+        parent: parent,
+        "upstream_send",
+        otel.kind = "client",
+        upstream.ttfb_ms = tracing::field::Empty,
+    )
+}
+
+/// 把 span 的上下文写入上游请求的 `traceparent`
+///
+/// 未启用导出时不做处理，入站的 `traceparent` 按白名单原样转发
+pub fn inject(span: &Span, headers: &mut reqwest::header::HeaderMap) {
+    #[cfg(feature = "otel")]
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&span.context(), &mut HeaderInjector(headers))
+    });
+    #[cfg(not(feature = "otel"))]
+    let _ = (span, headers);
+}
+
+#[cfg(feature = "otel")]
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+#[cfg(feature = "otel")]
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+#[cfg(feature = "otel")]
+struct HeaderInjector<'a>(&'a mut reqwest::header::HeaderMap);
+
+#[cfg(feature = "otel")]
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            reqwest::header::HeaderName::from_bytes(key.as_bytes()),
+            reqwest::header::HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct TestWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for TestWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_log_format() {
+        let writer = TestWriter::default();
+        let make_writer = {
+            let writer = writer.clone();
+            move || writer.clone()
+        };
+        let subscriber =
+            tracing_subscriber::registry().with(fmt_layer(LogFormat::Json, make_writer));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(status = 200, "代理请求完成");
+            tracing::warn!("上游超时");
+        });
+
+        let output = String::from_utf8(writer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["fields"]["message"], "代理请求完成");
+        assert_eq!(lines[0]["fields"]["status"], 200);
+        assert_eq!(lines[1]["level"], "WARN");
+    }
+}
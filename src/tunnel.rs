@@ -0,0 +1,236 @@
+use crate::audit;
+use crate::auth;
+use crate::jwt::JwtClaims;
+use crate::proxy::{AppError, UpstreamTimeout};
+use crate::AppConfig;
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use hyper_util::rt::TokioIo;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tracing::{debug, info, warn};
+
+/// 处理 `CONNECT host:port`（`allow_connect`），其余请求交给后续路由
+///
+/// 认证通过并连上目标后回复 200，之后在客户端与目标之间双向转发原始字节
+pub async fn connect_middleware(
+    State(config): State<Arc<AppConfig>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if request.method() != Method::CONNECT {
+        return next.run(request).await;
+    }
+
+    // 代理客户端通常使用 Proxy-Authorization，也接受与其他接口相同的 Authorization
+    let credentials: Vec<String> = [header::PROXY_AUTHORIZATION, header::AUTHORIZATION]
+        .iter()
+        .filter_map(|name| request.headers().get(name))
+        .filter_map(|value| value.to_str().ok())
+        .map(str::to_string)
+        .collect();
+    let mut authorized = false;
+    let mut claims: Option<JwtClaims> = None;
+    for value in &credentials {
+        if auth::valid_bearer(value, &config.token)
+            || config.state.token_hashes.verify_bearer(value).await
+        {
+            authorized = true;
+            break;
+        }
+    }
+    if let (false, Some(verifier)) = (authorized, &config.state.jwt) {
+        for value in &credentials {
+            match verifier.verify_bearer(value).await {
+                Some(Ok(verified)) => {
+                    claims = Some(verified);
+                    authorized = true;
+                    break;
+                }
+                Some(Err(e)) => debug!("CONNECT JWT 认证失败: {}", e),
+                None => {}
+            }
+        }
+    }
+    if !authorized {
+        let credential = credentials
+            .first()
+            .map_or("", |value| auth::bearer_token(value).unwrap_or(value));
+        audit::auth_failure(&config, &request, "connect", credential, "CONNECT 认证失败");
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::PROXY_AUTHENTICATION_REQUIRED;
+        response.headers_mut().insert(
+            header::PROXY_AUTHENTICATE,
+            HeaderValue::from_static("Bearer"),
+        );
+        return response;
+    }
+
+    let authority = match request.uri().authority() {
+        Some(authority) if authority.port_u16().is_some() => authority.to_string(),
+        _ => {
+            return AppError::BadRequest(format!(
+                "CONNECT 目标必须为 host:port，收到 {}",
+                request.uri()
+            ))
+            .into_response()
+        }
+    };
+    let host = request
+        .uri()
+        .host()
+        .unwrap_or_default()
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    if let Some(claims) = claims.filter(|claims| !claims.allows_host(host)) {
+        debug!(
+            "CONNECT 目标不在 allowed_hosts 中: {} (sub={:?})",
+            authority, claims.sub
+        );
+        return AppError::Forbidden(format!("目标主机 {} 不在 token 的 allowed_hosts 中", host))
+            .into_response();
+    }
+
+    let timeout = Duration::from_secs(config.state.config.upstream_timeout_secs);
+    let mut target = match tokio::time::timeout(timeout, TcpStream::connect(&authority)).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => return AppError::upstream(&e).into_response(),
+        Err(_) => return AppError::upstream(&UpstreamTimeout).into_response(),
+    };
+    info!("CONNECT 隧道: {}", authority);
+
+    let upgrade = hyper::upgrade::on(&mut request);
+    tokio::spawn(async move {
+        let mut client = match upgrade.await {
+            Ok(upgraded) => TokioIo::new(upgraded),
+            Err(e) => {
+                warn!("CONNECT 升级失败: {} {}", authority, e);
+                return;
+            }
+        };
+        match tokio::io::copy_bidirectional(&mut client, &mut target).await {
+            Ok((sent, received)) => debug!(
+                "CONNECT 隧道关闭: {} 发送 {} 字节，接收 {} 字节",
+                authority, sent, received
+            ),
+            Err(e) => debug!("CONNECT 隧道中断: {} {}", authority, e),
+        }
+    });
+
+    // 2xx 的 CONNECT 响应不能带 Content-Length，响应体不给出确定长度，避免 axum 自动补上
+    let body = Body::from_stream(futures_util::stream::empty::<Result<Bytes, std::io::Error>>());
+    (StatusCode::OK, body).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proxy::{tests::spawn_tls_upstream, AppState};
+    use axum::{routing::get, Router};
+
+    /// 启用 CONNECT 的测试服务，返回监听地址
+    async fn spawn_agent() -> String {
+        let config = Config {
+            allow_connect: true,
+            ..Config::default()
+        };
+        let app_config = Arc::new(AppConfig {
+            state: Arc::new(AppState::new(reqwest::Client::new(), &config)),
+            token: "test-token".to_string(),
+        });
+        let app = Router::new().route("/", get(|| async { "agent" })).layer(
+            axum::middleware::from_fn_with_state(app_config, connect_middleware),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            crate::server::serve(crate::server::Listener::Tcp(listener), app, &config.server).await
+        });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn test_connect_tunnel() {
+        let upstream = spawn_tls_upstream(
+            include_bytes!("../testdata/ca_signed.crt"),
+            include_bytes!("../testdata/ca_signed.key"),
+        )
+        .await;
+        let ca = reqwest::Certificate::from_pem(include_bytes!("../testdata/test_ca.crt")).unwrap();
+        let target = format!("https://localhost:{}/", upstream.port());
+        let agent = spawn_agent().await;
+
+        let client = |token: &'static str| {
+            let proxy = reqwest::Proxy::https(&agent)
+                .unwrap()
+                .custom_http_auth(reqwest::header::HeaderValue::from_static(token));
+            reqwest::Client::builder()
+                .proxy(proxy)
+                .add_root_certificate(ca.clone())
+                .build()
+                .unwrap()
+        };
+
+        // TLS 握手经隧道直达上游，证书由客户端自行校验
+        let response = client("Bearer test-token")
+            .get(&target)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.text().await.unwrap(), "secure");
+
+        assert!(client("Bearer wrong-token")
+            .get(&target)
+            .send()
+            .await
+            .is_err());
+
+        // 其他方法照常路由
+        assert_eq!(
+            reqwest::get(format!("{}/", agent))
+                .await
+                .unwrap()
+                .text()
+                .await
+                .unwrap(),
+            "agent"
+        );
+    }
+
+    /// 发送原始 CONNECT 请求，返回响应状态码
+    async fn connect_status(agent: &str, target: &str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = TcpStream::connect(agent.trim_start_matches("http://"))
+            .await
+            .unwrap();
+        let request = format!(
+            "CONNECT {} HTTP/1.1\r\nHost: {}\r\nProxy-Authorization: Bearer test-token\r\n\r\n",
+            target, target
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut buf = [0u8; 12];
+        stream.read_exact(&mut buf).await.unwrap();
+        String::from_utf8_lossy(&buf[9..]).to_string()
+    }
+
+    #[tokio::test]
+    async fn test_connect_errors() {
+        let agent = spawn_agent().await;
+
+        // 没有端口的目标无效，目标端口未监听时返回 502
+        assert_eq!(connect_status(&agent, "localhost").await, "400");
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = closed.local_addr().unwrap().to_string();
+        drop(closed);
+        assert_eq!(connect_status(&agent, &target).await, "502");
+    }
+}
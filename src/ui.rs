@@ -0,0 +1,69 @@
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+
+const INDEX_HTML: &str = include_str!("../assets/ui/index.html");
+const APP_JS: &str = include_str!("../assets/ui/app.js");
+const STYLE_CSS: &str = include_str!("../assets/ui/style.css");
+
+/// 仅允许加载同源脚本/样式并请求同源接口，禁止内联脚本和被嵌入 iframe
+const CONTENT_SECURITY_POLICY: &str = "default-src 'none'; script-src 'self'; \
+    style-src 'self'; connect-src 'self'; img-src 'self' data:; \
+    base-uri 'none'; form-action 'none'; frame-ancestors 'none'";
+
+/// 控制台页面路由，不经过 Bearer 认证（页面内接口调用仍需 Token）
+pub fn router<S>() -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/ui", get(redirect_handler))
+        .route("/ui/", get(index_handler))
+        .route("/ui/index.html", get(index_handler))
+        .route("/ui/app.js", get(app_js_handler))
+        .route("/ui/style.css", get(style_css_handler))
+}
+
+fn static_response(content_type: &'static str, body: &'static str) -> Response {
+    let mut resp = body.into_response();
+    let headers = resp.headers_mut();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    headers.insert(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static(CONTENT_SECURITY_POLICY),
+    );
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    headers.insert(
+        header::REFERRER_POLICY,
+        HeaderValue::from_static("no-referrer"),
+    );
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    resp
+}
+
+/// `/ui` 跳转到 `ui/`，使用相对地址以兼容路径前缀部署
+async fn redirect_handler() -> Response {
+    let mut resp = Response::new(axum::body::Body::empty());
+    *resp.status_mut() = StatusCode::MOVED_PERMANENTLY;
+    resp.headers_mut()
+        .insert(header::LOCATION, HeaderValue::from_static("ui/"));
+    resp
+}
+
+async fn index_handler() -> Response {
+    static_response("text/html; charset=utf-8", INDEX_HTML)
+}
+
+async fn app_js_handler() -> Response {
+    static_response("text/javascript; charset=utf-8", APP_JS)
+}
+
+async fn style_css_handler() -> Response {
+    static_response("text/css; charset=utf-8", STYLE_CSS)
+}
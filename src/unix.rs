@@ -0,0 +1,264 @@
+#[cfg(all(unix, feature = "unix-upstream"))]
+use crate::proxy::UpstreamTimeout;
+use crate::proxy::{BoxError, ProxyRequestSpec, UpstreamResponse};
+use std::path::PathBuf;
+#[cfg(all(unix, feature = "unix-upstream"))]
+use {
+    crate::stream::UpstreamTrailers,
+    axum::http::{header, HeaderName, HeaderValue, Method, Request},
+    futures_util::TryStreamExt,
+    http_body_util::{BodyStream, Full},
+    hyper_util::rt::TokioIo,
+    std::os::unix::fs::FileTypeExt,
+    std::time::Duration,
+    tokio::net::UnixStream,
+};
+
+pub const UNIX_SCHEME: &str = "unix:";
+
+pub fn is_unix_target(url: &str) -> bool {
+    url.len() >= UNIX_SCHEME.len() && url[..UNIX_SCHEME.len()].eq_ignore_ascii_case(UNIX_SCHEME)
+}
+
+/// 由 socket 路径与请求路径拼出 `unix:/var/run/docker.sock:/containers/json` 形式的目标地址
+pub fn unix_target_url(socket: &str, path: &str) -> String {
+    let path = path.trim();
+    if path.starts_with('/') {
+        format!("{}{}:{}", UNIX_SCHEME, socket.trim(), path)
+    } else {
+        format!("{}{}:/{}", UNIX_SCHEME, socket.trim(), path)
+    }
+}
+
+/// 拆分 `unix:/run/app.sock/path?x=1` 或 `unix:/run/app.sock:/path?x=1` 为 socket 路径与请求路径
+///
+/// 用 `:` 分隔时冒号前即为 socket 路径；否则从前往后逐级检查路径，第一个实际存在的 socket 文件即为
+/// socket 路径，其余部分为请求路径
+#[cfg(all(unix, feature = "unix-upstream"))]
+pub fn split_unix_target(url: &str) -> Option<(PathBuf, String)> {
+    if !is_unix_target(url) {
+        return None;
+    }
+
+    let rest = &url[UNIX_SCHEME.len()..];
+    let (path, query) = match rest.find('?') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, ""),
+    };
+    if !path.starts_with('/') {
+        return None;
+    }
+
+    if let Some((socket, request_path)) = path.split_once(":/") {
+        return Some((PathBuf::from(socket), format!("/{}{}", request_path, query)));
+    }
+
+    let mut socket = String::new();
+    for segment in path[1..].split('/') {
+        socket.push('/');
+        socket.push_str(segment);
+
+        let is_socket = std::fs::metadata(&socket)
+            .map(|m| m.file_type().is_socket())
+            .unwrap_or(false);
+        if is_socket {
+            let request_path = &path[socket.len()..];
+            let request_path = if request_path.is_empty() {
+                "/"
+            } else {
+                request_path
+            };
+            return Some((PathBuf::from(&socket), format!("{}{}", request_path, query)));
+        }
+    }
+
+    None
+}
+
+#[cfg(not(all(unix, feature = "unix-upstream")))]
+pub fn split_unix_target(_url: &str) -> Option<(PathBuf, String)> {
+    None
+}
+
+#[cfg(not(all(unix, feature = "unix-upstream")))]
+pub async fn send(
+    _spec: &ProxyRequestSpec,
+    _timeout: std::time::Duration,
+) -> Result<UpstreamResponse, BoxError> {
+    Err("当前平台不支持 unix socket".into())
+}
+
+/// 通过 Unix socket 以 HTTP/1.1 发送请求，`timeout` 为等待响应头的时限
+#[cfg(all(unix, feature = "unix-upstream"))]
+pub async fn send(
+    spec: &ProxyRequestSpec,
+    timeout: Duration,
+) -> Result<UpstreamResponse, BoxError> {
+    let (socket, path_and_query) =
+        split_unix_target(&spec.url).ok_or("未找到对应的 unix socket 文件")?;
+
+    let response = tokio::time::timeout(timeout, async {
+        let stream = UnixStream::connect(&socket).await?;
+        let (mut sender, conn) =
+            hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+        tokio::spawn(async move {
+            let _ = conn.await;
+        });
+
+        let mut request = Request::builder()
+            .method(Method::from_bytes(spec.method.as_str().as_bytes())?)
+            .uri(path_and_query.as_str())
+            .header(header::HOST, "localhost");
+        for (name, value) in spec.headers.iter() {
+            request = request.header(
+                HeaderName::from_bytes(name.as_str().as_bytes())?,
+                HeaderValue::from_bytes(value.as_bytes())?,
+            );
+        }
+        let request = request.body(Full::new(spec.body.clone()))?;
+
+        Ok::<_, BoxError>(sender.send_request(request).await?)
+    })
+    .await
+    .map_err(|_| UpstreamTimeout)??;
+
+    let mut headers = reqwest::header::HeaderMap::new();
+    for (name, value) in response.headers().iter() {
+        if let (Ok(name), Ok(value)) = (
+            reqwest::header::HeaderName::from_bytes(name.as_str().as_bytes()),
+            reqwest::header::HeaderValue::from_bytes(value.as_bytes()),
+        ) {
+            headers.append(name, value);
+        }
+    }
+
+    let status = response.status().as_u16();
+    // 数据帧作为响应体，trailer 帧留给转发给客户端的响应
+    let trailers = UpstreamTrailers::default();
+    let slot = trailers.clone();
+    let body = BodyStream::new(response.into_body())
+        .map_err(BoxError::from)
+        .try_filter_map(move |frame| {
+            let data = match frame.into_data() {
+                Ok(data) => Some(data),
+                Err(frame) => {
+                    if let Ok(trailers) = frame.into_trailers() {
+                        *slot.lock().unwrap() = Some(trailers);
+                    }
+                    None
+                }
+            };
+            std::future::ready(Ok(data))
+        });
+
+    Ok(UpstreamResponse {
+        status,
+        headers,
+        url: spec.url.clone(),
+        version: reqwest::Version::HTTP_11,
+        trailers,
+        local_address: None,
+        body: Box::pin(body),
+    })
+}
+
+#[cfg(all(test, unix, feature = "unix-upstream"))]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use futures_util::StreamExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UnixListener;
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("rha-{}-{}.sock", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    /// 简单的 HTTP/1.1 服务端：把请求行原样写入响应体
+    fn spawn_server(path: &PathBuf) {
+        let listener = UnixListener::bind(path).unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = Vec::new();
+                let mut chunk = [0u8; 1024];
+                while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let n = stream.read(&mut chunk).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    buf.extend_from_slice(&chunk[..n]);
+                }
+                let request = String::from_utf8_lossy(&buf);
+                let request_line = request.lines().next().unwrap_or("").to_string();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nX-Upstream: unix\r\n\r\n{}",
+                    request_line.len(),
+                    request_line
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn test_split_unix_target() {
+        let path = socket_path("split");
+        let _listener = UnixListener::bind(&path).unwrap();
+        let url = format!("unix:{}/api/items?x=1", path.display());
+
+        let (socket, request_path) = split_unix_target(&url).unwrap();
+        assert_eq!(socket, path);
+        assert_eq!(request_path, "/api/items?x=1");
+
+        let (_, request_path) = split_unix_target(&format!("unix:{}", path.display())).unwrap();
+        assert_eq!(request_path, "/");
+
+        assert!(split_unix_target("unix:/nonexistent/app.sock/path").is_none());
+
+        // 显式分隔时不要求 socket 文件存在
+        let (socket, request_path) =
+            split_unix_target("unix:/var/run/docker.sock:/containers/json?all=1").unwrap();
+        assert_eq!(socket, PathBuf::from("/var/run/docker.sock"));
+        assert_eq!(request_path, "/containers/json?all=1");
+        assert_eq!(
+            unix_target_url("/var/run/docker.sock", "containers/json"),
+            "unix:/var/run/docker.sock:/containers/json"
+        );
+        assert!(split_unix_target("https://example.com/").is_none());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_send_over_unix_socket() {
+        let path = socket_path("send");
+        spawn_server(&path);
+
+        let spec = ProxyRequestSpec {
+            url: format!("unix:{}/hello?name=world", path.display()),
+            method: reqwest::Method::GET,
+            headers: reqwest::header::HeaderMap::new(),
+            body: Bytes::new(),
+            timeout: None,
+            follow_redirects: false,
+            http_version: None,
+            ip_preference: None,
+            resolve: None,
+            upload_progress: None,
+            streamed_body: None,
+            http3: false,
+            streaming: false,
+        };
+
+        let response = send(&spec, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.headers.get("x-upstream").unwrap(), "unix");
+
+        let body: Vec<Bytes> = response.body.map(|chunk| chunk.unwrap()).collect().await;
+        assert_eq!(body.concat(), b"GET /hello?name=world HTTP/1.1");
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
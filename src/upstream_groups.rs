@@ -0,0 +1,440 @@
+//! 上游组（`upstream_groups`）：客户端以 `group://<名称>/<路径>` 访问，按权重平滑轮询选择组内
+//! 后端并展开为实际地址
+//!
+//! 被动健康检查（`upstream_health`）：后端在 `window_secs` 内连续出现 `failure_threshold` 次
+//! 连接失败、超时或 5xx 响应时被摘除 `cooldown_secs` 秒，期间请求分发到组内其他后端；冷却结束后
+//! 重新参与选择，第一个请求成功即恢复，失败则再次摘除
+
+use crate::config::{UpstreamBackend, UpstreamHealthConfig};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// 上游组形式的目标地址前缀：`group://<名称>/<路径>?<查询>`
+pub const GROUP_PREFIX: &str = "group://";
+
+/// 地址是否为上游组形式
+pub fn is_group(url: &str) -> bool {
+    url.trim_start().starts_with(GROUP_PREFIX)
+}
+
+/// 后端的被动健康状态
+#[derive(Debug, Default)]
+struct BackendHealth {
+    /// 当前统计窗口内连续失败的次数
+    failures: u32,
+    /// 统计窗口内第一次失败的时间
+    window_start: Option<Instant>,
+    /// 摘除的截止时间；已过期但尚未有请求成功时处于试探状态
+    ejected_until: Option<Instant>,
+}
+
+impl BackendHealth {
+    fn is_available(&self, now: Instant) -> bool {
+        self.ejected_until.is_none_or(|until| until <= now)
+    }
+}
+
+#[derive(Debug)]
+struct Group {
+    /// 后端基础地址（去掉末尾的 `/`）与权重
+    backends: Vec<(String, i64)>,
+    /// 平滑加权轮询的当前权重
+    current: Mutex<Vec<i64>>,
+}
+
+/// 后端健康状态的变化，用于发送通知（`notifications`）
+#[derive(Debug, Clone, PartialEq)]
+pub enum HealthChange {
+    /// 在统计窗口内失败次数达到阈值而被摘除，试探失败后的再次摘除不算
+    Ejected { backend: String, failures: u32 },
+    /// 被摘除的后端试探成功后恢复
+    Recovered { backend: String },
+}
+
+/// `GET /status` 中单个后端的状态
+#[derive(Debug, Serialize, PartialEq)]
+pub struct BackendStatus {
+    pub url_prefix: String,
+    pub weight: i64,
+    pub healthy: bool,
+    /// 当前统计窗口内连续失败的次数
+    pub failures: u32,
+    /// 距离重新参与选择的秒数，未被摘除时为 None
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ejected_secs: Option<u64>,
+}
+
+/// 配置的上游组，名称到后端列表，以及各后端（按基础地址）的健康状态
+#[derive(Debug, Default)]
+pub struct UpstreamGroups {
+    groups: HashMap<String, Group>,
+    health: Mutex<HashMap<String, BackendHealth>>,
+    failure_threshold: u32,
+    window: Duration,
+    cooldown: Duration,
+}
+
+impl UpstreamGroups {
+    pub fn new(
+        groups: &HashMap<String, Vec<UpstreamBackend>>,
+        health: &UpstreamHealthConfig,
+    ) -> Self {
+        let groups = groups
+            .iter()
+            .map(|(name, backends)| {
+                let backends: Vec<(String, i64)> = backends
+                    .iter()
+                    .map(|backend| {
+                        (
+                            backend.url_prefix.trim().trim_end_matches('/').to_string(),
+                            i64::from(backend.weight),
+                        )
+                    })
+                    .collect();
+                let current = Mutex::new(vec![0; backends.len()]);
+                (name.clone(), Group { backends, current })
+            })
+            .collect();
+        Self {
+            groups,
+            health: Mutex::new(HashMap::new()),
+            failure_threshold: health.failure_threshold.max(1),
+            window: Duration::from_secs(health.window_secs),
+            cooldown: Duration::from_secs(health.cooldown_secs),
+        }
+    }
+
+    /// 展开 `group://<名称>` 开头的地址；不是上游组形式时返回 `Ok(None)`
+    ///
+    /// 错误信息只包含客户端传入的组名，不列出已配置的组与后端
+    pub fn expand(&self, url: &str) -> Result<Option<String>, String> {
+        self.expand_at(url, Instant::now())
+    }
+
+    fn expand_at(&self, url: &str, now: Instant) -> Result<Option<String>, String> {
+        let Some(rest) = url.trim().strip_prefix(GROUP_PREFIX) else {
+            return Ok(None);
+        };
+        let split = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+        let (name, path) = rest.split_at(split);
+
+        let group = self
+            .groups
+            .get(name)
+            .ok_or_else(|| format!("未知的上游组 {:?}", name))?;
+        Ok(Some(format!("{}{}", self.pick(group, now), path)))
+    }
+
+    /// 平滑加权轮询（与 nginx 相同）：只在可用的后端中选择，全部被摘除时在所有后端中选择
+    fn pick<'a>(&self, group: &'a Group, now: Instant) -> &'a str {
+        let available: Vec<bool> = {
+            let health = self.health.lock().unwrap();
+            group
+                .backends
+                .iter()
+                .map(|(base, _)| health.get(base).is_none_or(|h| h.is_available(now)))
+                .collect()
+        };
+        let any_available = available.contains(&true);
+
+        let mut current = group.current.lock().unwrap();
+        let mut total = 0;
+        let mut best: Option<usize> = None;
+        for (i, (_, weight)) in group.backends.iter().enumerate() {
+            if any_available && !available[i] {
+                continue;
+            }
+            current[i] += weight;
+            total += weight;
+            if best.is_none_or(|best| current[i] > current[best]) {
+                best = Some(i);
+            }
+        }
+        let best = best.expect("上游组至少有一个后端");
+        current[best] -= total;
+        &group.backends[best].0
+    }
+
+    /// 记录发往 `url` 的请求结果：`status` 为上游状态码，请求失败（连接失败、超时等）时为 None；
+    /// 地址不在任何上游组的后端之下时忽略；后端被摘除或恢复时返回该变化
+    pub fn report(&self, url: &str, status: Option<u16>) -> Option<HealthChange> {
+        self.report_at(url, status, Instant::now())
+    }
+
+    fn report_at(&self, url: &str, status: Option<u16>, now: Instant) -> Option<HealthChange> {
+        let base = self.backend_of(url)?;
+        let failed = status.is_none_or(|status| status >= 500);
+        let mut health = self.health.lock().unwrap();
+        if !failed {
+            // 成功的请求清零失败计数，并让试探中的后端恢复
+            let state = health.remove(base)?;
+            state.ejected_until?;
+            info!("上游后端 {} 已恢复", base);
+            return Some(HealthChange::Recovered {
+                backend: base.to_string(),
+            });
+        }
+
+        let state = health.entry(base.to_string()).or_default();
+        if let Some(until) = state.ejected_until {
+            // 摘除期间仍在进行的请求不重复计数；试探请求失败时直接再次摘除
+            if until > now {
+                return None;
+            }
+            state.ejected_until = Some(now + self.cooldown);
+            warn!(
+                "上游后端 {} 仍不可用，继续摘除 {} 秒",
+                base,
+                self.cooldown.as_secs()
+            );
+            return None;
+        }
+        if state
+            .window_start
+            .is_none_or(|start| now.duration_since(start) > self.window)
+        {
+            state.window_start = Some(now);
+            state.failures = 0;
+        }
+        state.failures += 1;
+        if state.failures >= self.failure_threshold {
+            state.ejected_until = Some(now + self.cooldown);
+            state.failures = 0;
+            state.window_start = None;
+            warn!(
+                "上游后端 {} 在 {} 秒内失败 {} 次，摘除 {} 秒",
+                base,
+                self.window.as_secs(),
+                self.failure_threshold,
+                self.cooldown.as_secs()
+            );
+            return Some(HealthChange::Ejected {
+                backend: base.to_string(),
+                failures: self.failure_threshold,
+            });
+        }
+        None
+    }
+
+    /// `url` 所属的后端基础地址，有多个时取最长的
+    fn backend_of(&self, url: &str) -> Option<&str> {
+        self.groups
+            .values()
+            .flat_map(|group| &group.backends)
+            .map(|(base, _)| base.as_str())
+            .filter(|base| {
+                url.strip_prefix(base)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with(['/', '?', '#']))
+            })
+            .max_by_key(|base| base.len())
+    }
+
+    /// 各组后端的当前状态，供 `GET /status` 使用
+    pub fn status(&self) -> HashMap<String, Vec<BackendStatus>> {
+        self.status_at(Instant::now())
+    }
+
+    fn status_at(&self, now: Instant) -> HashMap<String, Vec<BackendStatus>> {
+        let health = self.health.lock().unwrap();
+        self.groups
+            .iter()
+            .map(|(name, group)| {
+                let backends = group
+                    .backends
+                    .iter()
+                    .map(|(base, weight)| {
+                        let state = health.get(base);
+                        let ejected = state
+                            .and_then(|state| state.ejected_until)
+                            .filter(|until| *until > now);
+                        BackendStatus {
+                            url_prefix: base.clone(),
+                            weight: *weight,
+                            healthy: ejected.is_none(),
+                            failures: state.map_or(0, |state| state.failures),
+                            ejected_secs: ejected
+                                .map(|until| until.duration_since(now).as_secs_f64().ceil() as u64),
+                        }
+                    })
+                    .collect();
+                (name.clone(), backends)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: &str = "http://a.internal:8080";
+    const B: &str = "http://b.internal:8080";
+    const C: &str = "http://c.internal:8080";
+
+    fn groups() -> UpstreamGroups {
+        let backend = |url_prefix: &str, weight: u32| UpstreamBackend {
+            url_prefix: url_prefix.to_string(),
+            weight,
+        };
+        UpstreamGroups::new(
+            &HashMap::from([
+                (
+                    "api".to_string(),
+                    vec![
+                        backend("http://a.internal:8080/", 5),
+                        backend(B, 1),
+                        backend(C, 1),
+                    ],
+                ),
+                (
+                    "single".to_string(),
+                    vec![backend("https://example.com/v1", 1)],
+                ),
+            ]),
+            &UpstreamHealthConfig {
+                failure_threshold: 3,
+                window_secs: 10,
+                cooldown_secs: 30,
+            },
+        )
+    }
+
+    fn pick(groups: &UpstreamGroups, now: Instant) -> String {
+        let url = groups.expand_at("group://api/x", now).unwrap().unwrap();
+        url.strip_suffix("/x").unwrap().to_string()
+    }
+
+    fn fail(groups: &UpstreamGroups, base: &str, times: usize, now: Instant) {
+        for _ in 0..times {
+            groups.report_at(&format!("{}/x", base), None, now);
+        }
+    }
+
+    #[test]
+    fn test_expand() {
+        let groups = groups();
+        assert_eq!(groups.expand("https://example.com/"), Ok(None));
+        assert_eq!(
+            groups
+                .expand("group://single/users?page=2")
+                .unwrap()
+                .unwrap(),
+            "https://example.com/v1/users?page=2"
+        );
+        assert_eq!(
+            groups.expand("group://single").unwrap().unwrap(),
+            "https://example.com/v1"
+        );
+        assert!(groups.expand("group://missing/x").is_err());
+        assert!(is_group(" group://api/x"));
+        assert!(!is_group("alias:api/x"));
+    }
+
+    #[test]
+    fn test_weighted_round_robin() {
+        let groups = groups();
+        let now = Instant::now();
+        // 平滑加权轮询：权重高的后端不会连续占满一轮
+        let sequence: Vec<String> = (0..7).map(|_| pick(&groups, now)).collect();
+        assert_eq!(sequence, [A, A, B, A, C, A, A]);
+
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for _ in 0..700 {
+            *counts.entry(pick(&groups, now)).or_default() += 1;
+        }
+        assert_eq!(counts[A], 500);
+        assert_eq!(counts[B], 100);
+        assert_eq!(counts[C], 100);
+    }
+
+    #[test]
+    fn test_ejection_threshold() {
+        let groups = groups();
+        let now = Instant::now();
+        // 未达到阈值时仍参与选择；不在基础地址之下的地址不计数
+        fail(&groups, A, 2, now);
+        fail(&groups, "http://a.internal:80800", 5, now);
+        assert!((0..7).any(|_| pick(&groups, now) == A));
+        assert_eq!(groups.status_at(now)["api"][0].failures, 2);
+
+        // 超出统计窗口的失败重新计数
+        let later = now + Duration::from_secs(11);
+        fail(&groups, A, 2, later);
+        assert!((0..7).any(|_| pick(&groups, later) == A));
+
+        // 成功的请求清零失败计数
+        groups.report_at("http://a.internal:8080/", Some(404), later);
+        fail(&groups, A, 2, later);
+        assert!((0..7).any(|_| pick(&groups, later) == A));
+
+        fail(&groups, A, 1, later);
+        for _ in 0..10 {
+            assert_ne!(pick(&groups, later), A);
+        }
+        let status = &groups.status_at(later)["api"];
+        assert_eq!(
+            status[0],
+            BackendStatus {
+                url_prefix: A.to_string(),
+                weight: 5,
+                healthy: false,
+                failures: 0,
+                ejected_secs: Some(30),
+            }
+        );
+        assert!(status[1].healthy);
+    }
+
+    #[test]
+    fn test_ejection_recovery() {
+        let groups = groups();
+        let now = Instant::now();
+        assert_eq!(groups.report_at(&format!("{}/x", A), Some(503), now), None);
+        assert_eq!(groups.report_at(&format!("{}/x", A), Some(500), now), None);
+        assert_eq!(
+            groups.report_at(&format!("{}/x", A), Some(502), now),
+            Some(HealthChange::Ejected {
+                backend: A.to_string(),
+                failures: 3,
+            })
+        );
+        assert!((0..10).all(|_| pick(&groups, now) != A));
+
+        // 冷却结束后重新参与选择，试探请求失败时立即再次摘除
+        let later = now + Duration::from_secs(30);
+        assert!((0..7).any(|_| pick(&groups, later) == A));
+        assert_eq!(groups.report_at(&format!("{}/x", A), None, later), None);
+        assert!((0..10).all(|_| pick(&groups, later) != A));
+
+        // 试探请求成功后恢复
+        let recovered = later + Duration::from_secs(30);
+        assert_eq!(
+            groups.report_at(&format!("{}/x", A), Some(200), recovered),
+            Some(HealthChange::Recovered {
+                backend: A.to_string(),
+            })
+        );
+        assert_eq!(
+            groups.report_at(&format!("{}/x", A), Some(200), recovered),
+            None
+        );
+        assert!(groups.status_at(recovered)["api"][0].healthy);
+        fail(&groups, A, 1, recovered);
+        assert!((0..7).any(|_| pick(&groups, recovered) == A));
+    }
+
+    #[test]
+    fn test_all_ejected() {
+        let groups = groups();
+        let now = Instant::now();
+        for base in [A, B, C] {
+            fail(&groups, base, 3, now);
+        }
+        // 全部被摘除时仍在所有后端中选择
+        assert!((0..7).any(|_| pick(&groups, now) == A));
+        assert!((0..7).any(|_| pick(&groups, now) == B));
+    }
+}
@@ -0,0 +1,92 @@
+use crate::config::RewriteRule;
+use regex::Regex;
+
+/// 编译后的目标地址改写规则
+#[derive(Debug, Clone, Default)]
+pub struct UrlRewriteRules {
+    rules: Vec<(Regex, String)>,
+}
+
+impl UrlRewriteRules {
+    /// 编译配置的规则，正则无效时返回出错的规则位置
+    pub fn parse(rules: &[RewriteRule]) -> Result<Self, String> {
+        let rules = rules
+            .iter()
+            .enumerate()
+            .map(|(index, rule)| {
+                Regex::new(&rule.from_regex)
+                    .map(|re| (re, rule.to.clone()))
+                    .map_err(|e| {
+                        format!("rewrite_rules[{}].from_regex: invalid regex: {}", index, e)
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { rules })
+    }
+
+    /// 按顺序找到第一条匹配的规则并替换（只替换第一处匹配），没有规则匹配时返回 None
+    pub fn apply(&self, url: &str) -> Option<String> {
+        self.rules
+            .iter()
+            .find(|(re, _)| re.is_match(url))
+            .map(|(re, to)| re.replace(url, to.as_str()).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(rules: &[(&str, &str)]) -> UrlRewriteRules {
+        let rules: Vec<RewriteRule> = rules
+            .iter()
+            .map(|(from_regex, to)| RewriteRule {
+                from_regex: from_regex.to_string(),
+                to: to.to_string(),
+            })
+            .collect();
+        UrlRewriteRules::parse(&rules).unwrap()
+    }
+
+    #[test]
+    fn test_host_rewrite() {
+        let rules = rules(&[
+            (r"^https://old\.example\.com/", "https://new.example.com/"),
+            (
+                r"^https://old\.example\.com/",
+                "https://unused.example.com/",
+            ),
+        ]);
+        assert_eq!(
+            rules.apply("https://old.example.com/a/b?x=1").as_deref(),
+            Some("https://new.example.com/a/b?x=1")
+        );
+        assert_eq!(rules.apply("https://other.example.com/a"), None);
+        assert_eq!(rules.apply("https://xold.example.com/a"), None);
+    }
+
+    #[test]
+    fn test_capture_group_substitution() {
+        let rules = rules(&[(
+            r"^https://api\.example\.com/v1/users/(?P<id>\d+)(.*)$",
+            "https://users.internal/profile/${id}$2",
+        )]);
+        assert_eq!(
+            rules
+                .apply("https://api.example.com/v1/users/42?full=1")
+                .as_deref(),
+            Some("https://users.internal/profile/42?full=1")
+        );
+        assert_eq!(rules.apply("https://api.example.com/v1/users/abc"), None);
+    }
+
+    #[test]
+    fn test_invalid_regex() {
+        let err = UrlRewriteRules::parse(&[RewriteRule {
+            from_regex: "(".to_string(),
+            to: String::new(),
+        }])
+        .unwrap_err();
+        assert!(err.starts_with("rewrite_rules[0].from_regex"), "{}", err);
+    }
+}
@@ -0,0 +1,1072 @@
+//! 端到端测试：在本地启动模拟上游，通过 `build_router` 构建的完整路由发起代理请求
+
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{HeaderMap, Method, Request, StatusCode};
+use axum::response::{AppendHeaders, IntoResponse, Redirect};
+use axum::routing::{any, get, post};
+use axum::{Json, Router};
+use remote_http_agent::build_router;
+use remote_http_agent::config::{Config, UpstreamBackend};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use tower::ServiceExt;
+
+const TOKEN: &str = "integration-token";
+
+/// 模拟上游与代理路由
+struct Harness {
+    app: Router,
+    upstream: SocketAddr,
+}
+
+impl Harness {
+    async fn new() -> Self {
+        Self::with_config(Config::default()).await
+    }
+
+    async fn with_config(config: Config) -> Self {
+        let upstream = spawn_upstream().await;
+        let app = build_router(Config {
+            token: TOKEN.to_string(),
+            ..config
+        })
+        .unwrap();
+        Harness { app, upstream }
+    }
+
+    /// 上游地址 `path` 对应的代理请求
+    fn request(&self, path: &str) -> axum::http::request::Builder {
+        self.request_with_token(path, TOKEN)
+    }
+
+    fn request_with_token(&self, path: &str, token: &str) -> axum::http::request::Builder {
+        let target = format!("http://{}{}", self.upstream, path);
+        Request::builder()
+            .uri(format!("/proxy?url={}", urlencoding::encode(&target)))
+            .header("authorization", format!("Bearer {}", token))
+    }
+
+    async fn send(&self, request: Request<Body>) -> (StatusCode, HeaderMap, String) {
+        let response = self.app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, headers, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    async fn get(&self, path: &str) -> (StatusCode, HeaderMap, String) {
+        self.send(self.request(path).body(Body::empty()).unwrap())
+            .await
+    }
+}
+
+async fn spawn_upstream() -> SocketAddr {
+    let app = Router::new()
+        .route(
+            "/hello",
+            get(|| async { ([("x-upstream", "mock")], "hello") }),
+        )
+        .route(
+            "/redirect",
+            get(|| async { Redirect::to("/hello?from=redirect") }),
+        )
+        .route(
+            "/elsewhere",
+            get(|| async { Redirect::to("https://other.example/landing") }),
+        )
+        .route(
+            "/cross-host",
+            get(|headers: HeaderMap| async move {
+                // 在 localhost 与 127.0.0.1 之间切换主机名
+                let host = headers["host"].to_str().unwrap();
+                let (name, port) = host.rsplit_once(':').unwrap();
+                let other = if name == "localhost" {
+                    "127.0.0.1"
+                } else {
+                    "localhost"
+                };
+                Redirect::to(&format!("http://{}:{}/hello", other, port))
+            }),
+        )
+        .route(
+            "/echo/*rest",
+            get(|uri: axum::http::Uri| async move { uri.to_string() }),
+        )
+        .route(
+            "/headers",
+            get(|headers: HeaderMap| async move {
+                let headers: serde_json::Map<String, serde_json::Value> = headers
+                    .iter()
+                    .map(|(name, value)| {
+                        (name.to_string(), value.to_str().unwrap_or_default().into())
+                    })
+                    .collect();
+                Json(headers)
+            }),
+        )
+        .route(
+            "/auth",
+            get(|| async {
+                (
+                    StatusCode::UNAUTHORIZED,
+                    [("www-authenticate", "Basic realm=\"mock\"")],
+                    "unauthorized",
+                )
+            }),
+        )
+        .route(
+            "/no-head",
+            any(|method: Method| async move {
+                if method == Method::HEAD {
+                    StatusCode::METHOD_NOT_ALLOWED.into_response()
+                } else {
+                    ([("x-upstream", "get")], "full body").into_response()
+                }
+            }),
+        )
+        .route(
+            "/items",
+            get(|headers: HeaderMap| async move {
+                let host = headers["host"].to_str().unwrap().to_string();
+                Json(serde_json::json!({
+                    "data": [{ "id": 1 }, { "id": 2 }],
+                    "next": format!("http://{}/items?page=2", host),
+                    "links": [{ "href": format!("http://{}/items/1", host) }],
+                }))
+            }),
+        )
+        .route(
+            "/grpc",
+            post(|headers: HeaderMap, body: axum::body::Bytes| async move {
+                let received = headers
+                    .get("x-grpc-web")
+                    .map(|v| v.to_str().unwrap().to_string())
+                    .unwrap_or_default();
+                // 没有消息时返回 trailers-only 响应，状态写在响应头中
+                if body.is_empty() {
+                    return (
+                        [
+                            ("content-type", "application/grpc-web+proto".to_string()),
+                            ("grpc-status", "5".to_string()),
+                            ("grpc-message", "not%20found".to_string()),
+                        ],
+                        Vec::new(),
+                    );
+                }
+                // 原样返回请求的消息帧，后接编码在响应体中的 trailer 帧
+                let trailer = b"grpc-status:0\r\ngrpc-message:\r\n";
+                let mut response = body.to_vec();
+                response.push(0x80);
+                response.extend_from_slice(&(trailer.len() as u32).to_be_bytes());
+                response.extend_from_slice(trailer);
+                (
+                    [
+                        ("content-type", "application/grpc-web+proto".to_string()),
+                        ("x-received-grpc-web", received),
+                        ("grpc-accept-encoding", "identity".to_string()),
+                    ],
+                    response,
+                )
+            }),
+        )
+        .route(
+            "/upload",
+            post(|body: axum::body::Bytes| async move { body.len().to_string() }),
+        )
+        .route(
+            "/cookie",
+            get(|| async {
+                (
+                    AppendHeaders([
+                        ("set-cookie", "session=abc; Path=/; HttpOnly"),
+                        ("set-cookie", "theme=dark"),
+                    ]),
+                    "ok",
+                )
+            }),
+        );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    addr
+}
+
+#[tokio::test]
+async fn test_get() {
+    let harness = Harness::new().await;
+    let (status, headers, body) = harness.get("/hello").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "hello");
+    assert_eq!(headers["x-upstream"], "mock");
+
+    let request = harness
+        .request_with_token("/hello", "wrong")
+        .body(Body::empty())
+        .unwrap();
+    let (status, _, _) = harness.send(request).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_redirect_location() {
+    let harness = Harness::new().await;
+    // 重定向以 200 返回，原状态码放在 `tun-status`，避免客户端自动跟随
+    let (status, headers, _) = harness.get("/redirect").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["tun-status"], "303");
+    assert!(headers.get("location").is_none());
+    let location = format!("http://{}/hello?from=redirect", harness.upstream);
+    assert_eq!(headers["tun-Location"], location.as_str());
+    assert_eq!(
+        headers["tun-Location-Proxy"],
+        format!("/proxy?url={}", urlencoding::encode(&location)).as_str()
+    );
+
+    let (_, headers, _) = harness.get("/elsewhere").await;
+    assert_eq!(headers["tun-Location"], "https://other.example/landing");
+    assert_eq!(
+        headers["tun-Location-Proxy"],
+        "/proxy?url=https%3A%2F%2Fother.example%2Flanding"
+    );
+}
+
+#[tokio::test]
+async fn test_path_target_encoding() {
+    let harness = Harness::new().await;
+    // 目标地址自身的百分号编码原样到达上游
+    let target = format!("http://{}/echo/a%2Fb?q=a%26b%2B1%25", harness.upstream);
+    let request = Request::builder()
+        .uri(format!("/proxy/{}", urlencoding::encode(&target)))
+        .header("authorization", format!("Bearer {}", TOKEN))
+        .body(Body::empty())
+        .unwrap();
+    let (status, _, body) = harness.send(request).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body, "/echo/a%2Fb?q=a%26b%2B1%25");
+}
+
+#[tokio::test]
+async fn test_tun_request_headers() {
+    let harness = Harness::new().await;
+    let request = harness
+        .request("/headers")
+        .header("user-agent", "client")
+        .header("tun-user-agent", "tunneled")
+        .header("tun-x-custom", "custom")
+        .header("x-plain", "dropped")
+        .body(Body::empty())
+        .unwrap();
+    let (status, _, body) = harness.send(request).await;
+    assert_eq!(status, StatusCode::OK);
+    let received: serde_json::Value = serde_json::from_str(&body).unwrap();
+    // `tun-` 前缀的头部去掉前缀后转发，并取代客户端的同名头部
+    assert_eq!(received["user-agent"], "tunneled");
+    assert_eq!(received["x-custom"], "custom");
+    // 不在转发白名单中的普通头部与代理自身的认证头部不会发往上游
+    assert!(received.get("x-plain").is_none());
+    assert!(received.get("authorization").is_none());
+    assert!(received.get("tun-x-custom").is_none());
+}
+
+#[tokio::test]
+async fn test_set_cookie() {
+    let harness = Harness::new().await;
+    let (status, headers, _) = harness.get("/cookie").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(headers.get("set-cookie").is_none());
+    let cookies: Vec<_> = headers
+        .get_all("tun-set-cookie")
+        .iter()
+        .map(|v| v.to_str().unwrap())
+        .collect();
+    assert_eq!(cookies, ["session=abc; Path=/; HttpOnly", "theme=dark"]);
+}
+
+#[tokio::test]
+async fn test_set_cookie_rewrite() {
+    let harness = Harness::with_config(Config {
+        cookie_rewrite: "samesite=none".to_string(),
+        ..Config::default()
+    })
+    .await;
+    let (_, headers, _) = harness.get("/cookie").await;
+    let cookies: Vec<_> = headers
+        .get_all("tun-set-cookie")
+        .iter()
+        .map(|v| v.to_str().unwrap())
+        .collect();
+    assert_eq!(cookies.len(), 2);
+    assert!(
+        cookies.iter().all(|c| c.contains("SameSite=None")),
+        "{:?}",
+        cookies
+    );
+}
+
+#[tokio::test]
+async fn test_tun_rename_response_headers() {
+    let harness = Harness::with_config(Config {
+        tun_rename_response_headers: vec!["WWW-Authenticate".to_string()],
+        ..Config::default()
+    })
+    .await;
+    let (status, headers, _) = harness.get("/auth").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    // 浏览器看不到原头部，不会弹出登录框
+    assert!(headers.get("www-authenticate").is_none());
+    assert_eq!(headers["tun-www-authenticate"], "Basic realm=\"mock\"");
+    let expose = headers["access-control-expose-headers"].to_str().unwrap();
+    assert!(expose.contains("tun-WWW-Authenticate"), "{}", expose);
+
+    // 未配置时原样返回
+    let harness = Harness::new().await;
+    let (_, headers, _) = harness.get("/auth").await;
+    assert_eq!(headers["www-authenticate"], "Basic realm=\"mock\"");
+    assert!(headers.get("tun-www-authenticate").is_none());
+}
+
+#[tokio::test]
+async fn test_token_hash() {
+    let harness = Harness::with_config(Config {
+        token_hash: vec![remote_http_agent::auth::hash_token("hashed-token").unwrap()],
+        ..Config::default()
+    })
+    .await;
+    // 迁移期间明文 token 与哈希同时有效
+    for token in ["hashed-token", "hashed-token", TOKEN] {
+        let request = harness
+            .request_with_token("/hello", token)
+            .body(Body::empty())
+            .unwrap();
+        let (status, _, body) = harness.send(request).await;
+        assert_eq!(status, StatusCode::OK, "{}", token);
+        assert_eq!(body, "hello");
+    }
+    let request = harness
+        .request_with_token("/hello", "wrong")
+        .body(Body::empty())
+        .unwrap();
+    let (status, _, _) = harness.send(request).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_jwt_auth() {
+    use jsonwebtoken::{EncodingKey, Header};
+    use remote_http_agent::config::{AuthConfig, JwtConfig};
+
+    let harness = Harness::with_config(Config {
+        auth: AuthConfig {
+            jwt: Some(JwtConfig {
+                secret: Some("jwt-secret".to_string()),
+                audiences: vec!["proxy".to_string()],
+                ..JwtConfig::default()
+            }),
+        },
+        ..Config::default()
+    })
+    .await;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let sign = |claims: serde_json::Value| {
+        jsonwebtoken::encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(b"jwt-secret"),
+        )
+        .unwrap()
+    };
+
+    let token = sign(serde_json::json!({
+        "sub": "alice", "aud": "proxy", "exp": now + 600, "allowed_hosts": ["127.0.0.1"],
+    }));
+    let request = harness
+        .request_with_token("/hello", &token)
+        .body(Body::empty())
+        .unwrap();
+    let (status, _, body) = harness.send(request).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "hello");
+
+    // 静态 token 仍然有效
+    let (status, _, _) = harness.get("/hello").await;
+    assert_eq!(status, StatusCode::OK);
+
+    let token = sign(serde_json::json!({
+        "aud": "proxy", "exp": now + 600, "allowed_hosts": ["api.example.com"],
+    }));
+    let request = harness
+        .request_with_token("/hello", &token)
+        .body(Body::empty())
+        .unwrap();
+    let (status, _, body) = harness.send(request).await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+    assert!(body.contains("allowed_hosts"), "{}", body);
+
+    // 代理跟随重定向时不会跳到 allowed_hosts 之外的主机
+    let token = sign(serde_json::json!({
+        "aud": "proxy", "exp": now + 600, "allowed_hosts": ["127.0.0.1"],
+    }));
+    let request = harness
+        .request_with_token("/hello", &token)
+        .method("POST")
+        .header("content-type", "application/vnd.tun.request+json")
+        .body(Body::from(
+            serde_json::json!({
+                "url": format!("http://{}/cross-host", harness.upstream),
+                "follow_redirects": true,
+            })
+            .to_string(),
+        ))
+        .unwrap();
+    let (_, headers, body) = harness.send(request).await;
+    assert_eq!(headers["tun-status"], "303");
+    assert_eq!(
+        headers["tun-location"],
+        format!("http://localhost:{}/hello", harness.upstream.port()).as_str()
+    );
+    assert_ne!(body, "hello");
+
+    // 设置了 allowed_hosts 的 token 不能用 tun-resolve 指定连接地址
+    let request = harness
+        .request_with_token("/hello", &token)
+        .header("tun-resolve", "127.0.0.1:127.0.0.1")
+        .body(Body::empty())
+        .unwrap();
+    let (status, _, body) = harness.send(request).await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+    assert!(body.contains("tun-resolve"), "{}", body);
+
+    let token = sign(serde_json::json!({"aud": "proxy", "exp": now - 600}));
+    let request = harness
+        .request_with_token("/hello", &token)
+        .body(Body::empty())
+        .unwrap();
+    let (status, _, body) = harness.send(request).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body["code"], "jwt_expired");
+}
+
+#[tokio::test]
+async fn test_max_body_bytes() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    fn chunked(chunks: usize, polled: Arc<AtomicBool>) -> Body {
+        let stream =
+            futures_util::StreamExt::map(futures_util::stream::iter(0..chunks), move |_| {
+                polled.store(true, Ordering::SeqCst);
+                Ok::<_, std::io::Error>(axum::body::Bytes::from_static(b"0123456789"))
+            });
+        Body::from_stream(stream)
+    }
+
+    // 完整读入与流式转发两种方式
+    for buffer_below in [None, Some(0)] {
+        let harness = Harness::with_config(Config {
+            max_body_bytes: Some(25),
+            buffer_request_below_bytes: buffer_below,
+            ..Config::default()
+        })
+        .await;
+
+        // Content-Length 超出上限时不读取请求体
+        let polled = Arc::new(AtomicBool::new(false));
+        let request = harness
+            .request("/upload")
+            .method("POST")
+            .header("content-length", "30")
+            .body(chunked(3, polled.clone()))
+            .unwrap();
+        let (status, _, body) = harness.send(request).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE, "{}", body);
+        assert!(body.contains("max_body_bytes"), "{}", body);
+        assert!(!polled.load(Ordering::SeqCst));
+
+        let request = harness
+            .request("/upload")
+            .method("POST")
+            .body(chunked(2, Arc::default()))
+            .unwrap();
+        let (status, _, body) = harness.send(request).await;
+        assert_eq!(status, StatusCode::OK, "{:?}", buffer_below);
+        assert_eq!(body, "20");
+
+        // 分块上传在接收过程中超出上限
+        let polled = Arc::new(AtomicBool::new(false));
+        let request = harness
+            .request("/upload")
+            .method("POST")
+            .body(chunked(5, polled.clone()))
+            .unwrap();
+        let (status, _, body) = harness.send(request).await;
+        assert_eq!(
+            status,
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "{:?} {}",
+            buffer_below,
+            body
+        );
+        assert!(polled.load(Ordering::SeqCst));
+    }
+}
+
+#[tokio::test]
+async fn test_head_fallback_to_get() {
+    for fallback in [false, true] {
+        let harness = Harness::with_config(Config {
+            head_fallback_to_get: fallback,
+            ..Config::default()
+        })
+        .await;
+        let request = harness
+            .request("/no-head")
+            .method("HEAD")
+            .body(Body::empty())
+            .unwrap();
+        let (status, headers, body) = harness.send(request).await;
+        if !fallback {
+            assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+            continue;
+        }
+        // 返回 GET 的状态与头部，不带响应体
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["x-upstream"], "get");
+        assert_eq!(headers["content-length"], "9");
+        assert_eq!(body, "");
+
+        // 只对 HEAD 生效，其他方法照常转发
+        let request = harness
+            .request("/no-head")
+            .method("DELETE")
+            .body(Body::empty())
+            .unwrap();
+        let (status, _, body) = harness.send(request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "full body");
+    }
+}
+
+#[tokio::test]
+async fn test_signed_url() {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use hmac::{Hmac, Mac};
+    use jsonwebtoken::{EncodingKey, Header};
+    use remote_http_agent::config::{AuthConfig, JwtConfig};
+
+    let harness = Harness::with_config(Config {
+        url_signing_key: Some("signing-key".to_string()),
+        forward_extra_query: true,
+        auth: AuthConfig {
+            jwt: Some(JwtConfig {
+                secret: Some("jwt-secret".to_string()),
+                ..JwtConfig::default()
+            }),
+        },
+        ..Config::default()
+    })
+    .await;
+    let target = format!("http://{}/hello", harness.upstream);
+    let unauthenticated = |uri: &str, method: &str| {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/sign")
+        .header("authorization", format!("Bearer {}", TOKEN))
+        .header("content-type", "application/json")
+        .body(Body::from(
+            serde_json::json!({ "url": target, "ttl_secs": 600 }).to_string(),
+        ))
+        .unwrap();
+    let (status, _, body) = harness.send(request).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let signed: serde_json::Value = serde_json::from_str(&body).unwrap();
+    let signed_url = signed["url"].as_str().unwrap().to_string();
+    assert!(signed_url.starts_with("/proxy?url="), "{}", signed_url);
+
+    // 不带 Authorization 也能访问
+    let (status, _, body) = harness.send(unauthenticated(&signed_url, "GET")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "hello");
+
+    // 默认只允许 GET/HEAD
+    let (status, _, body) = harness.send(unauthenticated(&signed_url, "POST")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(body.contains("signed_url_method_not_allowed"), "{}", body);
+
+    // 改动目标地址
+    let tampered = signed_url.replace("hello", "cookie");
+    let (status, _, body) = harness.send(unauthenticated(&tampered, "GET")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(body.contains("signed_url_invalid"), "{}", body);
+
+    // 附加的查询参数会改变目标地址
+    let (status, _, _) = harness
+        .send(unauthenticated(&format!("{}&extra=1", signed_url), "GET"))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // 按文档中的规范化字符串自行签名，已过期
+    let exp = 1_000_000_000u64;
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(b"signing-key").unwrap();
+    mac.update(format!("\n{}\n{}", exp, target).as_bytes());
+    let sig = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+    let expired = format!(
+        "/proxy?url={}&exp={}&sig={}",
+        urlencoding::encode(&target),
+        exp,
+        sig
+    );
+    let (status, _, body) = harness.send(unauthenticated(&expired, "GET")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body["code"], "signed_url_expired");
+
+    // 签名只用于 /proxy
+    let (status, _, _) = harness
+        .send(unauthenticated(
+            &signed_url.replace("/proxy?", "/lanip?"),
+            "GET",
+        ))
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // 只能签名当前 token 的 allowed_hosts 中的目标
+    let exp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        + 600;
+    let jwt = jsonwebtoken::encode(
+        &Header::default(),
+        &serde_json::json!({ "exp": exp, "allowed_hosts": ["api.example.com"] }),
+        &EncodingKey::from_secret(b"jwt-secret"),
+    )
+    .unwrap();
+    let request = Request::builder()
+        .method("POST")
+        .uri("/sign")
+        .header("authorization", format!("Bearer {}", jwt))
+        .header("content-type", "application/json")
+        .body(Body::from(
+            serde_json::json!({ "url": "http://169.254.169.254/" }).to_string(),
+        ))
+        .unwrap();
+    let (status, _, body) = harness.send(request).await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+    assert!(body.contains("allowed_hosts"), "{}", body);
+}
+
+#[tokio::test]
+async fn test_public_hosts() {
+    let harness = Harness::with_config(Config {
+        public_hosts: vec!["LOCALHOST".to_string()],
+        ..Config::default()
+    })
+    .await;
+    let port = harness.upstream.port();
+    let anonymous = |uri: String| Request::builder().uri(uri).body(Body::empty()).unwrap();
+    let public = format!("http://localhost:{}/hello", port);
+
+    for uri in [
+        format!("/proxy?url={}", urlencoding::encode(&public)),
+        format!("/proxy/{}", urlencoding::encode(&public)),
+    ] {
+        let (status, headers, body) = harness.send(anonymous(uri)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["x-upstream"], "mock");
+        assert_eq!(body, "hello");
+    }
+
+    // 其他主机、无法解析的地址与批量接口仍需认证
+    for uri in [
+        format!(
+            "/proxy?url={}",
+            urlencoding::encode(&format!("http://127.0.0.1:{}/hello", port))
+        ),
+        format!(
+            "/proxy?url={}",
+            urlencoding::encode("http://localhost:99999999/")
+        ),
+        format!(
+            "/proxy?url={}",
+            urlencoding::encode("localhost.evil.example/")
+        ),
+        "/proxy/batch".to_string(),
+        "/lanip".to_string(),
+    ] {
+        let (status, _, _) = harness.send(anonymous(uri.clone())).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", uri);
+    }
+
+    // 最终的目标地址（JSON 信封）不在 public_hosts 中
+    let request = Request::builder()
+        .method("POST")
+        .uri(format!("/proxy?url={}", urlencoding::encode(&public)))
+        .header("content-type", "application/vnd.tun.request+json")
+        .body(Body::from(
+            serde_json::json!({ "url": format!("http://127.0.0.1:{}/hello", port) }).to_string(),
+        ))
+        .unwrap();
+    let (status, _, _) = harness.send(request).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // 代理跟随重定向时不会跳到 public_hosts 之外的主机
+    let request = Request::builder()
+        .method("POST")
+        .uri(format!("/proxy?url={}", urlencoding::encode(&public)))
+        .header("content-type", "application/vnd.tun.request+json")
+        .body(Body::from(
+            serde_json::json!({
+                "url": format!("http://localhost:{}/cross-host", port),
+                "follow_redirects": true,
+            })
+            .to_string(),
+        ))
+        .unwrap();
+    let (_, headers, body) = harness.send(request).await;
+    assert_eq!(headers["tun-status"], "303");
+    assert_eq!(
+        headers["tun-location"],
+        format!("http://127.0.0.1:{}/hello", port).as_str()
+    );
+    assert_ne!(body, "hello");
+
+    // 匿名请求不能用 tun-resolve 指定连接地址
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let target = format!(
+        "http://localhost:{}/",
+        listener.local_addr().unwrap().port()
+    );
+    let request = Request::builder()
+        .uri(format!("/proxy?url={}", urlencoding::encode(&target)))
+        .header("tun-resolve", "localhost:127.0.0.1")
+        .body(Body::empty())
+        .unwrap();
+    let (status, _, body) = harness.send(request).await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+    assert!(body.contains("tun-resolve"), "{}", body);
+    assert_eq!(
+        listener.accept().unwrap_err().kind(),
+        std::io::ErrorKind::WouldBlock
+    );
+}
+
+#[tokio::test]
+async fn test_upstream_groups() {
+    let backend = |url_prefix: String, weight: u32| UpstreamBackend { url_prefix, weight };
+    let second = spawn_upstream().await;
+    // 未被占用的端口，连接会被拒绝
+    let closed = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    };
+    let upstream = spawn_upstream().await;
+    let harness = Harness::with_config(Config {
+        upstream_groups: HashMap::from([
+            (
+                "api".to_string(),
+                vec![
+                    backend(format!("http://{}", upstream), 2),
+                    backend(format!("http://{}/", second), 1),
+                ],
+            ),
+            (
+                "flaky".to_string(),
+                vec![
+                    backend(format!("http://{}", closed), 1),
+                    backend(format!("http://{}", upstream), 1),
+                ],
+            ),
+        ]),
+        ..Config::default()
+    })
+    .await;
+    let get = |target: &str| {
+        harness.send(
+            Request::builder()
+                .uri(format!("/proxy?url={}", urlencoding::encode(target)))
+                .header("authorization", format!("Bearer {}", TOKEN))
+                .body(Body::empty())
+                .unwrap(),
+        )
+    };
+
+    // 按权重 2:1 分配，`/headers` 回显的 Host 区分后端
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for _ in 0..30 {
+        let (status, _, body) = get("group://api/headers").await;
+        assert_eq!(status, StatusCode::OK);
+        let headers: serde_json::Value = serde_json::from_str(&body).unwrap();
+        *counts
+            .entry(headers["host"].as_str().unwrap().to_string())
+            .or_default() += 1;
+    }
+    assert_eq!(counts[&upstream.to_string()], 20);
+    assert_eq!(counts[&second.to_string()], 10);
+
+    // 不可用的后端连续失败后被摘除
+    let mut failures = 0;
+    for _ in 0..10 {
+        let (status, _, body) = get("group://flaky/hello").await;
+        if status == StatusCode::BAD_GATEWAY {
+            failures += 1;
+        } else {
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body, "hello");
+        }
+    }
+    // 默认连续失败 3 次后摘除
+    assert_eq!(failures, 3);
+
+    let (status, _, body) = harness
+        .send(
+            Request::builder()
+                .uri("/status")
+                .header("authorization", format!("Bearer {}", TOKEN))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+    let flaky = &body["upstream_groups"]["flaky"];
+    assert_eq!(flaky[0]["url_prefix"], format!("http://{}", closed));
+    assert_eq!(flaky[0]["healthy"], false);
+    assert!(flaky[0]["ejected_secs"].as_u64().unwrap() > 0);
+    assert_eq!(flaky[1]["healthy"], true);
+
+    let (status, _, _) = get("group://missing/hello").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_client_cidrs() {
+    let harness = Harness::with_config(Config {
+        client_allow_cidrs: vec!["203.0.113.7".to_string(), "2001:db8::/32".to_string()],
+        client_deny_cidrs: vec!["2001:db8::bad".to_string()],
+        trust_proxy_header: Some("X-Real-IP".to_string()),
+        trusted_proxies: vec!["127.0.0.1".to_string()],
+        ..Config::default()
+    })
+    .await;
+    let send = |peer: &str, real_ip: Option<&str>| {
+        let mut request = harness.request("/hello");
+        if let Some(real_ip) = real_ip {
+            request = request.header("x-real-ip", real_ip);
+        }
+        let mut request = request.body(Body::empty()).unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+        harness.send(request)
+    };
+
+    // 双栈监听时 IPv4 客户端的对端地址为 IPv4 映射的 IPv6 地址
+    for peer in [
+        "203.0.113.7:5000",
+        "[::ffff:203.0.113.7]:5000",
+        "[2001:db8::1]:5000",
+    ] {
+        let (status, _, body) = send(peer, None).await;
+        assert_eq!(status, StatusCode::OK, "{}", peer);
+        assert_eq!(body, "hello");
+    }
+
+    let (status, _, body) = send("[::ffff:198.51.100.1]:5000", None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(body.contains("client_ip_denied"), "{}", body);
+    let (status, _, _) = send("[2001:db8::bad]:5000", None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // 只有可信代理转发的 X-Real-IP 生效
+    let (status, _, _) = send("[::ffff:127.0.0.1]:5000", Some("203.0.113.7")).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _, _) = send("127.0.0.1:5000", Some("198.51.100.1")).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _, _) = send("198.51.100.1:5000", Some("203.0.113.7")).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // 拒绝发生在认证之前，没有对端地址的请求同样拒绝
+    let (status, _, _) = harness.get("/hello").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_rewrite_json_urls() {
+    let harness = Harness::with_config(Config {
+        rewrite_json_urls: vec!["/next".to_string(), "/links/*/href".to_string()],
+        ..Config::default()
+    })
+    .await;
+    let upstream = harness.upstream;
+    let (status, headers, body) = harness.get("/items").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["content-length"], body.len().to_string());
+
+    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+    let proxied = |path: &str| {
+        format!(
+            "/proxy?url={}",
+            urlencoding::encode(&format!("http://{}{}", upstream, path))
+        )
+    };
+    assert_eq!(body["next"], proxied("/items?page=2"));
+    assert_eq!(body["links"][0]["href"], proxied("/items/1"));
+    assert_eq!(body["data"][1]["id"], 2);
+
+    // 跟随改写后的 next 地址翻页
+    let next = body["next"].as_str().unwrap().to_string();
+    let request = Request::builder()
+        .uri(next)
+        .header("authorization", format!("Bearer {}", TOKEN))
+        .body(Body::empty())
+        .unwrap();
+    let (status, _, _) = harness.send(request).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_grpc_web_passthrough() {
+    let harness = Harness::with_config(Config {
+        compress_responses: true,
+        ..Config::default()
+    })
+    .await;
+    let call = |message: Vec<u8>| {
+        harness
+            .request("/grpc")
+            .method(Method::POST)
+            .header("content-type", "application/grpc-web+proto")
+            .header("x-grpc-web", "1")
+            .header("accept-encoding", "gzip")
+            .body(Body::from(message))
+            .unwrap()
+    };
+
+    // 长度前缀的消息帧：标志字节、4 字节长度、消息
+    let message = [&[0u8, 0, 0, 0, 3][..], b"abc"].concat();
+    let response = harness
+        .app
+        .clone()
+        .oneshot(call(message.clone()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let headers = response.headers().clone();
+    assert_eq!(headers["content-type"], "application/grpc-web+proto");
+    assert_eq!(headers["x-received-grpc-web"], "1");
+    assert_eq!(headers["grpc-accept-encoding"], "identity");
+    assert!(headers.get("content-encoding").is_none());
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let (data, trailer) = body.split_at(message.len());
+    assert_eq!(data, &message[..]);
+    assert_eq!(trailer[0], 0x80);
+    let length = u32::from_be_bytes(trailer[1..5].try_into().unwrap()) as usize;
+    let trailer = std::str::from_utf8(&trailer[5..]).unwrap();
+    assert_eq!(trailer.len(), length);
+    assert!(trailer.starts_with("grpc-status:0\r\n"), "{}", trailer);
+
+    // trailers-only 响应的 grpc-status 在响应头中
+    let response = harness.app.clone().oneshot(call(Vec::new())).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["grpc-status"], "5");
+    assert_eq!(response.headers()["grpc-message"], "not%20found");
+}
+
+#[tokio::test]
+async fn test_access_log_file() {
+    use remote_http_agent::config::LogConfig;
+
+    let dir = std::env::temp_dir().join(format!("rha-access-log-{}", std::process::id()));
+    let path = dir.join("access.log");
+    let harness = Harness::with_config(Config {
+        log: LogConfig {
+            access_log_file: Some(path.to_string_lossy().into_owned()),
+            ..LogConfig::default()
+        },
+        ..Config::default()
+    })
+    .await;
+
+    let mut request = harness
+        .request("/hello")
+        .header("referer", "https://example.com/page")
+        .header("user-agent", "test-agent/1.0 \"beta\"")
+        .body(Body::empty())
+        .unwrap();
+    request.extensions_mut().insert(ConnectInfo(
+        "203.0.113.7:5000".parse::<SocketAddr>().unwrap(),
+    ));
+    let (status, _, body) = harness.send(request).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "hello");
+    // 认证失败的请求同样记录
+    let (status, _, _) = harness
+        .send(
+            harness
+                .request_with_token("/hello", "wrong")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let request_uri = harness
+        .request("/hello")
+        .body(())
+        .unwrap()
+        .uri()
+        .to_string();
+
+    // 丢弃路由后写完缓冲
+    drop(harness);
+    let log = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", log);
+
+    // 203.0.113.7 - - [10/Oct/2000:13:55:36 +0000] "GET /proxy?url=... HTTP/1.1" 200 5 "referer" "ua"
+    let (prefix, rest) = lines[0].split_once(" [").unwrap();
+    assert_eq!(prefix, "203.0.113.7 - -");
+    let (time, rest) = rest.split_once("] ").unwrap();
+    let (date, zone) = time.split_once(' ').unwrap();
+    assert_eq!(zone, "+0000");
+    let parts: Vec<&str> = date.split(['/', ':']).collect();
+    assert_eq!(parts.len(), 6, "{}", date);
+    assert!(
+        parts[0].len() == 2 && parts[0].parse::<u8>().is_ok(),
+        "{}",
+        date
+    );
+    assert!(
+        ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"]
+            .contains(&parts[1])
+    );
+    assert!(parts[2].len() == 4 && parts[3..].iter().all(|p| p.len() == 2));
+    assert_eq!(
+        rest,
+        format!(
+            "\"GET {} HTTP/1.1\" 200 5 \"https://example.com/page\" \"test-agent/1.0 \\\"beta\\\"\"",
+            request_uri
+        )
+    );
+
+    assert!(lines[1].starts_with("- - - ["), "{}", lines[1]);
+    assert!(lines[1].contains("\" 401 "), "{}", lines[1]);
+    assert!(lines[1].ends_with(" \"-\" \"-\""), "{}", lines[1]);
+}